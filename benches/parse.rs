@@ -0,0 +1,80 @@
+//! Benchmark of `parse::parse` on a long synthetic line, without a benchmark framework.
+//!
+//! Run it with `cargo bench --bench parse`. It prints the allocations and the time of one
+//! parse, counted by a global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use mysh::parse;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// A command with 500 arguments, like a pasted list of files, with some quotes, expansions
+/// and redirections.
+fn long_line() -> String {
+    let mut line = String::from("echo");
+    for i in 0..500 {
+        match i % 10 {
+            3 => line.push_str(&format!(" 'quoted arg {}'", i)),
+            5 => line.push_str(&format!(" \"$HOME/dir {}\"", i)),
+            7 => line.push_str(&format!(" ${{VAR{}}}", i)),
+            _ => line.push_str(&format!(" some/path/file-{}.txt", i)),
+        }
+    }
+    line.push_str(" > out.txt 2>&1 < in.txt");
+    line
+}
+
+fn main() {
+    let line = long_line();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(parse::parse(black_box(&line)).unwrap());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    // Warm up, then run for about a second
+    for _ in 0..100 {
+        black_box(parse::parse(black_box(&line)).unwrap());
+    }
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < Duration::from_secs(1) {
+        black_box(parse::parse(black_box(&line)).unwrap());
+        iterations += 1;
+    }
+    let per_parse = start.elapsed() / iterations;
+
+    println!(
+        "parse of a {} byte line: {} allocations, {:?} per parse ({} iterations)",
+        line.len(),
+        allocations,
+        per_parse,
+        iterations
+    );
+}
@@ -0,0 +1,286 @@
+//! Arithmetic expansion.
+//!
+//! `$((expr))` evaluates an integer expression after the parameters and commands in it are
+//! expanded, like bash. Numbers are 64-bit integers which wrap around on overflow, and
+//! variables are written without `$`, so `$((2 + 3 * x))` uses the value of `x`.
+//!
+//! Operators from the lowest precedence: `||`, `&&`, `==` `!=`, `<` `<=` `>` `>=`, `+` `-`,
+//! `*` `/` `%`, then the unary `-` `+` `!`. Comparisons give 1 if they are true, otherwise 0.
+
+/// How deep variables may refer to other variables, like `a=b b=a`.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+}
+
+/// Operators, longer ones first so that `<=` is not read as `<`.
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")",
+];
+
+/// Parse an integer constant: decimal, hexadecimal with `0x`, or octal with a leading `0`.
+fn parse_number(s: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        (hex, 16)
+    } else if s.len() > 1 && s.starts_with('0') {
+        (&s[1..], 8)
+    } else {
+        (s, 10)
+    };
+    // Too large constants wrap around like bash
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = c.to_digit(radix)?;
+        value = value.wrapping_mul(radix.into()).wrapping_add(digit.into());
+    }
+    (!digits.is_empty()).then_some(value)
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            if c.is_ascii_digit() {
+                let number = parse_number(word).ok_or_else(|| {
+                    format!(
+                        "{}: value too great for base (error token is \"{}\")",
+                        expr.trim(),
+                        word
+                    )
+                })?;
+                tokens.push(Token::Number(number));
+            } else {
+                tokens.push(Token::Name(word.to_owned()));
+            }
+            len
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| {
+                    format!(
+                        "{}: syntax error: invalid arithmetic operator (error token is \"{}\")",
+                        expr.trim(),
+                        rest
+                    )
+                })?;
+            tokens.push(Token::Op(op));
+            op.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Variable(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+}
+
+/// A recursive descent parser, with a function for each level of precedence.
+struct Parser<'a> {
+    expr: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self, ops: &[&str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    /// The message of a syntax error at the current token.
+    fn error(&self, message: &str) -> String {
+        let rest = match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => (*op).to_owned(),
+            Some(Token::Number(n)) => n.to_string(),
+            Some(Token::Name(name)) => name.clone(),
+            None => String::new(),
+        };
+        format!(
+            "{}: syntax error: {} (error token is \"{}\")",
+            self.expr.trim(),
+            message,
+            rest
+        )
+    }
+
+    /// Parse operators of the same precedence from left to right, with operands parsed by
+    /// `operand`.
+    fn binary(
+        &mut self,
+        ops: &[&str],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut left = operand(self)?;
+        while let Some(op) = self.peek_op(ops) {
+            self.pos += 1;
+            let right = operand(self)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&["&&"], Self::equality)
+    }
+
+    fn equality(&mut self) -> Result<Expr, String> {
+        self.binary(&["==", "!="], Self::relational)
+    }
+
+    fn relational(&mut self) -> Result<Expr, String> {
+        self.binary(&["<", "<=", ">", ">="], Self::additive)
+    }
+
+    fn additive(&mut self) -> Result<Expr, String> {
+        self.binary(&["+", "-"], Self::multiplicative)
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, String> {
+        self.binary(&["*", "/", "%"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if let Some(op) = self.peek_op(&["-", "+", "!"]) {
+            self.pos += 1;
+            return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let expr = match self.tokens.get(self.pos) {
+            Some(Token::Number(n)) => Expr::Number(*n),
+            Some(Token::Name(name)) => Expr::Variable(name.clone()),
+            Some(Token::Op("(")) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                if self.peek_op(&[")"]).is_none() {
+                    return Err(self.error("`)' expected"));
+                }
+                expr
+            }
+            _ => return Err(self.error("operand expected")),
+        };
+        self.pos += 1;
+        Ok(expr)
+    }
+}
+
+fn parse(expr: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        expr,
+        tokens: tokenize(expr)?,
+        pos: 0,
+    };
+    let parsed = parser.or()?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("invalid arithmetic operator"));
+    }
+    Ok(parsed)
+}
+
+struct Evaluator<'a> {
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    fn evaluate(&mut self, expr: &str) -> Result<i64, String> {
+        // An empty expression is 0, like `$(())`
+        if expr.trim().is_empty() {
+            return Ok(0);
+        }
+        let parsed = parse(expr)?;
+        self.evaluate_expr(&parsed, expr)
+    }
+
+    /// The value of a variable is an expression itself, while an unset or empty variable
+    /// is 0.
+    fn variable(&mut self, name: &str) -> Result<i64, String> {
+        let value = (self.lookup)(name).unwrap_or_default();
+        if let Ok(n) = value.trim().parse() {
+            return Ok(n);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err(format!(
+                "{}: expression recursion level exceeded (error token is \"{}\")",
+                value, value
+            ));
+        }
+        self.depth += 1;
+        let n = self.evaluate(&value);
+        self.depth -= 1;
+        n
+    }
+
+    fn evaluate_expr(&mut self, parsed: &Expr, expr: &str) -> Result<i64, String> {
+        let b = |b: bool| i64::from(b);
+        Ok(match parsed {
+            Expr::Number(n) => *n,
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Unary(op, operand) => {
+                let n = self.evaluate_expr(operand, expr)?;
+                match *op {
+                    "-" => n.wrapping_neg(),
+                    "!" => b(n == 0),
+                    _ => n,
+                }
+            }
+            // The right side is not evaluated if the value is known, so `0 && 1 / 0` is 0
+            Expr::Binary(left, "&&", right) => {
+                b(self.evaluate_expr(left, expr)? != 0 && self.evaluate_expr(right, expr)? != 0)
+            }
+            Expr::Binary(left, "||", right) => {
+                b(self.evaluate_expr(left, expr)? != 0 || self.evaluate_expr(right, expr)? != 0)
+            }
+            Expr::Binary(left, op, right) => {
+                let left = self.evaluate_expr(left, expr)?;
+                let right = self.evaluate_expr(right, expr)?;
+                match *op {
+                    "==" => b(left == right),
+                    "!=" => b(left != right),
+                    "<" => b(left < right),
+                    "<=" => b(left <= right),
+                    ">" => b(left > right),
+                    ">=" => b(left >= right),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" | "%" if right == 0 => {
+                        return Err(format!("{}: division by 0", expr.trim()));
+                    }
+                    "/" => left.wrapping_div(right),
+                    "%" => left.wrapping_rem(right),
+                    _ => unreachable!("unknown operator {}", op),
+                }
+            }
+        })
+    }
+}
+
+/// Evaluate an arithmetic expression, where `lookup` returns the value of a variable.
+/// Returns the error message if the expression is invalid or divides by zero.
+pub fn evaluate(expr: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<i64, String> {
+    Evaluator { lookup, depth: 0 }.evaluate(expr)
+}
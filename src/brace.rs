@@ -0,0 +1,210 @@
+//! Brace expansion.
+//!
+//! `a{b,c}d` expands to `abd acd`, and `{1..3}` to `1 2 3`, before any other expansion, like
+//! bash. It is textual: only unquoted braces and commas are special, and a group without a
+//! comma which is not a range, such as `{}` or `{a}`, is kept as it is.
+
+use crate::parse::{Word, WordPart};
+
+/// A character of unquoted text, which can be a brace or a comma, or any other part of a
+/// word, which is copied as it is.
+#[derive(Debug, Clone)]
+enum Token {
+    Char(char),
+    Part(WordPart),
+}
+
+fn tokenize(word: &Word) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Text {
+                value,
+                quoted: false,
+            } => tokens.extend(value.chars().map(Token::Char)),
+            part => tokens.push(Token::Part(part.clone())),
+        }
+    }
+    tokens
+}
+
+fn to_word(tokens: &[Token]) -> Word {
+    let mut word = Word::new();
+    for token in tokens {
+        match token {
+            Token::Char(c) => word.push(*c, false),
+            Token::Part(WordPart::Text {
+                value,
+                quoted: true,
+            }) if !value.is_empty() => value.chars().for_each(|c| word.push(c, true)),
+            Token::Part(part) => word.parts.push(part.clone()),
+        }
+    }
+    word
+}
+
+/// Find the group which starts at `tokens[start]`, a `{`. Returns the position of each
+/// comma at the top level of the group and of the closing `}`, or `None` if it is not
+/// closed.
+fn find_group(tokens: &[Token], start: usize) -> Option<(Vec<usize>, usize)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, token) in tokens.iter().enumerate().skip(start) {
+        match token {
+            Token::Char('{') => depth += 1,
+            Token::Char('}') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some((commas, i));
+                }
+            }
+            Token::Char(',') if depth == 1 => commas.push(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse a bound of a range, either an integer or a single character.
+enum Bound {
+    Integer(i64),
+    Char(char),
+}
+
+fn parse_bound(s: &str) -> Option<Bound> {
+    if let Ok(n) = s.parse() {
+        return Some(Bound::Integer(n));
+    }
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii() && !c.is_ascii_digit() => Some(Bound::Char(c)),
+        _ => None,
+    }
+}
+
+/// The width of an integer bound with leading zeros, like `01` or `-05`, otherwise 0.
+fn padded_width(s: &str) -> usize {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    if digits.len() > 1 && digits.starts_with('0') {
+        s.len()
+    } else {
+        0
+    }
+}
+
+/// Expand a range like `1..5`, `a..e` or `0..10..2`. Returns `None` if it is not a valid
+/// range, such as bounds of different kinds, then the group is kept as it is.
+///
+/// Like bash, the sign of the step is ignored, a range goes down if the end is less than
+/// the start, and integers are padded with zeros if a bound has leading zeros.
+fn expand_range(text: &str) -> Option<Vec<String>> {
+    let mut parts = text.split("..");
+    let (start, end) = (parts.next()?, parts.next()?);
+    let step = match parts.next() {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let values = |start: i64, end: i64| {
+        let count = start.abs_diff(end) / step + 1;
+        let down = end < start;
+        (0..count).map(move |i| {
+            let offset = i as i128 * step as i128;
+            (start as i128 + if down { -offset } else { offset }) as i64
+        })
+    };
+    match (parse_bound(start)?, parse_bound(end)?) {
+        (Bound::Integer(first), Bound::Integer(last)) => {
+            let width = padded_width(start).max(padded_width(end));
+            Some(
+                values(first, last)
+                    .map(|n| format!("{:0width$}", n, width = width))
+                    .collect(),
+            )
+        }
+        (Bound::Char(first), Bound::Char(last)) => Some(
+            values(i64::from(first as u8), i64::from(last as u8))
+                .map(|c| char::from(c as u8).to_string())
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// The alternatives of a group without the braces: the text between the commas, or the
+/// values of a range. Returns `None` if the group is not expanded.
+fn alternatives(group: &[Token], commas: &[usize]) -> Option<Vec<Vec<Token>>> {
+    if !commas.is_empty() {
+        // Each alternative is between two of the braces and commas
+        let mut bounds = vec![0];
+        bounds.extend(commas);
+        bounds.push(group.len() + 1);
+        return Some(
+            bounds
+                .windows(2)
+                .map(|bound| group[bound[0]..bound[1] - 1].to_vec())
+                .collect(),
+        );
+    }
+
+    // A range must be unquoted text
+    let text = group
+        .iter()
+        .map(|token| match token {
+            Token::Char(c) => Some(*c),
+            Token::Part(_) => None,
+        })
+        .collect::<Option<String>>()?;
+    let values = expand_range(&text)?;
+    Some(
+        values
+            .into_iter()
+            .map(|value| value.chars().map(Token::Char).collect())
+            .collect(),
+    )
+}
+
+fn expand_tokens(tokens: &[Token], words: &mut Vec<Word>) {
+    // The first group which can be expanded is expanded, then each result is expanded
+    // again for the groups nested in it or after it.
+    let group = tokens.iter().enumerate().find_map(|(start, token)| {
+        if !matches!(token, Token::Char('{')) {
+            return None;
+        }
+        let (commas, end) = find_group(tokens, start)?;
+        // Positions of the commas in the group
+        let commas: Vec<_> = commas.iter().map(|comma| comma - start).collect();
+        let alternatives = alternatives(&tokens[start + 1..end], &commas)?;
+        Some((start, end, alternatives))
+    });
+    let Some((start, end, alternatives)) = group else {
+        words.push(to_word(tokens));
+        return;
+    };
+
+    let (prefix, suffix) = (&tokens[..start], &tokens[end + 1..]);
+    for alternative in alternatives {
+        let mut expanded = prefix.to_vec();
+        expanded.extend(alternative);
+        expanded.extend_from_slice(suffix);
+        expand_tokens(&expanded, words);
+    }
+}
+
+/// Expand the braces of a word into words. Returns the word itself if there is nothing to
+/// expand.
+pub fn expand(word: &Word) -> Vec<Word> {
+    let has_brace = word
+        .parts
+        .iter()
+        .any(|part| matches!(part, WordPart::Text { value, quoted: false } if value.contains('{')));
+    if !has_brace {
+        return vec![word.clone()];
+    }
+    let mut words = Vec::new();
+    expand_tokens(&tokenize(word), &mut words);
+    words
+}
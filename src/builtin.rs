@@ -1,18 +1,38 @@
 use std::{
     cell::RefMut,
-    collections::HashMap,
-    fs::{DirEntry, ReadDir, read_dir},
-    io::{self, Write},
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{self, BufWriter, PipeWriter, Write},
+    mem::ManuallyDrop,
     ops::Deref,
-    path::{Path, PathBuf},
+    os::{
+        fd::{FromRawFd, RawFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::{Component, Path, PathBuf},
+    process::Child,
+    thread,
+    time::{Duration, Instant},
 };
 
 use is_executable::IsExecutable;
 use rustyline::history::History;
 
-use crate::env::{ExecContext, ExecEnv};
+use crate::{
+    env::{ExecContext, ExecEnv},
+    execution::{self, exit_status_code},
+    history::{append_history, expand_history, save_history},
+    job::JobState,
+    parse::is_valid_name,
+    printf, test,
+};
+
+/// A builtin command returns its exit status.
+type BuiltinExecFunc = fn(Vec<OsString>, RefMut<ExecEnv>, &mut ExecContext) -> i32;
 
-type BuiltinExecFunc = fn(Vec<String>, RefMut<ExecEnv>, &mut ExecContext);
+/// Exit status of a builtin whose output pipe is closed, as if it were killed by `SIGPIPE`.
+pub const BROKEN_PIPE_STATUS: i32 = 128 + libc::SIGPIPE;
 
 // single thread, so we use thread_local
 thread_local! {
@@ -21,128 +41,762 @@ thread_local! {
         let mut map = HashMap::<&'static str, BuiltinExecFunc>::new();
         map.insert("exit",    exit_command);
         map.insert("echo",    echo_command);
+        map.insert("printf",  printf_command);
         map.insert("type",    type_command);
         map.insert("pwd",     pwd_command);
         map.insert("cd",      cd_command);
         map.insert("history", history_command);
+        map.insert("command", command_command);
+        map.insert("builtin", builtin_command);
+        map.insert("set",     set_command);
+        map.insert("timeout", timeout_command);
+        map.insert("source",  source_command);
+        map.insert(".",       source_command);
+        map.insert("export",  export_command);
+        map.insert("unset",   unset_command);
+        map.insert("test",    test_command);
+        map.insert("[",       bracket_command);
+        map.insert("jobs",    jobs_command);
+        map.insert("fg",      fg_command);
+        map.insert("suspend", suspend_command);
+        map.insert("alias",   alias_command);
+        map.insert("unalias", unalias_command);
         map
     };
 }
 
+/// Returns the exit status for a failed write of the output.
+///
+/// When the reader of the output has gone, e.g. `history | head -1`, we just stop writing.
+pub(crate) fn output_error_status(e: io::Error) -> i32 {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        return BROKEN_PIPE_STATUS;
+    }
+    #[allow(clippy::explicit_write)]
+    let _ = writeln!(io::stderr(), "write error: {}", e);
+    1
+}
+
+/// Write to a file descriptor of the shell. Redirections of a builtin are applied to the fds
+/// of the shell while it runs, so this writes to the redirected file if there is one.
+///
+/// The fd is written directly without buffering, so nothing written by a builtin is left in
+/// a buffer when its redirections are restored. Output buffered by `io::stdout()` is flushed
+/// first, so that the order with other output of the shell is kept.
+pub fn write_to_fd(fd: RawFd, buf: &[u8]) -> io::Result<()> {
+    if fd == 1 {
+        io::stdout().flush()?;
+    }
+    // The fd is not owned here, so it must not be closed.
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    file.write_all(buf)
+}
+
+/// The output of a builtin, which is the pipe to the next command or fd 1, see
+/// `builtin_output!`. It is not buffered, wrap it in a `BufWriter` to write many pieces.
+enum BuiltinOutput<'a> {
+    Fd(ManuallyDrop<File>),
+    Pipe(&'a mut PipeWriter),
+}
+
+impl<'a> BuiltinOutput<'a> {
+    fn new(env: &'a mut ExecEnv) -> io::Result<Self> {
+        match &mut env.pipe_out {
+            Some(pipe_out) => Ok(Self::Pipe(pipe_out)),
+            None => {
+                // Like `write_to_fd`, output buffered by `io::stdout()` goes first.
+                io::stdout().flush()?;
+                // The fd is not owned here, so it must not be closed.
+                Ok(Self::Fd(ManuallyDrop::new(unsafe { File::from_raw_fd(1) })))
+            }
+        }
+    }
+}
+
+impl Write for BuiltinOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Fd(file) => file.write(buf),
+            Self::Pipe(pipe) => pipe.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write the output of a builtin. If the write fails, the builtin returns immediately with
+/// the status from `output_error_status`.
 macro_rules! builtin_output {
     ($env:expr, $($arg:tt)*) => {
-        #[allow(clippy::explicit_write)]
-        match &mut $env.pipe_out {
-            // We use write! to avoid capturing stdout in tests.
-            None => write!(io::stdout(), $($arg)*).unwrap(),
-            Some(pipe_out) => write!(pipe_out, $($arg)*).unwrap(),
+        builtin_output_bytes!($env, format!($($arg)*).as_bytes());
+    };
+}
+
+/// Same as `builtin_output!`, but writes raw bytes, which may not be valid UTF-8.
+macro_rules! builtin_output_bytes {
+    ($env:expr, $buf:expr) => {
+        let result = match &mut $env.pipe_out {
+            None => write_to_fd(1, $buf),
+            Some(pipe_out) => pipe_out.write_all($buf),
+        };
+        if let Err(e) = result {
+            return output_error_status(e);
         }
     };
 }
 
+/// Write an error message of a builtin to fd 2. Errors can't be reported if writing to it
+/// fails, so they are ignored.
+///
+/// `env` is not needed to write to fd 2, but it is taken like `builtin_output!`.
 macro_rules! builtin_error {
     ($env:expr, $($arg:tt)*) => {
-        #[allow(clippy::explicit_write)]
-        write!(io::stderr(), $($arg)*).unwrap()
+        let _ = &$env;
+        let _ = write_to_fd(2, format!($($arg)*).as_bytes());
     };
 }
 
 /// echo command implementation
-pub fn echo_command(args: Vec<String>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) {
-    builtin_output!(env, "{}\n", args.join(" "));
+///
+/// The arguments are written through a buffer, instead of being joined first, since there
+/// may be many of them.
+pub fn echo_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    fn write_args(env: &mut ExecEnv, args: &[OsString]) -> io::Result<()> {
+        let mut out = BufWriter::new(BuiltinOutput::new(env)?);
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b" ")?;
+            }
+            out.write_all(arg.as_bytes())?;
+        }
+        out.write_all(b"\n")?;
+        out.flush()
+    }
+
+    match write_args(&mut env, &args) {
+        Ok(()) => 0,
+        Err(e) => output_error_status(e),
+    }
+}
+
+/// printf command implementation, see `printf`
+pub fn printf_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let args = match args.first() {
+        Some(arg) if arg == "--" => &args[1..],
+        _ => &args[..],
+    };
+    let Some((format, args)) = args.split_first() else {
+        builtin_error!(env, "printf: usage: printf format [arguments]\n");
+        return 2;
+    };
+
+    let mut errors = Vec::new();
+    let buf = printf::format(format.as_bytes(), args, env.start_time, &mut errors);
+    builtin_output_bytes!(env, &buf);
+    for error in &errors {
+        builtin_error!(env, "{}\n", error);
+    }
+    i32::from(!errors.is_empty())
+}
+
+/// test command implementation, see `test`
+///
+/// Returns 0 if the expression is true, 1 if it is false, and 2 if it is invalid.
+pub fn test_command(args: Vec<OsString>, env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    match test::evaluate(&args) {
+        Ok(value) => i32::from(!value),
+        Err(msg) => {
+            builtin_error!(env, "test: {}\n", msg);
+            2
+        }
+    }
+}
+
+/// `[` is `test` with a `]` after the expression.
+pub fn bracket_command(mut args: Vec<OsString>, env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    if args.pop().is_none_or(|arg| arg != "]") {
+        builtin_error!(env, "[: missing `]'\n");
+        return 2;
+    }
+    match test::evaluate(&args) {
+        Ok(value) => i32::from(!value),
+        Err(msg) => {
+            builtin_error!(env, "[: {}\n", msg);
+            2
+        }
+    }
 }
 
 /// exit command should be handled earlier, so it does nothing here
-pub fn exit_command(_: Vec<String>, _: RefMut<ExecEnv>, _: &mut ExecContext) {}
+pub fn exit_command(_: Vec<OsString>, _: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    0
+}
 
-fn get_executable_in_path(cmd: &str, env: &ExecEnv) -> Option<DirEntry> {
-    fn dir_get_executable(name: &str, reader: ReadDir) -> Option<DirEntry> {
-        reader
-            .flatten()
-            .find(|entry| entry.path().is_executable() && entry.file_name() == name)
+/// command command should be handled earlier, so it does nothing here
+pub fn command_command(_: Vec<OsString>, _: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    0
+}
+
+/// timeout command should be handled earlier, so it does nothing here, see
+/// `wait_with_timeout`
+pub fn timeout_command(_: Vec<OsString>, _: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    0
+}
+
+/// source command should be handled earlier, so it does nothing here
+pub fn source_command(_: Vec<OsString>, _: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    0
+}
+
+/// Exit status of `timeout` when the command times out, like GNU `timeout`.
+pub const TIMEOUT_STATUS: i32 = 124;
+
+/// Exit status of `timeout` when it fails itself, such as with an invalid duration.
+pub const TIMEOUT_FAILURE_STATUS: i32 = 125;
+
+/// How long a command which timed out has to exit after `SIGTERM`, before `SIGKILL`.
+const TIMEOUT_KILL_AFTER: Duration = Duration::from_secs(1);
+
+const TIMEOUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Parse the duration of `timeout`: a number of seconds, which may be a fraction, with an
+/// optional suffix `s`, `m`, `h` or `d`.
+pub fn parse_duration(arg: &OsStr) -> Option<Duration> {
+    let arg = arg.to_str()?;
+    let (number, unit) = match arg.char_indices().last()? {
+        (i, 's') => (&arg[..i], 1.0),
+        (i, 'm') => (&arg[..i], 60.0),
+        (i, 'h') => (&arg[..i], 60.0 * 60.0),
+        (i, 'd') => (&arg[..i], 24.0 * 60.0 * 60.0),
+        _ => (arg, 1.0),
+    };
+    // `f64` also parses `inf` and `nan`, which are not durations.
+    if !number.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+        return None;
     }
+    let seconds: f64 = number.parse().ok()?;
+    Duration::try_from_secs_f64(seconds * unit).ok()
+}
 
-    for dir in env.path_env.iter() {
-        if let Ok(entries) = read_dir(dir)
-            && let Some(entry) = dir_get_executable(cmd, entries)
-        {
-            return Some(entry);
+/// Wait for `child` of `timeout`. When `duration` passes, it is sent `SIGTERM`, then
+/// `SIGKILL` if it is still running after `TIMEOUT_KILL_AFTER`. Like GNU `timeout`, a
+/// duration of 0 means no limit.
+///
+/// Returns the exit status of the child, or `TIMEOUT_STATUS` if it timed out.
+pub fn wait_with_timeout(child: &mut Child, duration: Duration) -> i32 {
+    let status = |status: io::Result<_>| status.map_or(1, exit_status_code);
+    if duration.is_zero() {
+        return status(child.wait());
+    }
+
+    let deadline = Instant::now() + duration;
+    loop {
+        match child.try_wait() {
+            Ok(Some(exited)) => return exit_status_code(exited),
+            Ok(None) if Instant::now() < deadline => thread::sleep(TIMEOUT_POLL_INTERVAL),
+            Ok(None) => break,
+            Err(e) => return status(Err(e)),
         }
     }
 
-    None
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+    let deadline = Instant::now() + TIMEOUT_KILL_AFTER;
+    while Instant::now() < deadline {
+        match child.try_wait() {
+            Ok(None) => thread::sleep(TIMEOUT_POLL_INTERVAL),
+            _ => return TIMEOUT_STATUS,
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    TIMEOUT_STATUS
+}
+
+/// builtin command should be handled earlier, so it does nothing here
+pub fn builtin_command(_: Vec<OsString>, _: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    0
+}
+
+/// The path of the executable `cmd` in the first directory of `PATH` which has it.
+pub(crate) fn get_executable_in_path(cmd: &OsStr, env: &ExecEnv) -> Option<PathBuf> {
+    env.path_env.iter().find_map(|dir| {
+        let names = env.path_cache.entries(dir);
+        if !names.iter().any(|name| name == cmd) {
+            return None;
+        }
+        Some(dir.join(cmd)).filter(|path| path.is_executable())
+    })
 }
 
 /// type command implementation
-pub fn type_command(args: Vec<String>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) {
+pub fn type_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
     // For now, we just handle one argument
     let first_arg = match args.first() {
         Some(arg) => arg,
         None => {
             // Handle no argument case, typically do nothing and return 1
-            // We will do this later
-            return;
+            return 1;
         }
     };
-    let builtin = BUILTIN_COMMANDS.with(|cmds| cmds.contains_key(first_arg.as_str()));
+    if let Some(value) = first_arg.to_str().and_then(|name| env.aliases.get(name)) {
+        let line = format!("{} is aliased to `{}'\n", first_arg.display(), value);
+        builtin_output!(env, "{}", line);
+        return 0;
+    }
+    let builtin = first_arg
+        .to_str()
+        .is_some_and(|name| BUILTIN_COMMANDS.with(|cmds| cmds.contains_key(name)));
 
     // builtin command
     if builtin {
-        builtin_output!(env, "{} is a shell builtin\n", first_arg);
-        return;
+        builtin_output!(env, "{} is a shell builtin\n", first_arg.display());
+        return 0;
     }
 
     // external command
-    if let Some(entry) = get_executable_in_path(first_arg, env.deref()) {
-        builtin_output!(env, "{} is {}\n", first_arg, entry.path().display());
-        return;
+    if let Some(path) = get_executable_in_path(first_arg, env.deref()) {
+        builtin_output!(env, "{} is {}\n", first_arg.display(), path.display());
+        return 0;
     }
 
-    builtin_error!(env, "{}: not found\n", first_arg);
+    builtin_error!(env, "{}: not found\n", first_arg.display());
+    1
 }
 
-pub fn pwd_command(_: Vec<String>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) {
-    if let Ok(path) = std::env::current_dir() {
-        builtin_output!(env, "{}\n", path.display());
+pub fn pwd_command(_: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    if !env.pwd.as_os_str().is_empty() {
+        let pwd = env.pwd.clone();
+        builtin_output!(env, "{}\n", pwd.display());
+        return 0;
+    }
+
+    match std::env::current_dir() {
+        Ok(path) => {
+            builtin_output!(env, "{}\n", path.display());
+            0
+        }
+        Err(e) => {
+            builtin_error!(env, "pwd: {}\n", e);
+            1
+        }
     }
 }
 
-pub fn cd_command(args: Vec<String>, _env: RefMut<ExecEnv>, _: &mut ExecContext) {
-    fn navigate(path: &Path) {
-        if std::env::set_current_dir(path).is_err() {
-            builtin_error!(_env, "cd: {}: No such file or directory\n", path.display());
+/// Remove `.` and `..` from a path lexically, `..` removes the previous component even if it
+/// is a symbolic link.
+pub(crate) fn normalize_logical_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
         }
     }
+    normalized
+}
+
+/// cd command implementation
+///
+/// Like bash, `cd` works on the logical working directory by default: `cd ..` from a
+/// directory entered through a symbolic link returns to the directory containing the link.
+/// If the logical path can't be used, the path is resolved physically instead.
+pub fn cd_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    if args.len() > 1 {
+        builtin_error!(env, "cd: too many arguments\n");
+        return 1;
+    }
+
+    let target = match args.first() {
+        Some(p) => PathBuf::from(p),
+        // The shell variable `HOME` is used first, like `~`. When it is not set, `bash`
+        // will print "bash: cd: HOME not set", while `zsh` will just do nothing. See
+        // `ShellOptions`. Both do nothing if it is empty.
+        _ => match env.get_var_or_env("HOME") {
+            Some(home_dir) if home_dir.is_empty() => return 0,
+            Some(home_dir) => PathBuf::from(home_dir),
+            None if env.options.posix => {
+                builtin_error!(env, "cd: HOME not set\n");
+                return 1;
+            }
+            None => return 0,
+        },
+    };
 
-    fn navigate_to_home() {
-        // When $HOME is not set, `bash` will print "bash: cd: HOME not set",
-        // while `zsh` will just do nothing. We follow `zsh`'s behavior here.
-        if let Some(home_dir) = std::env::home_dir() {
-            navigate(&home_dir);
+    if !env.pwd.as_os_str().is_empty() {
+        let logical = normalize_logical_path(&env.pwd.join(&target));
+        if std::env::set_current_dir(&logical).is_ok() {
+            env.pwd = logical;
+            return 0;
         }
     }
 
-    match args.first() {
-        None => {
-            navigate_to_home();
+    if std::env::set_current_dir(&target).is_err() {
+        builtin_error!(env, "cd: {}: No such file or directory\n", target.display());
+        return 1;
+    }
+    env.pwd = std::env::current_dir().unwrap_or_default();
+    0
+}
+
+/// set command implementation
+///
+/// Only `set -o name` and `set +o name` are supported now. `set -o` and `set +o` without a
+/// name list the options.
+pub fn set_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.to_str() {
+            Some("-o") => true,
+            Some("+o") => false,
+            _ => {
+                builtin_error!(env, "set: {}: invalid option\n", arg.display());
+                return 2;
+            }
+        };
+
+        let Some(name) = args.next() else {
+            for (name, value) in env.options.list() {
+                if arg == "-o" {
+                    builtin_output!(env, "{:<15}{}\n", name, if value { "on" } else { "off" });
+                } else {
+                    builtin_output!(env, "set {}o {}\n", if value { '-' } else { '+' }, name);
+                }
+            }
+            return 0;
+        };
+
+        if !name
+            .to_str()
+            .is_some_and(|name| env.options.set(name, value))
+        {
+            builtin_error!(env, "set: {}: invalid option name\n", name.display());
+            return 2;
+        }
+    }
+    0
+}
+
+/// export command implementation
+///
+/// `export name[=value]...` marks the variables to be passed to commands, and sets the ones
+/// with a value. Without arguments, or with `-p`, the exported variables are listed like
+/// bash does, including the ones from the environment of the shell.
+pub fn export_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let args = match args.split_first() {
+        Some((first, rest)) if first == "-p" || first == "--" => rest,
+        Some((first, _)) if first.as_bytes().starts_with(b"-") => {
+            builtin_error!(env, "export: {}: invalid option\n", first.display());
+            return 2;
+        }
+        _ => &args,
+    };
+    if args.is_empty() {
+        return list_exported(env);
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let (name, value) = match arg.to_str().map(|arg| arg.split_once('=')) {
+            Some(Some((name, value))) => (name, Some(value)),
+            Some(None) => (arg.to_str().unwrap(), None),
+            None => ("", None),
+        };
+        if !is_valid_name(name) {
+            builtin_error!(env, "export: `{}': not a valid identifier\n", arg.display());
+            status = 1;
+            continue;
         }
-        Some(p) => {
-            if p == "~" {
-                navigate_to_home();
-                return;
+        if let Some(value) = value {
+            env.set_var(name.to_owned(), value.to_owned());
+        }
+        env.export(name.to_owned());
+    }
+    status
+}
+
+/// unset command implementation
+///
+/// `unset name...` removes the variables, and names which are not set are ignored. Variables
+/// of the environment of the shell are removed from the environment of later commands too.
+/// Only variables are supported, so `-v` is accepted while `-f` is not.
+pub fn unset_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let args = match args.split_first() {
+        Some((first, rest)) if first == "-v" || first == "--" => rest,
+        Some((first, _)) if first.as_bytes().starts_with(b"-") => {
+            builtin_error!(env, "unset: {}: invalid option\n", first.display());
+            return 2;
+        }
+        _ => &args,
+    };
+
+    let mut status = 0;
+    for arg in args {
+        match arg.to_str().filter(|name| is_valid_name(name)) {
+            Some(name) => env.unset_var(name),
+            None => {
+                builtin_error!(env, "unset: `{}': not a valid identifier\n", arg.display());
+                status = 1;
             }
+        }
+    }
+    status
+}
 
-            let path = PathBuf::from(p);
-            navigate(&path);
+/// jobs command implementation
+///
+/// `jobs [%N]...` lists the jobs, or the ones named, with their state, like
+/// `[1]+  Running                 sleep 5 &`. Like bash, jobs which are done are removed
+/// once they are listed, so they are not reported again before the next prompt.
+pub fn jobs_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let jobs = env.jobs.clone();
+    jobs.reap();
+
+    let mut status = 0;
+    let ids: Vec<usize> = if args.is_empty() {
+        jobs.jobs().iter().map(|job| job.id).collect()
+    } else {
+        let mut ids = Vec::new();
+        for arg in &args {
+            match arg.to_str().and_then(|spec| jobs.find(spec)) {
+                Some(id) => ids.push(id),
+                None => {
+                    builtin_error!(env, "jobs: {}: no such job\n", arg.display());
+                    status = 1;
+                }
+            }
+        }
+        ids
+    };
+    for &id in &ids {
+        if let Some(line) = jobs.status_line(id) {
+            builtin_output!(env, "{}\n", line);
         }
     }
+    // Removed after all are listed, since the `+` and `-` marks depend on the others
+    for id in ids {
+        if jobs
+            .get(id)
+            .is_some_and(|job| matches!(job.state, JobState::Done(_)))
+        {
+            jobs.remove(id);
+        }
+    }
+    status
+}
+
+/// fg command implementation
+///
+/// `fg [job]` continues the job in the foreground and waits for it, see
+/// `execution::foreground_job`. The job is `%N` or `N`, and the current job without it.
+pub fn fg_command(args: Vec<OsString>, env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let (spec, name) = match args.as_slice() {
+        [] => (String::from("%%"), String::from("current")),
+        [arg] => {
+            let name = arg.to_string_lossy().into_owned();
+            let spec = if name.starts_with('%') {
+                name.clone()
+            } else {
+                format!("%{}", name)
+            };
+            (spec, name)
+        }
+        _ => {
+            builtin_error!(env, "fg: too many arguments\n");
+            return 1;
+        }
+    };
+    let Some(id) = env.jobs.find(&spec) else {
+        builtin_error!(env, "fg: {}: no such job\n", name);
+        return 1;
+    };
+    match execution::foreground_job(id, &env) {
+        Ok(status) => status,
+        Err(msg) => {
+            builtin_error!(env, "fg: {}\n", msg);
+            1
+        }
+    }
+}
+
+/// suspend command implementation
+///
+/// `suspend [-f]` stops the shell until it gets `SIGCONT`, like when it runs as a job of
+/// another shell. Like bash, a login shell is only stopped with `-f`, since there may be no
+/// shell to continue it. A shell without job control is never stopped, since it may share
+/// its process group with the command which started it, such as `make`.
+pub fn suspend_command(args: Vec<OsString>, env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let force = match args.as_slice() {
+        [] => false,
+        [arg] if arg == "-f" => true,
+        [arg, ..] => {
+            builtin_error!(env, "suspend: {}: invalid option\n", arg.display());
+            return 2;
+        }
+    };
+    if env.job_control.is_none() {
+        builtin_error!(env, "suspend: no job control\n");
+        return 1;
+    }
+    if env.login && !force {
+        builtin_error!(env, "suspend: cannot suspend a login shell\n");
+        return 1;
+    }
+    // With job control the shell ignores `SIGTSTP`, while `SIGSTOP` can't be ignored. The
+    // jobs are in their own process groups, so they are not stopped. The group is only
+    // stopped if the shell leads it, otherwise it belongs to whoever started the shell.
+    let pid = unsafe { libc::getpid() };
+    let target = if unsafe { libc::getpgrp() } == pid {
+        0
+    } else {
+        pid
+    };
+    if unsafe { libc::kill(target, libc::SIGSTOP) } == -1 {
+        builtin_error!(env, "suspend: {}\n", io::Error::last_os_error());
+        return 1;
+    }
+    0
+}
+
+/// An alias as `alias` lists it, like `alias ll='ls -l'`. Like bash, the value is always
+/// single-quoted, so it can be read back.
+fn alias_line(name: &str, value: &str) -> String {
+    format!("alias {}='{}'\n", name, value.replace('\'', r"'\''"))
+}
+
+/// alias command implementation
+///
+/// `alias name=value...` defines aliases, which are expanded when they are the first word
+/// of a command, see `parse::parse_command`. `alias name` prints the alias, and without
+/// arguments or with `-p`, all of them are listed sorted by name.
+pub fn alias_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let args = match args.split_first() {
+        Some((first, rest)) if first == "-p" || first == "--" => rest,
+        Some((first, _)) if first.as_bytes().starts_with(b"-") => {
+            builtin_error!(env, "alias: {}: invalid option\n", first.display());
+            return 2;
+        }
+        _ => &args,
+    };
+    if args.is_empty() {
+        let aliases: BTreeMap<_, _> = env.aliases.clone().into_iter().collect();
+        for (name, value) in aliases {
+            builtin_output!(env, "{}", alias_line(&name, &value));
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args {
+        let arg = arg.to_string_lossy();
+        match arg.split_once('=') {
+            // Characters which would end the word or quote it can't be in the name
+            Some((name, _))
+                if name.is_empty()
+                    || name
+                        .contains(|c: char| c.is_whitespace() || "/$`'\"\\|&;<>()".contains(c)) =>
+            {
+                builtin_error!(env, "alias: `{}': invalid alias name\n", name);
+                status = 1;
+            }
+            Some((name, value)) => {
+                env.aliases.insert(name.to_owned(), value.to_owned());
+            }
+            None => match env.aliases.get(arg.as_ref()) {
+                Some(value) => {
+                    let line = alias_line(&arg, value);
+                    builtin_output!(env, "{}", line);
+                }
+                None => {
+                    builtin_error!(env, "alias: {}: not found\n", arg);
+                    status = 1;
+                }
+            },
+        }
+    }
+    status
+}
+
+/// unalias command implementation
+///
+/// `unalias name...` removes the aliases, and `unalias -a` removes all of them.
+pub fn unalias_command(args: Vec<OsString>, mut env: RefMut<ExecEnv>, _: &mut ExecContext) -> i32 {
+    let args = match args.split_first() {
+        Some((first, _)) if first == "-a" => {
+            env.aliases.clear();
+            return 0;
+        }
+        Some((first, rest)) if first == "--" => rest,
+        Some((first, _)) if first.as_bytes().starts_with(b"-") => {
+            builtin_error!(env, "unalias: {}: invalid option\n", first.display());
+            return 2;
+        }
+        Some(_) => &args,
+        None => {
+            builtin_error!(env, "unalias: usage: unalias [-a] name [name ...]\n");
+            return 2;
+        }
+    };
+
+    let mut status = 0;
+    for arg in args {
+        let name = arg.to_string_lossy();
+        if env.aliases.remove(name.as_ref()).is_none() {
+            builtin_error!(env, "unalias: {}: not found\n", name);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// List the exported variables for `export`, sorted by name. Variables which are exported
+/// but not set are listed without a value.
+fn list_exported(mut env: RefMut<ExecEnv>) -> i32 {
+    let mut vars: BTreeMap<String, Option<String>> = std::env::vars_os()
+        .map(|(name, value)| {
+            let value = value.to_string_lossy().into_owned();
+            (name.to_string_lossy().into_owned(), Some(value))
+        })
+        .collect();
+    vars.retain(|name, _| !env.unset_env.contains(name));
+    for (name, value) in env.exported_vars() {
+        vars.insert(name.to_owned(), Some(value.to_owned()));
+    }
+    for name in &env.exported {
+        vars.entry(name.clone()).or_insert(None);
+    }
+
+    for (name, value) in vars {
+        match value {
+            Some(value) => {
+                // Escaped like in double quotes, so the output can be read back
+                let mut escaped = String::with_capacity(value.len());
+                for c in value.chars() {
+                    if matches!(c, '"' | '\\' | '$' | '`') {
+                        escaped.push('\\');
+                    }
+                    escaped.push(c);
+                }
+                builtin_output!(env, "declare -x {}=\"{}\"\n", name, escaped);
+            }
+            None => {
+                builtin_output!(env, "declare -x {}\n", name);
+            }
+        }
+    }
+    0
 }
 
 struct HistoryArgs {
     num: Option<usize>,
-    read: Option<String>,
-    write: Option<String>,
-    append: Option<String>,
+    read: Option<OsString>,
+    write: Option<OsString>,
+    append: Option<OsString>,
+    print: Option<Vec<OsString>>,
 }
 
 impl HistoryArgs {
@@ -152,6 +806,7 @@ impl HistoryArgs {
             read: None,
             write: None,
             append: None,
+            print: None,
         }
     }
 
@@ -160,82 +815,142 @@ impl HistoryArgs {
         self
     }
 
-    fn with_read(mut self, read: String) -> Self {
+    fn with_read(mut self, read: OsString) -> Self {
         self.read = Some(read);
         self
     }
 
-    fn with_write(mut self, write: String) -> Self {
+    fn with_write(mut self, write: OsString) -> Self {
         self.write = Some(write);
         self
     }
 
-    fn with_append(mut self, append: String) -> Self {
+    fn with_append(mut self, append: OsString) -> Self {
         self.append = Some(append);
         self
     }
+
+    fn with_print(mut self, print: Vec<OsString>) -> Self {
+        self.print = Some(print);
+        self
+    }
 }
 
-fn parse_history_args(args: Vec<String>) -> HistoryArgs {
-    let args_len = args.len();
-    for (i, arg) in args.iter().enumerate() {
-        if arg == "-r" && i + 1 < args_len {
-            return HistoryArgs::new().with_read(args[i + 1].clone());
-        } else if arg == "-w" && i + 1 < args_len {
-            return HistoryArgs::new().with_write(args[i + 1].clone());
-        } else if arg == "-a" && i + 1 < args_len {
-            return HistoryArgs::new().with_append(args[i + 1].clone());
-        } else if let Ok(num) = arg.parse::<usize>() {
-            return HistoryArgs::new().with_num(num);
+fn parse_history_args(args: Vec<OsString>) -> Result<HistoryArgs, String> {
+    let mut args = args.into_iter();
+    let Some(arg) = args.next() else {
+        return Ok(HistoryArgs::new());
+    };
+
+    let history_args = match arg.to_str() {
+        // All the remaining arguments are expanded
+        Some("-p") => return Ok(HistoryArgs::new().with_print(args.collect())),
+        Some(opt @ ("-r" | "-w" | "-a")) => {
+            let Some(file) = args.next() else {
+                return Err(format!("history: {}: option requires an argument", opt));
+            };
+            match opt {
+                "-r" => HistoryArgs::new().with_read(file),
+                "-w" => HistoryArgs::new().with_write(file),
+                _ => HistoryArgs::new().with_append(file),
+            }
         }
-    }
+        Some(opt) if opt.starts_with('-') => {
+            return Err(format!("history: {}: invalid option", opt));
+        }
+        _ => match arg.to_str().map(str::parse::<usize>) {
+            Some(Ok(num)) => HistoryArgs::new().with_num(num),
+            _ => {
+                return Err(format!(
+                    "history: {}: numeric argument required",
+                    arg.display()
+                ));
+            }
+        },
+    };
 
-    HistoryArgs::new()
+    if args.next().is_some() {
+        return Err(String::from("history: too many arguments"));
+    }
+    Ok(history_args)
 }
 
-fn list_history(mut env: RefMut<ExecEnv>, context: &ExecContext, num: usize) {
+fn list_history(mut env: RefMut<ExecEnv>, context: &ExecContext, num: usize) -> i32 {
     let ignore = context.history.len().saturating_sub(num);
 
-    context
-        .history
-        .iter()
-        .enumerate()
-        .skip(ignore)
-        .for_each(|(index, entry)| {
-            builtin_output!(env, "    {}  {}\n", index + 1, entry);
-        });
+    for (index, entry) in context.history.iter().enumerate().skip(ignore) {
+        builtin_output!(env, "    {}  {}\n", index + 1, entry);
+    }
+    0
 }
 
-pub fn history_command(args: Vec<String>, env: RefMut<ExecEnv>, context: &mut ExecContext) {
+pub fn history_command(
+    args: Vec<OsString>,
+    mut env: RefMut<ExecEnv>,
+    context: &mut ExecContext,
+) -> i32 {
     // Some shells don't add the `history` command to the history list,
     // but we will add it for simplicity.
-    let args = parse_history_args(args);
+    let args = match parse_history_args(args) {
+        Ok(args) => args,
+        Err(msg) => {
+            builtin_error!(env, "{}\n", msg);
+            return 2;
+        }
+    };
+
+    if let Some(print) = args.print {
+        // Expand all the arguments before printing, nothing is printed if one fails
+        let mut expanded = Vec::with_capacity(print.len());
+        for arg in print {
+            // The expansion works on text, other arguments are printed as they are
+            let Some(line) = arg.to_str() else {
+                expanded.push(arg.into_vec());
+                continue;
+            };
+            match expand_history(line, context.history) {
+                Ok(line) => expanded.push(line.into_bytes()),
+                Err(event) => {
+                    builtin_error!(env, "history: {}: event not found\n", event);
+                    return 1;
+                }
+            }
+        }
+        for mut line in expanded {
+            line.push(b'\n');
+            builtin_output_bytes!(env, &line);
+        }
+        return 0;
+    }
 
     if let Some(read_file) = args.read {
         let path = PathBuf::from(read_file);
         if let Err(e) = context.history.load(&path) {
             builtin_error!(env, "history: {}: {}\n", path.display(), e);
+            return 1;
         }
-        return;
+        return 0;
     }
 
     if let Some(write_file) = args.write {
         let path = PathBuf::from(write_file);
-        if let Err(e) = context.history.save(&path) {
+        if let Err(e) = save_history(context.history, &path) {
             builtin_error!(env, "history: {}: {}\n", path.display(), e);
+            return 1;
         }
-        return;
+        return 0;
     }
 
     if let Some(append_file) = args.append {
         let path = PathBuf::from(append_file);
-        if let Err(e) = context.history.append(&path) {
+        if let Err(e) = append_history(context.history, &path) {
             builtin_error!(env, "history: {}: {}\n", path.display(), e);
+            return 1;
         }
-        return;
+        return 0;
     }
 
     let num = args.num.unwrap_or(context.history.len());
 
-    list_history(env, context, num);
+    list_history(env, context, num)
 }
@@ -1,54 +1,199 @@
-use std::{
-    cell::RefCell,
-    fs::{self, DirEntry, ReadDir},
-    rc::Rc,
-};
+use std::{cell::RefCell, ffi::OsString, fs, os::unix::ffi::OsStrExt, path::PathBuf, rc::Rc};
 
 use is_executable::IsExecutable;
 use rustyline::{
-    Helper, Highlighter, Hinter, Validator,
+    Helper, Highlighter, Hinter,
     completion::{Completer, Pair},
+    validate::{ValidationContext, ValidationResult, Validator},
 };
 
 use crate::{
-    builtin::BUILTIN_COMMANDS,
+    builtin::{BUILTIN_COMMANDS, normalize_logical_path},
     env::ExecEnv,
-    parse::{self, ParseFragment},
+    parse,
+    quote::quote,
 };
 
-#[derive(Debug, Clone, Helper, Validator, Highlighter, Hinter)]
+/// Characters which start a new completion word inside an argument, such as the value in
+/// `--opt=value` or the last entry in `PATH=/bin:/usr/bin`.
+pub const DEFAULT_WORD_BREAKS: &[char] = &['=', ':'];
+
+/// The word under the cursor, found with the same quoting rules as the tokenizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CompletionWord {
+    /// Byte index where the replacement starts.
+    start: usize,
+    /// The text to complete, from `start` to the cursor.
+    text: String,
+    /// Whether the word is a command name.
+    command: bool,
+    /// The name of the command the word is an argument of, if it is not a redirection
+    /// target.
+    argument_of: Option<String>,
+}
+
+#[derive(Debug, Clone, Helper, Highlighter, Hinter)]
 pub struct ShellCompleter {
     builtins: Vec<&'static str>,
     env: Rc<RefCell<ExecEnv>>,
+    word_breaks: Vec<char>,
 }
 
 impl ShellCompleter {
     pub fn new(env: Rc<RefCell<ExecEnv>>) -> Self {
         let builtins = BUILTIN_COMMANDS.with(|map| map.keys().copied().collect());
-        Self { builtins, env }
+        Self {
+            builtins,
+            env,
+            word_breaks: DEFAULT_WORD_BREAKS.to_vec(),
+        }
     }
 
-    fn candidate_executable_in_path(prefix: &str, env: &ExecEnv) -> impl Iterator<Item = DirEntry> {
-        fn dir_candidate_executable(
-            prefix: &str,
-            reader: ReadDir,
-        ) -> impl Iterator<Item = DirEntry> {
-            reader.flatten().filter(move |entry| {
-                entry.path().is_executable()
-                    && entry.file_name().to_string_lossy().starts_with(prefix)
-            })
-        }
+    /// Set the characters which start a new completion word inside an argument.
+    pub fn set_word_breaks(&mut self, word_breaks: &[char]) {
+        self.word_breaks = word_breaks.to_vec();
+    }
+
+    /// Find the word under the cursor, `line` is the text before the cursor.
+    ///
+    /// Words are split at unquoted whitespace, `|`, `<` and `>`. The first word of a
+    /// command which is not an assignment or a redirection target is the command name.
+    fn completion_word(&self, line: &str) -> CompletionWord {
+        let mut start = 0;
+        let mut text = String::new();
+        // No command name yet in the current command
+        let mut expect_command = true;
+        let mut redirect_target = false;
+        let mut single_quote = false;
+        let mut double_quote = false;
+        let mut backslash = false;
+        // Whether the current word so far is `name=`, so it is an assignment.
+        let mut assignment = false;
+        let mut command_name = None;
 
-        env.path_env
-            .iter()
-            .filter_map(move |dir| {
-                if let Ok(entries) = fs::read_dir(dir) {
-                    Some(dir_candidate_executable(prefix, entries))
+        for (i, c) in line.char_indices() {
+            let next = i + c.len_utf8();
+            if backslash {
+                backslash = false;
+                text.push(c);
+                continue;
+            }
+            if single_quote {
+                if c == '\'' {
+                    single_quote = false;
                 } else {
-                    None
+                    text.push(c);
                 }
-            })
+                continue;
+            }
+            if double_quote {
+                match c {
+                    '"' => double_quote = false,
+                    '\\' => backslash = true,
+                    _ => text.push(c),
+                }
+                continue;
+            }
+
+            match c {
+                '\\' => backslash = true,
+                '\'' => single_quote = true,
+                '"' => double_quote = true,
+                c if c.is_whitespace() || matches!(c, '|' | '<' | '>') => {
+                    // The word before the delimiter ends here, unless it is the fd of a
+                    // redirection like `2>`
+                    let fd = matches!(c, '<' | '>') && text.chars().all(|c| c.is_ascii_digit());
+                    if i > start && !fd {
+                        if expect_command && !assignment && !redirect_target {
+                            expect_command = false;
+                            command_name = Some(text.clone());
+                        }
+                        redirect_target = false;
+                    }
+                    text.clear();
+                    assignment = false;
+                    match c {
+                        '|' => {
+                            expect_command = true;
+                            command_name = None;
+                        }
+                        '<' | '>' => redirect_target = true,
+                        _ => {}
+                    }
+                    start = next;
+                }
+                '=' if expect_command
+                    && !redirect_target
+                    && !assignment
+                    && parse::is_valid_name(&text) =>
+                {
+                    assignment = true;
+                    text.clear();
+                    start = next;
+                }
+                c if (!expect_command || assignment) && self.word_breaks.contains(&c) => {
+                    text.clear();
+                    start = next;
+                }
+                _ => text.push(c),
+            }
+        }
+
+        let command = expect_command && !redirect_target && !assignment;
+        CompletionWord {
+            start,
+            text,
+            command,
+            argument_of: command_name.filter(|_| !command && !redirect_target),
+        }
+    }
+
+    /// Names of the executables in `PATH` starting with `prefix`, see `PathCache`.
+    fn candidate_executable_in_path(prefix: &str, env: &ExecEnv) -> Vec<OsString> {
+        let mut candidates = Vec::new();
+        for dir in env.path_env.iter() {
+            let names = env.path_cache.entries(dir);
+            candidates.extend(
+                names
+                    .iter()
+                    .filter(|name| name.as_bytes().starts_with(prefix.as_bytes()))
+                    .filter(|name| dir.join(name).is_executable())
+                    .cloned(),
+            );
+        }
+        candidates
+    }
+
+    /// Directories matching `frag`, as the argument of `cd`.
+    ///
+    /// `frag` is split at the last `/`: the directory part is read, and its entries are
+    /// matched with the rest, so `src/comp` completes to `src/completion/`. Hidden
+    /// directories are only listed when the rest starts with `.`.
+    fn candidate_directories(frag: &str, env: &ExecEnv) -> Vec<Pair> {
+        let (dir, prefix) = match frag.rfind('/') {
+            Some(i) => frag.split_at(i + 1),
+            None => ("", frag),
+        };
+        // Relative paths are read from the logical working directory, like `cd` does.
+        let path = if env.pwd.as_os_str().is_empty() {
+            PathBuf::from(if dir.is_empty() { "." } else { dir })
+        } else {
+            normalize_logical_path(&env.pwd.join(dir))
+        };
+        let Ok(entries) = fs::read_dir(&path) else {
+            return Vec::new();
+        };
+        entries
             .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| name.starts_with(prefix))
+            .filter(|name| prefix.starts_with('.') || !name.starts_with('.'))
+            .map(|name| Pair {
+                replacement: format!("{}/", quote(&format!("{}{}", dir, name))),
+                display: format!("{}/", name),
+            })
+            .collect()
     }
 }
 
@@ -61,41 +206,55 @@ impl Completer for ShellCompleter {
         pos: usize,
         _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        let fragments = parse::parse_to_fragments(line);
-
-        if fragments.is_empty() {
-            return Ok((0, Vec::new()));
-        }
-
-        let fragment_index = fragments.len() - 1;
-        let last_fragment = &fragments[fragment_index];
+        let word = self.completion_word(&line[..pos]);
 
-        // Inplement basic completion for the first fragment only
-        if fragment_index == 0
-            && let ParseFragment::Argument(frag) = last_fragment
-        {
+        if word.command {
+            let frag = word.text.as_str();
             let env = self.env.borrow();
             let iter = Self::candidate_executable_in_path(frag, &env);
             let mut matches: Vec<_> = self
                 .builtins
                 .iter()
                 .filter(|cmd| cmd.starts_with(frag))
-                .map(|r| r.to_string())
-                .chain(iter.map(|entry| entry.file_name().to_string_lossy().to_string()))
+                .map(OsString::from)
+                .chain(iter)
                 .map(|cmd| {
-                    let mut replacement = cmd.clone();
-                    replacement.push(' ');
+                    let display = cmd.to_string_lossy().into_owned();
+                    let replacement = match cmd.to_str() {
+                        Some(cmd) => format!("{} ", quote(cmd)),
+                        // The line being edited is a `String`, so a name which is not valid
+                        // UTF-8 can be listed, but not inserted. Leave the line unchanged.
+                        None => line[word.start..pos].to_owned(),
+                    };
                     Pair {
-                        display: cmd,
+                        display,
                         replacement,
                     }
                 })
                 .collect();
             matches.sort_unstable_by(|a, b| a.display.cmp(&b.display));
             matches.dedup_by(|a, b| a.display == b.display);
-            return Ok((0, matches));
+            return Ok((word.start, matches));
+        }
+
+        if word.argument_of.as_deref() == Some("cd") {
+            let mut matches = Self::candidate_directories(&word.text, &self.env.borrow());
+            matches.sort_unstable_by(|a, b| a.display.cmp(&b.display));
+            return Ok((word.start, matches));
         }
 
-        Ok((pos, Vec::new()))
+        // Only command names and directories for `cd` can be completed now
+        Ok((word.start, Vec::new()))
+    }
+}
+
+/// A line ending with a backslash or in quotes continues on the next line.
+impl Validator for ShellCompleter {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if parse::is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
     }
 }
@@ -0,0 +1,194 @@
+//! The conditional command `[[ ... ]]`.
+//!
+//! Unlike `[`, words are not split or expanded as patterns, and the operators are
+//! recognized by the parser, so `&&`, `||`, `<` and `>` don't need to be quoted. The right
+//! side of `==` and `!=` is a pattern, and the right side of `=~` is an extended regular
+//! expression. Quoted parts of them only match themselves.
+
+use std::{
+    ffi::{CString, OsStr},
+    os::unix::ffi::OsStrExt,
+};
+
+use crate::{
+    env::ExecEnv,
+    expand::{expand_pattern, expand_value},
+    glob,
+    parse::Word,
+    test,
+};
+
+enum Expr<'a> {
+    Not(Box<Expr<'a>>),
+    And(Box<Expr<'a>>, Box<Expr<'a>>),
+    Or(Box<Expr<'a>>, Box<Expr<'a>>),
+    Unary(&'a str, &'a Word),
+    Binary(&'a Word, &'a str, &'a Word),
+    Word(&'a Word),
+}
+
+fn is_binary(op: &str) -> bool {
+    op == "=~" || test::is_binary(OsStr::new(op))
+}
+
+struct Parser<'a> {
+    words: &'a [Word],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Returns the operator at `offset`, which must not be quoted.
+    fn peek_op(&self, offset: usize) -> Option<&'a str> {
+        self.words
+            .get(self.pos + offset)
+            .and_then(Word::as_unquoted_text)
+    }
+
+    fn expect_operand(&mut self) -> Result<&'a Word, String> {
+        let word = self
+            .words
+            .get(self.pos)
+            .ok_or_else(|| String::from("syntax error in conditional expression"))?;
+        self.pos += 1;
+        Ok(word)
+    }
+
+    fn or(&mut self) -> Result<Expr<'a>, String> {
+        let mut expr = self.and()?;
+        while self.peek_op(0) == Some("||") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr<'a>, String> {
+        let mut expr = self.not()?;
+        while self.peek_op(0) == Some("&&") {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr<'a>, String> {
+        if self.peek_op(0) == Some("!") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr<'a>, String> {
+        match self.peek_op(0) {
+            Some("(") => {
+                self.pos += 1;
+                let expr = self.or()?;
+                if self.peek_op(0) != Some(")") {
+                    return Err(String::from("expected `)'"));
+                }
+                self.pos += 1;
+                return Ok(expr);
+            }
+            Some(op @ ("&&" | "||" | ")" | "&" | "|")) => {
+                return Err(format!("syntax error near `{}'", op));
+            }
+            Some(op) if test::is_unary(OsStr::new(op)) && self.pos + 1 < self.words.len() => {
+                self.pos += 1;
+                return Ok(Expr::Unary(op, self.expect_operand()?));
+            }
+            _ => {}
+        }
+
+        let left = self.expect_operand()?;
+        match self.peek_op(0) {
+            Some(op) if is_binary(op) => {
+                self.pos += 1;
+                Ok(Expr::Binary(left, op, self.expect_operand()?))
+            }
+            _ => Ok(Expr::Word(left)),
+        }
+    }
+}
+
+/// Push `s` to an extended regular expression so that it only matches itself.
+fn escape_regex(regex: &mut Vec<u8>, s: &[u8]) {
+    for &b in s {
+        if b"\\^$.|?*+()[]{}".contains(&b) {
+            regex.push(b'\\');
+        }
+        regex.push(b);
+    }
+}
+
+/// Returns true if `s` matches the extended regular expression `regex`, which may match any
+/// part of it.
+fn regex_matches(s: &OsStr, regex: &[u8]) -> Result<bool, String> {
+    let invalid = || {
+        format!(
+            "{}: invalid regular expression",
+            String::from_utf8_lossy(regex)
+        )
+    };
+    let pattern = CString::new(regex).map_err(|_| invalid())?;
+    // NUL can't be in an argument, so it is never in an expansion either
+    let s = CString::new(s.as_bytes()).map_err(|_| invalid())?;
+
+    let mut compiled = std::mem::MaybeUninit::<libc::regex_t>::uninit();
+    let ret = unsafe {
+        libc::regcomp(
+            compiled.as_mut_ptr(),
+            pattern.as_ptr(),
+            libc::REG_EXTENDED | libc::REG_NOSUB,
+        )
+    };
+    if ret != 0 {
+        return Err(invalid());
+    }
+    let ret = unsafe { libc::regexec(compiled.as_ptr(), s.as_ptr(), 0, std::ptr::null_mut(), 0) };
+    unsafe { libc::regfree(compiled.as_mut_ptr()) };
+    Ok(ret == 0)
+}
+
+fn evaluate_expr(expr: &Expr, env: &mut ExecEnv) -> Result<bool, String> {
+    Ok(match expr {
+        Expr::Not(expr) => !evaluate_expr(expr, env)?,
+        // The right side is not expanded if the value is known, like bash.
+        Expr::And(left, right) => evaluate_expr(left, env)? && evaluate_expr(right, env)?,
+        Expr::Or(left, right) => evaluate_expr(left, env)? || evaluate_expr(right, env)?,
+        Expr::Unary(op, word) => test::unary(OsStr::new(op), &expand_value(word, env)?)?,
+        Expr::Binary(left, op, right) => {
+            let left = expand_value(left, env)?;
+            match *op {
+                "==" | "=" | "!=" => {
+                    let pattern = expand_pattern(right, env, glob::escape_into)?;
+                    glob::matches(&pattern, left.as_bytes()) == (*op != "!=")
+                }
+                "=~" => regex_matches(&left, &expand_pattern(right, env, escape_regex)?)?,
+                _ => test::binary(&left, OsStr::new(op), &expand_value(right, env)?)?,
+            }
+        }
+        Expr::Word(word) => !expand_value(word, env)?.is_empty(),
+    })
+}
+
+/// Evaluate `[[ ... ]]`. `words` are all the words of the command, including `[[` and `]]`.
+pub fn evaluate(words: &[Word], env: &mut ExecEnv) -> Result<bool, String> {
+    let words = match words {
+        [_, words @ .., last] if last.as_unquoted_text() == Some("]]") => words,
+        [.., last] => {
+            return Err(format!("syntax error near `{}'", last.to_text()));
+        }
+        [] => unreachable!("`[[' is the first word"),
+    };
+    if words.is_empty() {
+        return Err(String::from("syntax error near `]]'"));
+    }
+
+    let mut parser = Parser { words, pos: 0 };
+    let expr = parser.or()?;
+    if let Some(word) = words.get(parser.pos) {
+        return Err(format!("syntax error near `{}'", word.to_text()));
+    }
+    evaluate_expr(&expr, env)
+}
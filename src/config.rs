@@ -0,0 +1,131 @@
+//! Shell configuration file.
+//!
+//! The configuration is written in a small subset of TOML: `[section]` headers, and
+//! `key = value` lines, where a value is a boolean or a double-quoted string. Unknown
+//! sections and keys are reported as warnings, so that an old mysh can still start with a
+//! newer configuration file.
+
+use std::{fmt::Write, path::PathBuf};
+
+use crate::env::ExecEnv;
+
+/// A value in the configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Bool(bool),
+    String(String),
+}
+
+impl Value {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "true" => return Some(Value::Bool(true)),
+            "false" => return Some(Value::Bool(false)),
+            _ => {}
+        }
+
+        let inner = s.strip_prefix('"')?.strip_suffix('"')?;
+        let mut value = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    c @ ('"' | '\\') => value.push(c),
+                    _ => return None,
+                },
+                '"' => return None,
+                _ => value.push(c),
+            }
+        }
+        Some(Value::String(value))
+    }
+}
+
+fn quote_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Write the current configuration of the shell.
+pub fn dump_config(env: &ExecEnv) -> String {
+    let mut config = String::from("# mysh configuration\n");
+
+    config.push_str("\n[options]\n");
+    for (name, value) in env.options.list() {
+        writeln!(config, "{} = {}", name, value).unwrap();
+    }
+
+    config.push_str("\n[history]\n");
+    if let Some(histfile) = &env.histfile_env {
+        // Paths which are not valid UTF-8 can't be written to the file.
+        if let Some(histfile) = histfile.to_str() {
+            writeln!(config, "file = {}", quote_string(histfile)).unwrap();
+        }
+    }
+
+    config
+}
+
+/// Apply a configuration to the shell, returns the warnings for lines that can't be applied.
+pub fn load_config(env: &mut ExecEnv, config: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut section = String::new();
+
+    for (index, line) in config.lines().enumerate() {
+        let lineno = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_owned();
+            if !matches!(section.as_str(), "options" | "history") {
+                warnings.push(format!("line {}: unknown section `{}`", lineno, section));
+            }
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            warnings.push(format!("line {}: expected `key = value`", lineno));
+            continue;
+        };
+        let key = key.trim();
+        let Some(value) = Value::parse(value.trim()) else {
+            warnings.push(format!("line {}: invalid value for `{}`", lineno, key));
+            continue;
+        };
+
+        let applied = match (section.as_str(), value) {
+            ("options", Value::Bool(value)) => env.options.set(key, value),
+            ("history", Value::String(value)) if key == "file" => {
+                env.histfile_env = Some(PathBuf::from(value));
+                true
+            }
+            // Unknown sections are warned when the header is read
+            (section, _) if !matches!(section, "" | "options" | "history") => continue,
+            _ => false,
+        };
+        if !applied {
+            warnings.push(format!(
+                "line {}: unknown key or invalid value `{}` in section `{}`",
+                lineno, key, section
+            ));
+        }
+    }
+
+    warnings
+}
@@ -1,12 +1,26 @@
 use std::{
-    io::{PipeReader, PipeWriter},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, PipeReader, PipeWriter},
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+    rc::Rc,
+    time::{Duration, SystemTime},
 };
 
 use directories::BaseDirs;
+use libc::pid_t;
 use rustyline::history::FileHistory;
 
+use crate::{
+    expand::ProcessSubstitution,
+    job::{JobControl, JobTable},
+};
+
 #[derive(Debug, Clone)]
 pub struct PathEnv {
     pub paths: Vec<PathBuf>,
@@ -42,23 +56,293 @@ impl DerefMut for PathEnv {
     }
 }
 
+/// A directory which was modified this recently when it was read may be modified again
+/// without its modification time changing, since timestamps are only updated every clock
+/// tick. It is read again on the next lookup, like git does with racily clean files.
+const PATH_CACHE_RACY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The names of the files in the directories of `PATH`, so that completion and command
+/// lookup don't read them every time.
+///
+/// A directory is read again when its modification time changes, which happens when a file
+/// is added to it, removed or renamed, like when a program is installed.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    dirs: RefCell<HashMap<PathBuf, CachedDir>>,
+}
+
+#[derive(Debug)]
+struct CachedDir {
+    mtime: SystemTime,
+    names: Rc<[OsString]>,
+    /// The directory was modified just before it was read, see `PATH_CACHE_RACY_INTERVAL`.
+    racy: bool,
+}
+
+impl PathCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The names of the files in `dir`, which is empty if it can't be read.
+    pub fn entries(&self, dir: &Path) -> Rc<[OsString]> {
+        let Ok(mtime) = fs::metadata(dir).and_then(|metadata| metadata.modified()) else {
+            self.dirs.borrow_mut().remove(dir);
+            return Rc::from([]);
+        };
+        if let Some(cached) = self.dirs.borrow().get(dir)
+            && cached.mtime == mtime
+            && !cached.racy
+        {
+            return Rc::clone(&cached.names);
+        }
+
+        let read_time = SystemTime::now();
+        let names: Rc<[OsString]> = match fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().map(|entry| entry.file_name()).collect(),
+            Err(_) => return Rc::from([]),
+        };
+        let racy = read_time
+            .duration_since(mtime)
+            .map_or(true, |age| age < PATH_CACHE_RACY_INTERVAL);
+        self.dirs.borrow_mut().insert(
+            dir.to_owned(),
+            CachedDir {
+                mtime,
+                names: Rc::clone(&names),
+                racy,
+            },
+        );
+        names
+    }
+}
+
+/// Options that change the behavior of the shell.
+///
+/// Where bash and zsh behave differently, mysh follows zsh by default. With `posix` set, it
+/// follows bash (which is closer to POSIX) instead:
+///
+/// - `cd` without arguments prints `cd: HOME not set` when `HOME` is unset, while zsh does
+///   nothing.
+/// - When Ctrl-D is pressed, `exit` is printed before exiting, while zsh prints nothing.
+///
+/// Multiple redirections of the same fd, like `echo value > 1 > 2`, follow bash by
+/// default, where only the last one takes effect. With `multios` set, all of them take
+/// effect like zsh.
+///
+/// The line editor uses the vi mode if `vi` is set, otherwise the emacs mode like bash. Only
+/// one of the `vi` and `emacs` options is on.
+///
+/// With `autocd`, a command without arguments which is a directory changes into it like
+/// `cd`, if there is no such command.
+///
+/// When a command is not found, the closest command name is suggested unless `suggest` is
+/// unset. With `correct`, an interactive shell asks whether to run it instead, like zsh.
+///
+/// Finished jobs are reported before the next prompt, or right away with `notify`.
+///
+/// Lines read by an interactive shell are added to the history unless `history` is unset,
+/// e.g. while typing secrets.
+#[derive(Debug, Clone, Default)]
+pub struct ShellOptions {
+    pub posix: bool,
+    pub multios: bool,
+    pub vi: bool,
+    pub autocd: bool,
+    pub suggest: bool,
+    pub correct: bool,
+    pub notify: bool,
+    pub history: bool,
+}
+
+impl ShellOptions {
+    pub fn new() -> Self {
+        Self {
+            suggest: true,
+            history: true,
+            ..Self::default()
+        }
+    }
+
+    /// Names of options for `set -o`, with their current values.
+    pub fn list(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("posix", self.posix),
+            ("multios", self.multios),
+            ("emacs", !self.vi),
+            ("vi", self.vi),
+            ("autocd", self.autocd),
+            ("suggest", self.suggest),
+            ("correct", self.correct),
+            ("notify", self.notify),
+            ("history", self.history),
+        ]
+    }
+
+    /// Set an option by its name, returns false if there is no such option.
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        match name {
+            "posix" => self.posix = value,
+            "multios" => self.multios = value,
+            "emacs" => self.vi = !value,
+            "vi" => self.vi = value,
+            "autocd" => self.autocd = value,
+            "suggest" => self.suggest = value,
+            "correct" => self.correct = value,
+            "notify" => self.notify = value,
+            "history" => self.history = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Returns `$PWD` if it is an absolute path to the working directory, like bash does.
+/// Otherwise returns the physical working directory.
+fn initial_pwd() -> PathBuf {
+    let Ok(cwd) = std::env::current_dir() else {
+        return PathBuf::new();
+    };
+    let Some(pwd) = std::env::var_os("PWD").map(PathBuf::from) else {
+        return cwd;
+    };
+
+    let same_file = |a: &Path, b: &Path| match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    };
+    let has_dots = pwd
+        .components()
+        .any(|c| matches!(c, Component::CurDir | Component::ParentDir));
+    if pwd.is_absolute() && !has_dots && same_file(&pwd, &cwd) {
+        pwd
+    } else {
+        cwd
+    }
+}
+
+/// A callback which is given a command line before it runs.
+pub type PreExecHook = Box<dyn FnMut(&str)>;
+/// A callback which is given a command line and its status after it runs.
+pub type PostExecHook = Box<dyn FnMut(&str, i32)>;
+
+/// Callbacks around the command lines read by the interactive shell or run by `Shell`, such
+/// as to update the title of a terminal. Command substitutions and `$PROMPT_COMMAND` don't
+/// call them.
+///
+/// They are taken out of the environment while they are called, so they may use it, but
+/// hooks added by them are dropped.
+#[derive(Default)]
+pub struct Hooks {
+    pub pre_exec: Vec<PreExecHook>,
+    pub post_exec: Vec<PostExecHook>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("pre_exec", &self.pre_exec.len())
+            .field("post_exec", &self.post_exec.len())
+            .finish()
+    }
+}
+
+impl Hooks {
+    /// Call the `pre_exec` hooks of `env` with `line`.
+    pub fn pre_exec(env: &Rc<RefCell<ExecEnv>>, line: &str) {
+        let mut hooks = std::mem::take(&mut env.borrow_mut().hooks);
+        for hook in &mut hooks.pre_exec {
+            hook(line);
+        }
+        env.borrow_mut().hooks = hooks;
+    }
+
+    /// Call the `post_exec` hooks of `env` with `line` and its status.
+    pub fn post_exec(env: &Rc<RefCell<ExecEnv>>, line: &str, status: i32) {
+        let mut hooks = std::mem::take(&mut env.borrow_mut().hooks);
+        for hook in &mut hooks.post_exec {
+            hook(line, status);
+        }
+        env.borrow_mut().hooks = hooks;
+    }
+}
+
 #[derive(Debug)]
 pub struct ExecEnv {
     pub path_env: PathEnv,
+    /// Files in the directories of `path_env`, see `PathCache`.
+    pub path_cache: PathCache,
     pub histfile_env: Option<PathBuf>,
     pub base_dirs: BaseDirs,
     pub pipe_in: Option<PipeReader>,
     pub pipe_out: Option<PipeWriter>,
+    /// `$0`
+    pub shell_name: OsString,
+    /// `$1`, `$2`, ...
+    pub positional_params: Vec<OsString>,
+    /// Shell variables
+    pub variables: HashMap<String, String>,
+    /// Aliases defined by `alias`, which are expanded when commands are parsed.
+    pub aliases: HashMap<String, String>,
+    /// Names marked by `export`, which may not be set yet.
+    pub exported: HashSet<String>,
+    /// Variables of the environment of the shell which are removed by `unset`. The
+    /// environment of the process is not changed, they are hidden instead.
+    pub unset_env: HashSet<String>,
+    pub options: ShellOptions,
+    /// Exit status of the last pipeline, `$?`
+    pub last_status: i32,
+    /// Exit status of the last command substitution of the command being expanded, which
+    /// is the status of a command without a name, like `x=$(false)`.
+    pub substitution_status: Option<i32>,
+    /// Whether commands are read from the user, rather than from `-c` or a script.
+    pub interactive: bool,
+    /// Whether the shell is a login shell, which is started with `-` before its name.
+    pub login: bool,
+    pub jobs: JobTable,
+    /// Set when `exit` is refused because of jobs, until another command runs.
+    pub exit_warned: bool,
+    /// Set if the shell has job control, see `job`.
+    pub job_control: Option<JobControl>,
+    /// The logical working directory (`$PWD`), which may contain symbolic links. It is empty
+    /// if the working directory is unknown.
+    pub pwd: PathBuf,
+    /// Callbacks around command lines, see `Hooks`.
+    pub hooks: Hooks,
+    /// When the shell started, which is `-2` of `printf '%(fmt)T'`.
+    pub start_time: SystemTime,
+    /// Processes of `<(command)` and `>(command)` started while expanding the current
+    /// command, which are waited for with the pipeline.
+    pub process_substitutions: Vec<ProcessSubstitution>,
 }
 
 impl ExecEnv {
     pub fn new(base_dirs: BaseDirs) -> Self {
         Self {
             path_env: PathEnv::new(),
+            path_cache: PathCache::new(),
             histfile_env: None,
             base_dirs,
             pipe_in: None,
             pipe_out: None,
+            shell_name: OsString::from("mysh"),
+            positional_params: Vec::new(),
+            variables: HashMap::new(),
+            aliases: HashMap::new(),
+            exported: HashSet::new(),
+            unset_env: HashSet::new(),
+            options: ShellOptions::new(),
+            last_status: 0,
+            substitution_status: None,
+            interactive: false,
+            login: false,
+            jobs: JobTable::new(),
+            exit_warned: false,
+            job_control: None,
+            pwd: initial_pwd(),
+            hooks: Hooks::default(),
+            start_time: SystemTime::now(),
+            process_substitutions: Vec::new(),
         }
     }
 
@@ -66,9 +350,160 @@ impl ExecEnv {
         Self {
             path_env,
             histfile_env,
-            base_dirs,
-            pipe_in: None,
-            pipe_out: None,
+            ..Self::new(base_dirs)
+        }
+    }
+
+    /// Returns the path of the history file, which is the first usable one of:
+    ///
+    /// 1. `$HISTFILE`
+    /// 2. `mysh_history` in the XDG data directory
+    /// 3. `$HOME/.mysh_history`
+    /// 4. `mysh_history-<uid>` in the temporary directory
+    ///
+    /// A path is usable if the file can be opened for appending, so it is created if it
+    /// doesn't exist. Returns the error of the last path if none of them is usable.
+    pub fn histfile_path(&self) -> io::Result<PathBuf> {
+        let data_dir = self.base_dirs.data_local_dir();
+        let uid = unsafe { libc::getuid() };
+        let candidates = [
+            (self.histfile_env.clone(), false),
+            (Some(data_dir.join("mysh_history")), true),
+            (Some(self.base_dirs.home_dir().join(".mysh_history")), false),
+            (
+                Some(std::env::temp_dir().join(format!("mysh_history-{}", uid))),
+                false,
+            ),
+        ];
+
+        let mut last_error = None;
+        for (path, create_dir) in candidates {
+            let Some(path) = path else { continue };
+            if create_dir && let Err(e) = fs::DirBuilder::new().recursive(true).create(data_dir) {
+                last_error = Some(e);
+                continue;
+            }
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(_) => return Ok(path),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap())
+    }
+
+    pub fn set_positional_params(&mut self, shell_name: OsString, params: Vec<OsString>) {
+        self.shell_name = shell_name;
+        self.positional_params = params;
+    }
+
+    /// Returns true if the shell may exit now.
+    ///
+    /// Like bash, an interactive shell with jobs refuses the first attempt to exit with a
+    /// warning, and exits on an immediate second attempt.
+    pub fn confirm_exit(&mut self) -> bool {
+        if !self.interactive || std::mem::take(&mut self.exit_warned) {
+            return true;
+        }
+        let Some(warning) = self.jobs.exit_warning() else {
+            return true;
+        };
+        eprintln!("{}", warning);
+        self.exit_warned = true;
+        false
+    }
+
+    /// Exit status of the last pipeline, `$?`.
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    /// Flags of the shell for `$-`, where `i` means interactive.
+    pub fn flags(&self) -> String {
+        let mut flags = String::new();
+        if self.interactive {
+            flags.push('i');
+        }
+        flags
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&str> {
+        self.variables.get(name).map(String::as_str)
+    }
+
+    pub fn set_var(&mut self, name: String, value: String) {
+        self.unset_env.remove(&name);
+        self.variables.insert(name, value);
+    }
+
+    /// Returns the value of a shell variable, or a variable of the environment if there is
+    /// no such shell variable.
+    pub fn get_var_or_env(&self, name: &str) -> Option<OsString> {
+        match self.get_var(name) {
+            Some(value) => Some(OsString::from(value)),
+            None if self.unset_env.contains(name) => None,
+            None => std::env::var_os(name),
+        }
+    }
+
+    /// Remove a variable, including its `export` mark and the variable of the environment
+    /// with the same name.
+    pub fn unset_var(&mut self, name: &str) {
+        self.variables.remove(name);
+        self.exported.remove(name);
+        if std::env::var_os(name).is_some() {
+            self.unset_env.insert(name.to_owned());
+        }
+    }
+
+    /// Mark a variable to be passed to commands.
+    pub fn export(&mut self, name: String) {
+        self.exported.insert(name);
+    }
+
+    /// Shell variables which are passed to commands: the exported ones, and the ones which
+    /// replace a variable of the environment, since that is passed to commands anyway.
+    pub fn exported_vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.variables
+            .iter()
+            .filter(|(name, _)| {
+                self.exported.contains(*name) || std::env::var_os(name.as_str()).is_some()
+            })
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    /// Save the state which a subshell may change, see `EnvSnapshot`.
+    pub fn snapshot(&self) -> EnvSnapshot {
+        EnvSnapshot {
+            path_env: self.path_env.clone(),
+            shell_name: self.shell_name.clone(),
+            positional_params: self.positional_params.clone(),
+            variables: self.variables.clone(),
+            aliases: self.aliases.clone(),
+            exported: self.exported.clone(),
+            unset_env: self.unset_env.clone(),
+            options: self.options.clone(),
+            pwd: self.pwd.clone(),
+            cwd: std::env::current_dir().ok(),
+        }
+    }
+
+    /// Roll back to `snapshot`, discarding the changes made since, including the working
+    /// directory of the process.
+    pub fn restore(&mut self, snapshot: EnvSnapshot) {
+        self.path_env = snapshot.path_env;
+        self.shell_name = snapshot.shell_name;
+        self.positional_params = snapshot.positional_params;
+        self.variables = snapshot.variables;
+        self.aliases = snapshot.aliases;
+        self.exported = snapshot.exported;
+        self.unset_env = snapshot.unset_env;
+        self.options = snapshot.options;
+        self.pwd = snapshot.pwd;
+        if let Some(cwd) = snapshot.cwd
+            && std::env::current_dir().ok().as_ref() != Some(&cwd)
+        {
+            // The directory may have been removed since, like bash, stay where we are.
+            let _ = std::env::set_current_dir(cwd);
         }
     }
 
@@ -78,12 +513,42 @@ impl ExecEnv {
     }
 }
 
+/// The state of `ExecEnv` which is isolated in subshells, such as `( ... )` and command
+/// substitution: variables, aliases, options, positional parameters and the working
+/// directory.
+///
+/// `$?`, jobs and pipes are not part of it, since they are shared with the parent shell.
+#[derive(Debug, Clone)]
+pub struct EnvSnapshot {
+    path_env: PathEnv,
+    shell_name: OsString,
+    positional_params: Vec<OsString>,
+    variables: HashMap<String, String>,
+    aliases: HashMap<String, String>,
+    exported: HashSet<String>,
+    unset_env: HashSet<String>,
+    options: ShellOptions,
+    pwd: PathBuf,
+    /// The physical working directory of the process.
+    cwd: Option<PathBuf>,
+}
+
 pub struct ExecContext<'a> {
     pub history: &'a mut FileHistory,
+    /// Process group of the pipeline being started with job control, which is set when its
+    /// first external command is spawned.
+    pub pgid: Option<pid_t>,
+    /// The pipeline being started ends with `&`, so it doesn't get the terminal and the
+    /// shell doesn't wait for it.
+    pub background: bool,
 }
 
 impl<'a> ExecContext<'a> {
     pub fn new(history: &'a mut FileHistory) -> Self {
-        Self { history }
+        Self {
+            history,
+            pgid: None,
+            background: false,
+        }
     }
 }
@@ -5,64 +5,275 @@ pub mod result;
 use std::{
     cell::RefCell,
     collections::VecDeque,
-    io::{self, PipeReader, PipeWriter},
-    process::Child,
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{self, PipeReader, PipeWriter, Write},
+    os::{
+        fd::OwnedFd,
+        unix::{ffi::OsStrExt, process::ExitStatusExt},
+    },
+    path::Path,
+    process::{Child, ExitStatus},
     rc::Rc,
+    time::Duration,
 };
 
 use crate::{
+    conditional,
     env::{ExecContext, ExecEnv},
     execution::{
         data::{CommandDescriptor, RawCommand},
         result::{CommandResult, ExecutionResult},
     },
-    redirect::RedirectHandler,
+    expand::{self, ExpandedCommand},
+    job::{self, JobState, JobWait},
+    parse::{self, ParseData, Word},
+    redirect::{self, InputSource, Multios, Redirect, RedirectHandler},
+    suggest,
 };
 
+/// Run a pipeline. `command` is the text of the pipeline, which is shown if it is stopped.
 pub fn execute_command_chain(
+    command: &str,
+    exec_chain: VecDeque<CommandDescriptor>,
+    env: Rc<RefCell<ExecEnv>>,
+    context: ExecContext,
+) -> CommandResult {
+    execute_command_chain_to(command, exec_chain, env, context, None)
+}
+
+/// Same as `execute_command_chain`, but the stdout of the last command is written to
+/// `output` instead of the stdout of the shell if it is given.
+pub fn execute_command_chain_to(
+    command: &str,
     mut exec_chain: VecDeque<CommandDescriptor>,
     env: Rc<RefCell<ExecEnv>>,
     mut context: ExecContext,
+    mut output: Option<PipeWriter>,
+) -> CommandResult {
+    // The warning of `exit` only holds for the next command.
+    let exit_warned = std::mem::take(&mut env.borrow_mut().exit_warned);
+
+    while let Some(descriptor) = exec_chain.pop_front() {
+        // `a && b` runs `b` only if `a` succeeds, and `a || b` only if it fails, while
+        // `a; b` always runs `b`. A skipped pipeline keeps the status, so `false && a || b`
+        // runs `b`, like bash.
+        let (run, first) = match descriptor {
+            CommandDescriptor::Begin(data) | CommandDescriptor::Next(data) => (true, data),
+            CommandDescriptor::And(data) => (env.borrow().last_status == 0, data),
+            CommandDescriptor::Or(data) => (env.borrow().last_status != 0, data),
+            CommandDescriptor::Pipe(_) | CommandDescriptor::Background => {
+                unreachable!("A pipeline starts with a command.")
+            }
+        };
+        let negate = first.negate;
+        let mut pipeline = VecDeque::from([first]);
+        while let Some(CommandDescriptor::Pipe(_)) = exec_chain.front() {
+            if let Some(CommandDescriptor::Pipe(data)) = exec_chain.pop_front() {
+                pipeline.push_back(data);
+            }
+        }
+        context.background = matches!(exec_chain.front(), Some(CommandDescriptor::Background));
+        if context.background {
+            exec_chain.pop_front();
+        }
+        if !run {
+            continue;
+        }
+
+        let pipe_out = if exec_chain.is_empty() {
+            output.take()
+        } else {
+            match output.as_ref().map(PipeWriter::try_clone).transpose() {
+                Ok(pipe_out) => pipe_out,
+                Err(e) => {
+                    eprintln!("mysh: can't duplicate the output: {}", e);
+                    env.borrow_mut().last_status = 1;
+                    return CommandResult::Normal;
+                }
+            }
+        };
+        let ret = execute_pipeline(
+            command,
+            pipeline,
+            Rc::clone(&env),
+            &mut context,
+            pipe_out,
+            exit_warned,
+        );
+        if let CommandResult::Exit(_) = ret {
+            return ret;
+        }
+        // `! a && b` runs `b` if `a` fails
+        if negate && !context.background {
+            let mut env = env.borrow_mut();
+            env.last_status = i32::from(env.last_status == 0);
+        }
+    }
+    CommandResult::Normal
+}
+
+/// Run the commands of a pipeline, see `execute_command_chain_to`. With
+/// `ExecContext::background`, it is added to the job table instead of being waited for.
+fn execute_pipeline(
+    command: &str,
+    mut pipeline: VecDeque<ParseData>,
+    env: Rc<RefCell<ExecEnv>>,
+    context: &mut ExecContext,
+    output: Option<PipeWriter>,
+    exit_warned: bool,
 ) -> CommandResult {
     /// pools of child processes to wait for
     struct ExecChainGuard {
-        processes: VecDeque<Child>,
+        processes: VecDeque<(Child, Multios)>,
+        /// Processes of `<(command)` and `>(command)`, see `ExecEnv::process_substitutions`.
+        substitutions: Vec<libc::pid_t>,
+        env: Rc<RefCell<ExecEnv>>,
+        command: String,
+        /// The processes are detached instead of waited for, see `detach`.
+        background: bool,
     }
 
     impl ExecChainGuard {
-        fn new() -> Self {
+        fn new(env: Rc<RefCell<ExecEnv>>, command: &str, background: bool) -> Self {
             Self {
                 processes: VecDeque::new(),
+                substitutions: Vec::new(),
+                env,
+                command: command.to_owned(),
+                background,
+            }
+        }
+
+        /// Take the process substitutions of the command which was just started. The
+        /// shell closes its ends of their pipes, since the command has its own copies.
+        fn add_substitutions(&mut self) {
+            let substitutions = std::mem::take(&mut self.env.borrow_mut().process_substitutions);
+            self.substitutions.extend(
+                substitutions
+                    .into_iter()
+                    .map(|substitution| substitution.pid),
+            );
+        }
+
+        /// Wait for the process substitutions. Their status is not used, like bash.
+        fn wait_substitutions(&mut self) {
+            for pid in self.substitutions.drain(..) {
+                while unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) } == -1
+                    && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+                {
+                }
+            }
+        }
+
+        /// Add the processes to the job table as a running job instead of waiting for them.
+        /// An interactive shell prints the job id and the pid of the last process, like
+        /// `[1] 1234`. Process substitutions are left running, like in a stopped job.
+        fn detach(&mut self) {
+            self.substitutions.clear();
+            if self.processes.is_empty() {
+                return;
+            }
+            // Dropping the children doesn't wait for them, and the threads copying their
+            // redirections keep running.
+            let pids: Vec<_> = self
+                .processes
+                .drain(..)
+                .map(|(child, _)| child.id() as libc::pid_t)
+                .collect();
+            let env = self.env.borrow();
+            let last_pid = pids[pids.len() - 1];
+            let command = std::mem::take(&mut self.command);
+            let id = env.jobs.add(pids[0], command, pids, JobState::Running);
+            if env.interactive {
+                eprintln!("[{}] {}", id, last_pid);
+            }
+        }
+
+        /// Wait for all the processes, returns the status of the last one, or `None` if
+        /// there are none. With job control, a stopped job is added to the job table, and
+        /// its process substitutions are left running.
+        fn wait(&mut self) -> Option<i32> {
+            if self.processes.is_empty() {
+                self.wait_substitutions();
+                return None;
+            }
+            let job_control = self.env.borrow().job_control;
+            let pids: Vec<_> = self
+                .processes
+                .iter()
+                .map(|(child, _)| child.id() as libc::pid_t)
+                .collect();
+            // With job control, the first process is the leader of the process group
+            let pgid = pids[0];
+            let result = job::wait_for_job(&pids, job_control.map(|_| pgid));
+            if let Some(job_control) = job_control
+                && let Err(e) = job_control.take_terminal()
+            {
+                eprintln!("mysh: can't take the terminal back: {}", e);
+            }
+            // The processes are waited for by `wait_for_job`, so dropping them does nothing.
+            let processes = self.processes.drain(..);
+            match result {
+                JobWait::Done {
+                    status,
+                    interrupted,
+                } => {
+                    for (_, multios) in processes {
+                        multios.wait();
+                    }
+                    self.wait_substitutions();
+                    Some(match status {
+                        // Like Ctrl-C at the prompt, even if the job catches `SIGINT`
+                        _ if interrupted => 128 + libc::SIGINT,
+                        Ok(status) => exit_status_code(ExitStatus::from_raw(status)),
+                        Err(_) => 1,
+                    })
+                }
+                // Redirections of the stopped job are still copied after it continues.
+                JobWait::Stopped { signal, pids } => {
+                    self.substitutions.clear();
+                    let command = std::mem::take(&mut self.command);
+                    let jobs = &self.env.borrow().jobs;
+                    let id = jobs.add(pgid, command, pids, JobState::Stopped);
+                    eprintln!("\n{}", jobs.status_line(id).unwrap_or_default());
+                    Some(128 + signal)
+                }
             }
         }
     }
 
     impl Drop for ExecChainGuard {
         fn drop(&mut self) {
-            for mut child in self.processes.drain(..) {
-                let _ = child.wait(); // TODO: handle error
+            if self.background {
+                self.detach();
+            } else {
+                self.wait();
             }
         }
     }
 
-    let mut pool = ExecChainGuard::new();
+    let mut pool = ExecChainGuard::new(Rc::clone(&env), command, context.background);
+    // Each pipeline is a job of its own, like `a` and `b` of `a && b`
+    context.pgid = None;
 
-    let mut first = match exec_chain.pop_front() {
-        Some(CommandDescriptor::Begin(exec)) => exec,
-        _ => return CommandResult::Normal, // empty or invalid
+    let Some(mut first) = pipeline.pop_front() else {
+        return CommandResult::Normal;
     };
 
     let mut pipe_in = None;
-    while let Some(CommandDescriptor::Pipe(exec)) = exec_chain.pop_front() {
+    while let Some(exec) = pipeline.pop_front() {
         let (reader, writer) = io::pipe().unwrap(); // TODO: handle error
-        let ret = execute_command(first, pipe_in, Some(writer), Rc::clone(&env), &mut context);
+        let ret = expand_and_execute(first, pipe_in, Some(writer), Rc::clone(&env), context);
+        pool.add_substitutions();
 
         first = exec;
         match ret {
-            ExecutionResult::Running(child) => pool.processes.push_back(child),
-            ExecutionResult::Exit => return CommandResult::Exit,
-            ExecutionResult::Error(msg) => {
+            ExecutionResult::Running(child, multios) => pool.processes.push_back((child, multios)),
+            ExecutionResult::Exit(status) => return exit_shell_or_warn(status, exit_warned, &env),
+            ExecutionResult::Error(msg, status) => {
                 eprintln!("{}", msg);
+                env.borrow_mut().last_status = status;
                 return CommandResult::Normal;
             }
             ExecutionResult::Normal => { /* continue */ }
@@ -70,58 +281,658 @@ pub fn execute_command_chain(
         pipe_in = Some(reader);
     }
 
-    let ret = execute_command(first, pipe_in, None, env, &mut context);
+    // The status of a pipeline is the status of its last command.
+    let ret = expand_and_execute(first, pipe_in, output, Rc::clone(&env), context);
+    pool.add_substitutions();
+    if context.background {
+        // Builtins still run in the shell before it returns, but `exit` doesn't exit it.
+        // Like bash, the status of starting a background job is 0.
+        match ret {
+            ExecutionResult::Running(child, multios) => pool.processes.push_back((child, multios)),
+            ExecutionResult::Error(msg, _) => eprintln!("{}", msg),
+            ExecutionResult::Exit(_) | ExecutionResult::Normal => {}
+        }
+        env.borrow_mut().last_status = 0;
+        return CommandResult::Normal;
+    }
     match ret {
-        ExecutionResult::Running(child) => {
-            pool.processes.push_back(child);
+        ExecutionResult::Running(child, multios) => {
+            pool.processes.push_back((child, multios));
+            let status = pool.wait().unwrap();
+            env.borrow_mut().last_status = status;
             CommandResult::Normal
         }
-        ExecutionResult::Exit => CommandResult::Exit,
-        ExecutionResult::Error(msg) => {
+        ExecutionResult::Exit(status) => exit_shell_or_warn(status, exit_warned, &env),
+        ExecutionResult::Error(msg, status) => {
             eprintln!("{}", msg);
+            env.borrow_mut().last_status = status;
             CommandResult::Normal
         }
+        // The status of a builtin is set when it returns
         ExecutionResult::Normal => CommandResult::Normal,
     }
 }
 
-pub fn execute_command(
+/// Exit the shell, unless there are jobs and the user hasn't been warned by the previous
+/// command, see `ExecEnv::confirm_exit`.
+fn exit_shell_or_warn(status: i32, exit_warned: bool, env: &Rc<RefCell<ExecEnv>>) -> CommandResult {
+    let mut env = env.borrow_mut();
+    env.exit_warned = exit_warned;
+    if env.confirm_exit() {
+        return CommandResult::Exit(status);
+    }
+    env.last_status = 1;
+    CommandResult::Normal
+}
+
+/// Continue the job `id` in the foreground and wait for it, like `fg %1`. Returns its
+/// status, or the error if there is no job control.
+///
+/// Like bash, the command of the job is printed first. If the job is stopped again, it is
+/// put back into the job table as the current job.
+pub fn foreground_job(id: usize, env: &ExecEnv) -> Result<i32, String> {
+    let Some(job_control) = env.job_control else {
+        return Err(String::from("no job control"));
+    };
+    let Some(mut job) = env.jobs.remove(id) else {
+        return Err(format!("%{}: no such job", id));
+    };
+    // Like the output of a builtin, it is written to the fd right away, before the job
+    // writes anything.
+    let line = format!("{}\n", job.command);
+    let _ = match env.pipe_out.as_ref() {
+        Some(mut pipe_out) => pipe_out.write_all(line.as_bytes()),
+        None => crate::builtin::write_to_fd(1, line.as_bytes()),
+    };
+
+    // Jobs are only in the table while they are not in the foreground, so they are not
+    // reaped by `Notifier` while the shell waits for them.
+    if let Err(e) = job_control.give_terminal(job.pgid) {
+        eprintln!("mysh: can't give the terminal to the job: {}", e);
+    }
+    unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+    let result = job::wait_for_job(&job.pids, Some(job.pgid));
+    if let Err(e) = job_control.take_terminal() {
+        eprintln!("mysh: can't take the terminal back: {}", e);
+    }
+    Ok(match result {
+        JobWait::Done {
+            status,
+            interrupted,
+        } => match status {
+            _ if interrupted => 128 + libc::SIGINT,
+            // The last process exited before the others, while it was in the background
+            Ok(_) if !job.pids.contains(&job.last_pid) => {
+                exit_status_code(ExitStatus::from_raw(job.last_status))
+            }
+            Ok(status) => exit_status_code(ExitStatus::from_raw(status)),
+            Err(_) => 1,
+        },
+        JobWait::Stopped { signal, pids } => {
+            job.pids = pids;
+            job.state = JobState::Stopped;
+            let id = job.id;
+            env.jobs.restore(job);
+            eprintln!("\n{}", env.jobs.status_line(id).unwrap_or_default());
+            128 + signal
+        }
+    })
+}
+
+/// Convert the status of a waited child into the status of the shell, as `$?` shows it.
+///
+/// A normal exit gives the exit code. Like bash, a child killed or stopped by signal `N`
+/// gives `128 + N`.
+pub fn exit_status_code(status: ExitStatus) -> i32 {
+    match (status.code(), status.signal(), status.stopped_signal()) {
+        (Some(code), _, _) => code,
+        (None, Some(signal), _) | (None, None, Some(signal)) => 128 + signal,
+        (None, None, None) => 1,
+    }
+}
+
+fn expand_and_execute(
+    data: ParseData,
+    pipe_in: Option<PipeReader>,
+    pipe_out: Option<PipeWriter>,
+    env: Rc<RefCell<ExecEnv>>,
+    context: &mut ExecContext,
+) -> ExecutionResult {
+    if data.words.first().and_then(Word::as_unquoted_text) == Some("[[") {
+        let mut env = env.borrow_mut();
+        return match conditional::evaluate(&data.words, &mut env) {
+            Ok(value) => {
+                env.last_status = i32::from(!value);
+                ExecutionResult::Normal
+            }
+            Err(msg) => ExecutionResult::Error(format!("mysh: {}", msg), 2),
+        };
+    }
+
+    // Like bash, a job spec as the command resumes the job, like `fg %1`. A quoted `'%1'`
+    // or a path like `./%1` still runs a command.
+    if let Some(spec) = data.words.first().and_then(Word::as_unquoted_text)
+        && spec.starts_with('%')
+    {
+        let status = {
+            let env = env.borrow();
+            match env.jobs.find(spec) {
+                Some(id) => foreground_job(id, &env),
+                None => Err(format!("{}: no such job", spec)),
+            }
+        };
+        return match status {
+            Ok(status) => {
+                env.borrow_mut().last_status = status;
+                ExecutionResult::Normal
+            }
+            Err(msg) => ExecutionResult::Error(format!("mysh: {}", msg), 1),
+        };
+    }
+
+    let expanded = expand::expand_command(data, &mut env.borrow_mut());
+    match expanded {
+        Ok(ExpandedCommand::Command(raw_cmd)) => {
+            execute_command(raw_cmd, pipe_in, pipe_out, env, context)
+        }
+        Ok(ExpandedCommand::Redirect(redirect)) => execute_redirect_only(&redirect, env),
+        Err(msg) => ExecutionResult::Error(format!("mysh: {}", msg), 1),
+    }
+}
+
+/// Handle a command with only redirections. Like zsh, `> file` creates or truncates the
+/// file. There is no command to read the input of `< file`, so it is an error, while a
+/// here-string or `<&N` is ignored.
+fn execute_redirect_only(redirect: &Redirect, env: Rc<RefCell<ExecEnv>>) -> ExecutionResult {
+    let file = redirect.input.iter().find_map(|input| match &input.source {
+        InputSource::File(filename) => Some(filename),
+        InputSource::Text(_) | InputSource::Fd(_) => None,
+    });
+    if let Some(filename) = file {
+        return ExecutionResult::Error(
+            format!("mysh: {}: no command to read the input", filename.display()),
+            1,
+        );
+    }
+
+    for output in &redirect.output {
+        if let Err(e) = output.open() {
+            return ExecutionResult::Error(
+                format!("mysh: {}: {}", output.filename.display(), e),
+                1,
+            );
+        }
+    }
+
+    let mut env = env.borrow_mut();
+    env.last_status = env.substitution_status.unwrap_or(0);
+    ExecutionResult::Normal
+}
+
+/// Handle `exit [n]`. Without `n`, the shell exits with the status of the last command.
+fn exit_shell(args: &[OsString], last_status: i32) -> ExecutionResult {
+    let arg = match args {
+        [] => return ExecutionResult::Exit(last_status),
+        [arg] => arg,
+        _ => return ExecutionResult::Error(String::from("exit: too many arguments"), 1),
+    };
+    match arg.to_str().and_then(|arg| arg.parse::<i64>().ok()) {
+        // Only the low 8 bits are kept by the system
+        Some(status) => ExecutionResult::Exit((status & 0xff) as i32),
+        None => {
+            // Like bash, the shell still exits
+            eprintln!("exit: {}: numeric argument required", arg.display());
+            ExecutionResult::Exit(2)
+        }
+    }
+}
+
+/// Handle `command [-i] [--] name [argument]...`.
+///
+/// Returns the command to run instead, or `None` if there is no command name. With `-i`,
+/// the command runs with an empty environment except a minimal `PATH`, while assignments
+/// before `command` are still passed to it.
+fn strip_command(mut raw_cmd: RawCommand) -> Result<Option<RawCommand>, String> {
+    let mut args = std::mem::take(&mut raw_cmd.arguments).into_iter();
+    let cmd = loop {
+        let Some(arg) = args.next() else {
+            return Ok(None);
+        };
+        match arg.to_str() {
+            Some("-i") => raw_cmd.clean_env = true,
+            Some("--") => match args.next() {
+                Some(cmd) => break cmd,
+                None => return Ok(None),
+            },
+            Some(opt) if opt.starts_with('-') && opt.len() > 1 => {
+                return Err(format!("command: {}: invalid option", opt));
+            }
+            _ => break arg,
+        }
+    };
+    raw_cmd.cmd = cmd;
+    raw_cmd.arguments = args.collect();
+    Ok(Some(raw_cmd))
+}
+
+/// Handle `builtin name [argument]...`, which only runs builtin commands.
+///
+/// Returns the builtin command to run instead, or `None` if there is no command name.
+fn strip_builtin(mut raw_cmd: RawCommand) -> Result<Option<RawCommand>, String> {
+    let mut args = std::mem::take(&mut raw_cmd.arguments).into_iter();
+    let Some(cmd) = args.next() else {
+        return Ok(None);
+    };
+    let is_builtin = cmd
+        .to_str()
+        .is_some_and(|name| crate::builtin::BUILTIN_COMMANDS.with(|map| map.contains_key(name)));
+    if !is_builtin {
+        return Err(format!("builtin: {}: not a shell builtin", cmd.display()));
+    }
+    raw_cmd.cmd = cmd;
+    raw_cmd.arguments = args.collect();
+    Ok(Some(raw_cmd))
+}
+
+/// Handle `timeout [--] duration command [argument]...`.
+///
+/// Returns the duration and the command to run.
+fn strip_timeout(mut raw_cmd: RawCommand) -> Result<(Duration, RawCommand), String> {
+    let mut args = std::mem::take(&mut raw_cmd.arguments)
+        .into_iter()
+        .peekable();
+    if args.next_if(|arg| arg == "--").is_none()
+        && let Some(opt) = args.peek().filter(|arg| arg.as_bytes().starts_with(b"-"))
+    {
+        return Err(format!("timeout: {}: invalid option", opt.display()));
+    }
+    let (Some(duration), Some(cmd)) = (args.next(), args.next()) else {
+        return Err(String::from(
+            "timeout: usage: timeout duration command [argument]...",
+        ));
+    };
+    let Some(duration) = crate::builtin::parse_duration(&duration) else {
+        return Err(format!(
+            "timeout: invalid time interval '{}'",
+            duration.display()
+        ));
+    };
+    raw_cmd.cmd = cmd;
+    raw_cmd.arguments = args.collect();
+    Ok((duration, raw_cmd))
+}
+
+/// Run `raw_cmd` of `timeout`, and wait for it until `duration` passes, see
+/// `builtin::wait_with_timeout`. Builtins run in the shell, so they have no limit.
+fn execute_with_timeout(
+    duration: Duration,
     raw_cmd: RawCommand,
     pipe_in: Option<PipeReader>,
     pipe_out: Option<PipeWriter>,
     env: Rc<RefCell<ExecEnv>>,
     context: &mut ExecContext,
+) -> ExecutionResult {
+    // Whether the command is a pipeline by itself, so the job is over when it exits.
+    let alone = context.pgid.is_none() && pipe_out.is_none();
+    match execute_command(raw_cmd, pipe_in, pipe_out, Rc::clone(&env), context) {
+        ExecutionResult::Running(mut child, multios) => {
+            let status = crate::builtin::wait_with_timeout(&mut child, duration);
+            multios.wait();
+            let mut env = env.borrow_mut();
+            if let Some(job_control) = env.job_control.filter(|_| alone)
+                && let Err(e) = job_control.take_terminal()
+            {
+                eprintln!("mysh: can't take the terminal back: {}", e);
+            }
+            env.last_status = status;
+            ExecutionResult::Normal
+        }
+        result => result,
+    }
+}
+
+/// Run `source file [argument]...` or `. file [argument]...`: the lines of the file run in
+/// the shell, so that variables and aliases set by them are kept. A line continues on the
+/// next one while it is incomplete, e.g. in quotes, and the file must not end in the middle
+/// of one. The arguments are the positional parameters while it runs, like bash.
+fn execute_source(
+    raw_cmd: RawCommand,
+    pipe_out: Option<PipeWriter>,
+    env: Rc<RefCell<ExecEnv>>,
+    context: &mut ExecContext,
+) -> ExecutionResult {
+    let name = raw_cmd.cmd.display().to_string();
+    let mut args = raw_cmd.arguments.into_iter();
+    let Some(path) = args.next() else {
+        return ExecutionResult::Error(format!("{}: filename argument required", name), 2);
+    };
+    let script = match std::fs::read(&path) {
+        Ok(script) => String::from_utf8_lossy(&script).into_owned(),
+        Err(e) => {
+            let msg = format!(
+                "{}: {}: {}",
+                name,
+                path.display(),
+                redirect::error_message(&e)
+            );
+            return ExecutionResult::Error(msg, 1);
+        }
+    };
+
+    // The lines write to fd 1, so a pipe is set there like the pipe of a builtin which
+    // needs it at fd 1, see `RedirectHandler::with_stdout`.
+    let stdout = pipe_out
+        .filter(|_| !raw_cmd.redirect.redirects_output(1))
+        .map(OwnedFd::from);
+    let redirects = match raw_cmd.redirect.open() {
+        Ok(redirects) => redirects,
+        Err(msg) => return ExecutionResult::Error(format!("mysh: {}", msg), 1),
+    };
+    let _handler = match RedirectHandler::with_stdout(redirects, stdout) {
+        Ok(handler) => handler,
+        Err(e) => {
+            let msg = format!("mysh: redirection error: {}", redirect::error_message(&e));
+            return ExecutionResult::Error(msg, 1);
+        }
+    };
+
+    let params: Vec<_> = args.collect();
+    let saved_params = (!params.is_empty())
+        .then(|| std::mem::replace(&mut env.borrow_mut().positional_params, params));
+    env.borrow_mut().last_status = 0;
+    let mut ret = ExecutionResult::Normal;
+    let mut command = String::new();
+    for line in script.lines() {
+        if command.is_empty() && (line.trim().is_empty() || line.trim_start().starts_with('#')) {
+            continue;
+        }
+        command.push_str(line);
+        if parse::is_incomplete(&command) {
+            command.push('\n');
+            continue;
+        }
+        let history = ExecContext::new(context.history);
+        let command = std::mem::take(&mut command);
+        if let CommandResult::Exit(status) =
+            crate::get_input_and_run(&command, Rc::clone(&env), history)
+        {
+            ret = ExecutionResult::Exit(status);
+            break;
+        }
+    }
+    if !command.is_empty() {
+        let msg = format!("{}: {}: unexpected end of file", name, path.display());
+        ret = ExecutionResult::Error(msg, 2);
+    }
+    // Output buffered by builtins must reach the redirected fd, see `execute_command`
+    let _ = io::stdout().flush();
+    if let Some(params) = saved_params {
+        env.borrow_mut().positional_params = params;
+    }
+    ret
+}
+
+/// Returns true if `raw_cmd` changes into a directory with `autocd`: it has no arguments,
+/// names a directory, and is not a command in `PATH`, which takes precedence.
+fn is_autocd(raw_cmd: &RawCommand, env: &ExecEnv) -> bool {
+    if !env.options.autocd || !raw_cmd.arguments.is_empty() {
+        return false;
+    }
+    let name = &raw_cmd.cmd;
+    if !Path::new(name).is_dir() {
+        return false;
+    }
+    name.as_bytes().contains(&b'/') || crate::builtin::get_executable_in_path(name, env).is_none()
+}
+
+/// The error message of a command which is not found in `PATH`, with the closest command
+/// name if `suggest` is set.
+fn command_not_found(name: &OsStr, env: &ExecEnv) -> String {
+    let suggestion = name
+        .to_str()
+        .filter(|_| env.options.suggest)
+        .and_then(|name| suggest::suggest_command(name, env));
+    match suggestion {
+        Some(suggestion) => format!(
+            "mysh: {}: command not found \u{2014} did you mean '{}'?",
+            name.display(),
+            suggestion
+        ),
+        None => format!("mysh: {}: command not found", name.display()),
+    }
+}
+
+/// Returns true if the shell should ask whether to correct `raw_cmd`: `correct` is set in an
+/// interactive shell, and the command is not found in `PATH`.
+fn should_correct(raw_cmd: &RawCommand, env: &ExecEnv) -> bool {
+    env.options.correct
+        && env.interactive
+        && !raw_cmd.cmd.as_bytes().contains(&b'/')
+        && crate::builtin::get_executable_in_path(&raw_cmd.cmd, env).is_none()
+}
+
+/// Ask whether to run the closest command instead of `raw_cmd`, like zsh: `y` runs it, `n`
+/// runs the command as it is, and `a` aborts the command line. Editing the command line, `e`
+/// in zsh, is not supported. Aliases are expanded when the line is parsed, so an alias is
+/// not offered as a correction.
+///
+/// Returns false if the command line is aborted.
+fn correct_command(raw_cmd: &mut RawCommand, env: &ExecEnv) -> bool {
+    let Some(correction) = raw_cmd
+        .cmd
+        .to_str()
+        .and_then(|name| suggest::suggest_command(name, env))
+        .filter(|correction| !env.aliases.contains_key(correction))
+    else {
+        return true;
+    };
+    eprint!(
+        "mysh: correct '{}' to '{}' [nya]? ",
+        raw_cmd.cmd.display(),
+        correction
+    );
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return true;
+    }
+    match answer.trim() {
+        "y" => {
+            raw_cmd.cmd = OsString::from(correction);
+            true
+        }
+        "a" => false,
+        _ => true,
+    }
+}
+
+pub fn execute_command(
+    mut raw_cmd: RawCommand,
+    pipe_in: Option<PipeReader>,
+    pipe_out: Option<PipeWriter>,
+    env: Rc<RefCell<ExecEnv>>,
+    context: &mut ExecContext,
 ) -> ExecutionResult {
     if raw_cmd.cmd == "exit" {
-        return ExecutionResult::Exit;
+        return exit_shell(&raw_cmd.arguments, env.borrow().last_status);
+    }
+
+    if raw_cmd.cmd == "builtin" {
+        return match strip_builtin(raw_cmd) {
+            Ok(Some(raw_cmd)) => execute_command(raw_cmd, pipe_in, pipe_out, env, context),
+            Ok(None) => {
+                env.borrow_mut().last_status = 0;
+                ExecutionResult::Normal
+            }
+            Err(msg) => ExecutionResult::Error(msg, 1),
+        };
     }
 
-    let f = crate::builtin::BUILTIN_COMMANDS.with(|map| map.get(raw_cmd.cmd.as_str()).copied());
+    if raw_cmd.cmd == "command" {
+        return match strip_command(raw_cmd) {
+            Ok(Some(raw_cmd)) => execute_command(raw_cmd, pipe_in, pipe_out, env, context),
+            Ok(None) => {
+                env.borrow_mut().last_status = 0;
+                ExecutionResult::Normal
+            }
+            Err(msg) => ExecutionResult::Error(msg, 2),
+        };
+    }
+
+    if raw_cmd.cmd == "timeout" {
+        return match strip_timeout(raw_cmd) {
+            Ok((duration, raw_cmd)) => {
+                execute_with_timeout(duration, raw_cmd, pipe_in, pipe_out, env, context)
+            }
+            Err(msg) => ExecutionResult::Error(msg, crate::builtin::TIMEOUT_FAILURE_STATUS),
+        };
+    }
+
+    if raw_cmd.cmd == "source" || raw_cmd.cmd == "." {
+        return execute_source(raw_cmd, pipe_out, env, context);
+    }
+
+    // Builtin names are all valid UTF-8
+    let builtin = |name: &OsStr| {
+        name.to_str()
+            .and_then(|name| crate::builtin::BUILTIN_COMMANDS.with(|map| map.get(name).copied()))
+    };
+    let mut f = builtin(&raw_cmd.cmd);
+    if f.is_none() && is_autocd(&raw_cmd, &env.borrow()) {
+        let dir = std::mem::replace(&mut raw_cmd.cmd, OsString::from("cd"));
+        raw_cmd.arguments.push(dir);
+        f = Some(crate::builtin::cd_command);
+    }
+    if f.is_none() && should_correct(&raw_cmd, &env.borrow()) {
+        if !correct_command(&mut raw_cmd, &env.borrow()) {
+            return ExecutionResult::Normal;
+        }
+        f = builtin(&raw_cmd.cmd);
+    }
+    // Builtins take any bytes, but a command which can't be executed gives 126 like bash.
+    if f.is_none()
+        && let Err(msg) = raw_cmd.check_nul()
+    {
+        return ExecutionResult::Error(format!("mysh: {}", msg), 126);
+    }
+    // Like external commands, redirections of builtins take precedence over pipes.
+    let stdout_redirected = raw_cmd.redirect.redirects_output(1);
+    let mut multios = match Multios::start(&mut raw_cmd.redirect) {
+        Ok(multios) => multios,
+        Err(msg) => return ExecutionResult::Error(format!("mysh: {}", msg), 1),
+    };
+
     if let Some(func) = f {
         // RedirectHandler scope
-        let _handler = RedirectHandler::new(&raw_cmd.redirect);
         {
+            // Builtins write to the pipe directly, unless a duplication like `2>&1` needs
+            // it at fd 1.
+            let pipe_out = pipe_out.filter(|_| !stdout_redirected);
+            let (stdout, pipe_out) = match pipe_out {
+                Some(pipe_out) if !raw_cmd.redirect.dups.is_empty() => {
+                    (Some(OwnedFd::from(pipe_out)), None)
+                }
+                pipe_out => (None, pipe_out),
+            };
+            let redirects = match raw_cmd.redirect.open() {
+                Ok(redirects) => redirects,
+                Err(msg) => return ExecutionResult::Error(format!("mysh: {}", msg), 1),
+            };
+            let redirection_error = |e: io::Error| {
+                ExecutionResult::Error(
+                    format!("mysh: redirection error: {}", redirect::error_message(&e)),
+                    1,
+                )
+            };
+            let mut handler = match RedirectHandler::with_stdout(redirects, stdout) {
+                Ok(handler) => handler,
+                Err(e) => return redirection_error(e),
+            };
+            for (fd, file) in std::mem::take(&mut multios.fds) {
+                if let Err(e) = handler.push_fd(fd, file) {
+                    return redirection_error(e);
+                }
+            }
             let mut e = env.borrow_mut();
             e.pipe_in = pipe_in;
             e.pipe_out = pipe_out;
 
-            func(raw_cmd.arguments, e, context);
+            let mut status = func(raw_cmd.arguments, e, context);
+            // Buffered output must reach the redirected fd before the handler restores it,
+            // otherwise it is flushed to the original fd later.
+            if let Err(e) = io::stdout().flush() {
+                status = crate::builtin::output_error_status(e);
+            }
 
             let mut e = env.borrow_mut();
             e.reset_pipes();
+            e.last_status = status;
         }
+        multios.wait();
         return ExecutionResult::Normal;
     }
 
+    // Files are opened by the shell, so that errors name the file
+    let redirects = match raw_cmd.redirect.open() {
+        Ok(redirects) => redirects,
+        Err(msg) => return ExecutionResult::Error(format!("mysh: {}", msg), 1),
+    };
+    let job_control = env.borrow().job_control;
+    let name = raw_cmd.cmd.clone();
     let mut builder = process::ChildBuilder::new(raw_cmd);
+    builder.redirects(redirects);
+    if let Some(job_control) = job_control {
+        let foreground = (!context.background).then_some(job_control);
+        builder.job(context.pgid.unwrap_or(0), foreground);
+    }
+    for (fd, file) in std::mem::take(&mut multios.fds) {
+        builder.fd(fd, file);
+    }
+    for (name, value) in env.borrow().exported_vars() {
+        builder.env(name.to_owned(), value.to_owned());
+    }
+    for name in &env.borrow().unset_env {
+        builder.env_remove(name.clone());
+    }
+    let pwd = env.borrow().pwd.clone();
+    if !pwd.as_os_str().is_empty() {
+        builder.pwd(pwd);
+    }
     if let Some(pipe_in) = pipe_in {
         builder.stdin(pipe_in);
+    } else if context.background && job_control.is_none() {
+        // Like bash, a background job without job control can't read the terminal
+        match File::open("/dev/null") {
+            Ok(null) => builder.stdin(null),
+            Err(e) => return ExecutionResult::Error(format!("mysh: /dev/null: {}", e), 1),
+        }
     }
     if let Some(pipe_out) = pipe_out {
         builder.stdout(pipe_out);
     }
+    // Like bash, 127 if the command is not found, and 126 if it can't be executed.
     builder
         .build()
-        .map(ExecutionResult::Running)
-        .unwrap_or_else(|e| ExecutionResult::Error(e.to_string()))
+        .map(|child| {
+            if let Some(job_control) = job_control {
+                // Also set in the child, whichever runs first
+                let pid = child.id() as libc::pid_t;
+                let pgid = *context.pgid.get_or_insert(pid);
+                unsafe { libc::setpgid(pid, pgid) };
+                if !context.background {
+                    let _ = job_control.give_terminal(pgid);
+                }
+            }
+            ExecutionResult::Running(child, multios)
+        })
+        .unwrap_or_else(|e| {
+            if e.kind() == io::ErrorKind::NotFound && !name.as_bytes().contains(&b'/') {
+                return ExecutionResult::Error(command_not_found(&name, &env.borrow()), 127);
+            }
+            let status = if e.kind() == io::ErrorKind::NotFound {
+                127
+            } else {
+                126
+            };
+            ExecutionResult::Error(e.to_string(), status)
+        })
 }
@@ -1,31 +1,70 @@
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+};
+
 use crate::{parse::ParseData, redirect::Redirect};
 
+/// A command after expansion.
+///
+/// The command name and arguments are `OsString`, since they are passed to the OS as is and
+/// may not be valid UTF-8.
 #[derive(Debug)]
 pub struct RawCommand {
-    pub cmd: String,
-    pub arguments: Vec<String>,
+    pub cmd: OsString,
+    pub arguments: Vec<OsString>,
     pub redirect: Redirect,
+    /// `name=value` before the command, which are only set for the command itself.
+    pub assignments: Vec<(String, OsString)>,
+    /// Run the command with an empty environment, see `command -i`.
+    pub clean_env: bool,
 }
 
 impl RawCommand {
-    pub fn new(cmd: String, arguments: Vec<String>, redirect: Redirect) -> Self {
+    pub fn new(cmd: OsString, arguments: Vec<OsString>, redirect: Redirect) -> Self {
         Self {
             cmd,
             arguments,
             redirect,
+            assignments: Vec::new(),
+            clean_env: false,
         }
     }
 
-    pub(crate) fn from_parse_data(data: ParseData) -> Option<Self> {
-        match data.first_arg {
-            Some(cmd) => Some(Self::new(cmd, data.arguments, data.redirect)),
-            None => None,
+    /// Returns an error if the command name, an argument or an assignment contains a NUL
+    /// byte, which can't be passed to `execve`.
+    pub fn check_nul(&self) -> Result<(), String> {
+        let has_nul = |s: &OsStr| s.as_bytes().contains(&0);
+        if has_nul(&self.cmd) {
+            return Err(String::from("command name contains a NUL byte"));
+        }
+        if self.arguments.iter().any(|arg| has_nul(arg)) {
+            return Err(format!(
+                "{}: argument contains a NUL byte",
+                self.cmd.display()
+            ));
+        }
+        if let Some((name, _)) = self.assignments.iter().find(|(_, value)| has_nul(value)) {
+            return Err(format!("{}: value contains a NUL byte", name));
         }
+        Ok(())
     }
 }
 
+/// Commands in a chain are kept unexpanded, since expansion depends on the state of the
+/// environment when the command is about to run.
 #[derive(Debug)]
 pub enum CommandDescriptor {
-    Begin(RawCommand),
-    Pipe(RawCommand),
+    Begin(ParseData),
+    /// A command after `|`, in the same pipeline as the one before it.
+    Pipe(ParseData),
+    /// The first command of a pipeline after `&&`, which runs if the one before succeeds.
+    And(ParseData),
+    /// The first command of a pipeline after `||`, which runs if the one before fails.
+    Or(ParseData),
+    /// The first command of a pipeline after `;`, which runs after the one before.
+    Next(ParseData),
+    /// `&` after the commands of a pipeline, which runs it in the background without waiting
+    /// for it.
+    Background,
 }
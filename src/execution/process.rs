@@ -1,19 +1,47 @@
 use std::{
-    fs::{File, OpenOptions},
     io::{self, Error},
     os::{
-        fd::{AsRawFd, IntoRawFd, OwnedFd},
+        fd::{AsRawFd, OwnedFd},
         unix::process::CommandExt,
     },
+    path::PathBuf,
     process::{Child, Command},
 };
 
-use crate::execution::data::RawCommand;
+use crate::{execution::data::RawCommand, job::JobControl, redirect::FdSource};
+
+/// `PATH` given to commands run with a clean environment.
+pub const CLEAN_ENV_PATH: &str = "/usr/bin:/bin";
+
+/// Signals whose dispositions are changed in the shell, which are restored to the default
+/// in children. Rust ignores `SIGPIPE` before `main`, so without this `yes | head -1` never
+/// ends. The signals of `job::JOB_CONTROL_SIGNALS` are ignored with job control.
+const RESET_SIGNALS: &[libc::c_int] = &[libc::SIGPIPE, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// Restore the default dispositions of `RESET_SIGNALS`, called in the child before exec.
+fn reset_signals() -> io::Result<()> {
+    for &signal in RESET_SIGNALS {
+        if unsafe { libc::signal(signal, libc::SIG_DFL) } == libc::SIG_ERR {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
+}
 
 pub struct ChildBuilder {
     commnad: RawCommand,
     stdout: Option<OwnedFd>,
     stdin: Option<OwnedFd>,
+    pwd: Option<PathBuf>,
+    /// Exported shell variables, see `ExecEnv::exported_vars`.
+    envs: Vec<(String, String)>,
+    /// Variables of the environment removed by `unset`.
+    removed_envs: Vec<String>,
+    /// Redirections with their files opened, see `Redirect::open`.
+    redirects: Vec<(i32, FdSource)>,
+    /// fds which are set after the redirections, see `Multios`.
+    fds: Vec<(i32, OwnedFd)>,
+    job: Option<(libc::pid_t, Option<JobControl>)>,
 }
 
 impl ChildBuilder {
@@ -22,9 +50,47 @@ impl ChildBuilder {
             commnad: command,
             stdout: None,
             stdin: None,
+            pwd: None,
+            envs: Vec::new(),
+            removed_envs: Vec::new(),
+            redirects: Vec::new(),
+            fds: Vec::new(),
+            job: None,
         }
     }
 
+    /// Set `PWD` of the child to the logical working directory of the shell.
+    pub fn pwd(&mut self, pwd: PathBuf) {
+        self.pwd = Some(pwd);
+    }
+
+    /// Set a variable in the environment of the child, unless it runs with a clean
+    /// environment.
+    pub fn env(&mut self, name: String, value: String) {
+        self.envs.push((name, value));
+    }
+
+    /// Remove a variable from the environment of the child.
+    pub fn env_remove(&mut self, name: String) {
+        self.removed_envs.push(name);
+    }
+
+    /// Set the redirections of the child, opened by `Redirect::open`.
+    pub fn redirects(&mut self, redirects: Vec<(i32, FdSource)>) {
+        self.redirects = redirects;
+    }
+
+    /// Set `fd` of the child to `file`, replacing its redirections.
+    pub fn fd<T: Into<OwnedFd>>(&mut self, fd: i32, file: T) {
+        self.fds.push((fd, file.into()));
+    }
+
+    /// Run the child in the process group `pgid`, or in a new process group if `pgid` is 0.
+    /// With `control`, the group is put in the foreground of the terminal.
+    pub fn job(&mut self, pgid: libc::pid_t, control: Option<JobControl>) {
+        self.job = Some((pgid, control));
+    }
+
     pub fn stdout<T: Into<OwnedFd>>(&mut self, fd: T) {
         self.stdout = Some(fd.into());
     }
@@ -36,38 +102,58 @@ impl ChildBuilder {
     pub fn build(self) -> io::Result<Child> {
         let mut cmd = Command::new(&self.commnad.cmd);
         cmd.args(&self.commnad.arguments);
+        if self.commnad.clean_env {
+            cmd.env_clear();
+            cmd.env("PATH", CLEAN_ENV_PATH);
+        } else {
+            for name in &self.removed_envs {
+                cmd.env_remove(name);
+            }
+            cmd.envs(self.envs);
+            if let Some(pwd) = &self.pwd {
+                cmd.env("PWD", pwd);
+            }
+        }
+        // Assignments before the command still apply on top of a clean environment.
+        cmd.envs(self.commnad.assignments.iter().map(|(k, v)| (k, v)));
+        // Closed in the shell when `cmd` is dropped after spawning
+        let redirects = self.redirects;
+        let fds = self.fds;
+        if let Some((pgid, control)) = self.job {
+            cmd.process_group(pgid);
+            // The shell gives the terminal after spawning too, but the child may need it
+            // before the shell runs again. It still ignores `SIGTTOU` here.
+            if let Some(control) = control {
+                unsafe {
+                    cmd.pre_exec(move || control.give_terminal(libc::getpgrp()));
+                }
+            }
+        }
         unsafe {
+            cmd.pre_exec(reset_signals);
             cmd.pre_exec(move || {
-                for input in &self.commnad.redirect.input {
-                    let f = File::open(&input.filename)?;
-                    if f.as_raw_fd() == input.fd {
-                        // stop closing the file when f goes out of scope
-                        let _ = f.into_raw_fd();
-                    } else {
-                        let fd = f.as_raw_fd();
-                        if libc::dup2(fd, input.fd) == -1 {
-                            return Err(Error::last_os_error());
-                        }
-                    } // close f when it goes out of scope
-                }
-                for output in &self.commnad.redirect.output {
-                    let f = if output.append {
-                        OpenOptions::new()
-                            .create(true)
-                            .append(true)
-                            .open(&output.filename)?
+                // `dup2` does nothing if both fds are the same, which would keep it
+                // close-on-exec, like `3>&3`
+                let set_fd = |from: i32, fd: i32| {
+                    let ret = if from == fd {
+                        libc::fcntl(fd, libc::F_SETFD, 0)
                     } else {
-                        File::create(&output.filename)?
+                        libc::dup2(from, fd)
                     };
-                    if f.as_raw_fd() == output.fd {
-                        // stop closing the file when f goes out of scope
-                        let _ = f.into_raw_fd();
-                    } else {
-                        let fd = f.as_raw_fd();
-                        if libc::dup2(fd, output.fd) == -1 {
-                            return Err(Error::last_os_error());
-                        }
-                    } // close f when it goes out of scope
+                    if ret == -1 {
+                        return Err(Error::last_os_error());
+                    }
+                    Ok(())
+                };
+                for (fd, source) in &redirects {
+                    let from = match source {
+                        FdSource::File(file) => file.as_raw_fd(),
+                        FdSource::Dup(target) => *target,
+                    };
+                    set_fd(from, *fd)?;
+                }
+                for (fd, file) in &fds {
+                    set_fd(file.as_raw_fd(), *fd)?;
                 }
                 Ok(())
             });
@@ -1,16 +1,22 @@
 use std::process::Child;
 
+use crate::redirect::Multios;
+
 // TODO: improve
 #[derive(Debug)]
 pub enum ExecutionResult {
-    Exit,
+    /// The shell exits with the status.
+    Exit(i32),
     Normal,
-    Running(Child),
-    Error(String),
+    /// The child is running, and `Multios` copies its redirections until it exits.
+    Running(Child, Multios),
+    /// An error message, and the exit status of the command.
+    Error(String, i32),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum CommandResult {
-    Exit,
+    /// The shell exits with the status.
+    Exit(i32),
     Normal,
 }
@@ -0,0 +1,649 @@
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, OsStr, OsString},
+    io::{self, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::ffi::{OsStrExt, OsStringExt},
+    },
+    path::PathBuf,
+    rc::Rc,
+};
+
+use libc::pid_t;
+
+use rustyline::history::FileHistory;
+
+use crate::{
+    arith,
+    env::{ExecContext, ExecEnv},
+    execution::{data::RawCommand, result::CommandResult},
+    glob,
+    job::JobTable,
+    parse::{Assignment, ParamOperator, ParseData, Word, WordPart},
+    redirect::{InputRedirect, OutputRedirect, Redirect},
+};
+
+/// Characters used to split the result of unquoted expansions when `IFS` is unset.
+const DEFAULT_IFS: &str = " \t\n";
+
+fn get_ifs(env: &ExecEnv) -> &str {
+    env.get_var("IFS").unwrap_or(DEFAULT_IFS)
+}
+
+/// The value of a parameter.
+enum ParamValue<'a> {
+    Single(OsString),
+    /// `$@` and `$*` expand to one value per positional parameter.
+    Multiple(&'a [OsString]),
+}
+
+fn lookup_param<'a>(name: &str, env: &'a ExecEnv) -> ParamValue<'a> {
+    match name {
+        "@" | "*" => ParamValue::Multiple(&env.positional_params),
+        "#" => ParamValue::Single(env.positional_params.len().to_string().into()),
+        "0" => ParamValue::Single(env.shell_name.clone()),
+        "$" => ParamValue::Single(std::process::id().to_string().into()),
+        "-" => ParamValue::Single(env.flags().into()),
+        "?" => ParamValue::Single(env.last_status().to_string().into()),
+        "PPID" => ParamValue::Single(unsafe { libc::getppid() }.to_string().into()),
+        // The logical working directory is kept by the shell, see `cd`.
+        "PWD" if !env.pwd.as_os_str().is_empty() => ParamValue::Single(env.pwd.clone().into()),
+        _ => match name.parse::<usize>() {
+            Ok(n) => ParamValue::Single(
+                env.positional_params
+                    .get(n - 1)
+                    .cloned()
+                    .unwrap_or_default(),
+            ),
+            // A shell variable, then the environment. Unset variables are empty.
+            Err(_) => ParamValue::Single(env.get_var_or_env(name).unwrap_or_default()),
+        },
+    }
+}
+
+/// Fields being built while expanding a word.
+///
+/// Fields are built as bytes, since parameters may contain anything but NUL.
+struct FieldBuilder {
+    fields: Vec<OsString>,
+    current: Vec<u8>,
+    /// Whether `current` should be kept even if it is empty, e.g. `""`.
+    keep: bool,
+    /// Whether fields are expanded as patterns, see `glob`.
+    glob: bool,
+    /// `current` as a pattern, where quoted characters are escaped.
+    pattern: Vec<u8>,
+    /// Whether `pattern` has an unquoted wildcard.
+    has_wildcard: bool,
+    /// Whether the last field was ended by `IFS` whitespace, which takes the next
+    /// non-whitespace delimiter with it, see `push_split`.
+    after_whitespace: bool,
+}
+
+impl FieldBuilder {
+    fn new(glob: bool) -> Self {
+        Self {
+            fields: Vec::new(),
+            current: Vec::new(),
+            keep: false,
+            glob,
+            pattern: Vec::new(),
+            has_wildcard: false,
+            after_whitespace: false,
+        }
+    }
+
+    fn push_unquoted(&mut self, s: &OsStr) {
+        self.push_unquoted_bytes(s.as_bytes());
+    }
+
+    fn push_unquoted_bytes(&mut self, s: &[u8]) {
+        if s.is_empty() {
+            return;
+        }
+        self.after_whitespace = false;
+        self.current.extend_from_slice(s);
+        if self.glob {
+            self.pattern.extend_from_slice(s);
+            self.has_wildcard |= glob::has_wildcard(s);
+        }
+    }
+
+    fn push_quoted(&mut self, s: &OsStr) {
+        self.after_whitespace = false;
+        self.current.extend_from_slice(s.as_bytes());
+        if self.glob {
+            glob::escape_into(&mut self.pattern, s.as_bytes());
+        }
+        self.keep = true;
+    }
+
+    fn finish_field(&mut self) {
+        let pattern = std::mem::take(&mut self.pattern);
+        if std::mem::take(&mut self.has_wildcard) {
+            // Like bash, a pattern which matches nothing is kept as it is.
+            let paths = glob::expand(&pattern);
+            if !paths.is_empty() {
+                self.fields.extend(paths);
+                self.current.clear();
+                self.keep = false;
+                return;
+            }
+        }
+        if !self.current.is_empty() || self.keep {
+            let field = std::mem::take(&mut self.current);
+            self.fields.push(OsString::from_vec(field));
+        }
+        self.keep = false;
+    }
+
+    /// Push the result of an unquoted expansion, splitting it into fields with `IFS`.
+    ///
+    /// A sequence of whitespace in `IFS` is one delimiter, while every other character in
+    /// `IFS` delimits a field by itself, so `a::b` is split into `a`, an empty field and `b`
+    /// with `IFS=:`. Like POSIX, whitespace around one of the others is part of the same
+    /// delimiter, so `a : b` is split into `a` and `b` with `IFS=" :"`.
+    ///
+    /// Bytes that are not valid UTF-8 are never delimiters.
+    fn push_split(&mut self, s: &OsStr, ifs: &str) {
+        for chunk in s.as_bytes().utf8_chunks() {
+            for c in chunk.valid().chars() {
+                if !ifs.contains(c) {
+                    let mut buf = [0; 4];
+                    self.push_unquoted_bytes(c.encode_utf8(&mut buf).as_bytes());
+                } else if c.is_whitespace() {
+                    if !self.current.is_empty() || self.keep {
+                        self.finish_field();
+                        self.after_whitespace = true;
+                    }
+                } else if !std::mem::take(&mut self.after_whitespace) {
+                    self.keep = true;
+                    self.finish_field();
+                }
+            }
+            self.push_unquoted_bytes(chunk.invalid());
+        }
+    }
+
+    fn finish(mut self) -> Vec<OsString> {
+        self.finish_field();
+        self.fields
+    }
+}
+
+/// Expand a word, `split` is false when the word should be treated as if it were quoted.
+/// Fields are expanded as patterns only if they are split.
+fn expand_parts(word: &Word, env: &mut ExecEnv, split: bool) -> Result<Vec<OsString>, String> {
+    let mut builder = FieldBuilder::new(split);
+    push_parts(&mut builder, &word.parts, env, split, false)?;
+    Ok(builder.finish())
+}
+
+/// Push the expansion of `parts`. `split_text` is true for the word of `${name:-word}`, whose
+/// unquoted text is split like the result of an expansion.
+///
+/// Returns the error message if an arithmetic expansion fails.
+fn push_parts(
+    builder: &mut FieldBuilder,
+    parts: &[WordPart],
+    env: &mut ExecEnv,
+    split: bool,
+    split_text: bool,
+) -> Result<(), String> {
+    let push_text = |builder: &mut FieldBuilder, value: &str, quoted: bool, env: &ExecEnv| {
+        if quoted || !split {
+            builder.push_quoted(OsStr::new(value));
+        } else if split_text {
+            builder.push_split(OsStr::new(value), get_ifs(env));
+        } else {
+            builder.push_unquoted(OsStr::new(value));
+        }
+    };
+
+    let mut parts = parts;
+    if let Some((home, rest)) = expand_tilde(parts, env) {
+        // The home directory is not split
+        builder.push_quoted(&home);
+        push_text(builder, rest, false, env);
+        parts = &parts[1..];
+    }
+
+    for part in parts {
+        match part {
+            WordPart::Text { value, quoted } => push_text(builder, value, *quoted, env),
+            WordPart::Param { name, quoted } => push_param(builder, name, env, *quoted || !split),
+            WordPart::ParamOp {
+                name,
+                op,
+                word,
+                quoted,
+            } => {
+                let quoted = *quoted || !split;
+                if quoted {
+                    // "${name:+word}" is still one field if it expands to nothing
+                    builder.push_quoted(OsStr::new(""));
+                }
+                let is_null = match lookup_param(name, env) {
+                    ParamValue::Single(value) => value.is_empty(),
+                    ParamValue::Multiple(values) => values.is_empty(),
+                };
+                match op {
+                    ParamOperator::UseDefault if is_null => {
+                        push_parts(builder, &word.parts, env, split, true)?
+                    }
+                    ParamOperator::AssignDefault if is_null => {
+                        let value = expand_value(word, env)?;
+                        env.set_var(name.clone(), value.to_string_lossy().into_owned());
+                        push_param(builder, name, env, quoted);
+                    }
+                    ParamOperator::UseAlternative if !is_null => {
+                        push_parts(builder, &word.parts, env, split, true)?
+                    }
+                    ParamOperator::UseAlternative => {}
+                    _ => push_param(builder, name, env, quoted),
+                }
+            }
+            WordPart::Command { command, quoted } => {
+                let output = substitute_command(command, env);
+                if *quoted || !split {
+                    builder.push_quoted(&output);
+                } else {
+                    builder.push_split(&output, get_ifs(env));
+                }
+            }
+            WordPart::Arith { expr, quoted } => {
+                let value = OsString::from(evaluate_arith(expr, env)?.to_string());
+                if *quoted || !split {
+                    builder.push_quoted(&value);
+                } else {
+                    builder.push_split(&value, get_ifs(env));
+                }
+            }
+            // The path is not split, like bash
+            WordPart::Process { command, output } => {
+                builder.push_quoted(&substitute_process(command, *output, env)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Expand `~` or `~user` at the start of a word, which ends at the first `/`. The tilde
+/// must not be quoted, and nothing in the prefix may be quoted or expanded, so `~"user"` and
+/// `~$USER` are kept as they are.
+///
+/// Returns the home directory and the rest of the first part. `~` is `$HOME`, or the home
+/// directory found when the shell started if it is unset, while an unknown user is kept as
+/// it is.
+fn expand_tilde<'a>(parts: &'a [WordPart], env: &ExecEnv) -> Option<(OsString, &'a str)> {
+    let [
+        WordPart::Text {
+            value,
+            quoted: false,
+        },
+        rest @ ..,
+    ] = parts
+    else {
+        return None;
+    };
+    let prefix = value.strip_prefix('~')?;
+    let (user, rest) = match prefix.find('/') {
+        Some(i) => prefix.split_at(i),
+        None if rest.is_empty() => (prefix, ""),
+        None => return None,
+    };
+
+    if !user.is_empty() {
+        return user_home(user).map(|home| (home, rest));
+    }
+    let home = match lookup_param("HOME", env) {
+        ParamValue::Single(home) if !home.is_empty() => home,
+        _ => env.base_dirs.home_dir().as_os_str().to_owned(),
+    };
+    Some((home, rest))
+}
+
+/// Returns the home directory of `user` from the password database.
+fn user_home(user: &str) -> Option<OsString> {
+    let user = CString::new(user).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0; 1024];
+    loop {
+        let ret = unsafe {
+            libc::getpwnam_r(
+                user.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if ret != libc::ERANGE {
+            break;
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+    if result.is_null() {
+        return None;
+    }
+    let home = unsafe { CStr::from_ptr(passwd.pw_dir) };
+    Some(OsStr::from_bytes(home.to_bytes()).to_owned())
+}
+
+/// Push the value of a parameter, splitting it into fields unless it is quoted.
+fn push_param(builder: &mut FieldBuilder, name: &str, env: &ExecEnv, quoted: bool) {
+    let ifs = get_ifs(env);
+    match (lookup_param(name, env), quoted) {
+        (ParamValue::Single(value), true) => builder.push_quoted(&value),
+        (ParamValue::Single(value), false) => builder.push_split(&value, ifs),
+        (ParamValue::Multiple(values), true) if name == "@" => {
+            // "$@" keeps the boundaries of the positional parameters
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    builder.finish_field();
+                }
+                builder.push_quoted(value);
+            }
+        }
+        (ParamValue::Multiple(values), true) => {
+            // "$*" joins the positional parameters with the first character of IFS,
+            // or nothing if IFS is empty
+            let sep = ifs.chars().next().map(String::from).unwrap_or_default();
+            builder.push_quoted(&values.join(OsStr::new(&sep)));
+        }
+        (ParamValue::Multiple(values), false) => {
+            for (i, value) in values.iter().enumerate() {
+                if i != 0 {
+                    builder.finish_field();
+                }
+                builder.push_split(value, ifs);
+            }
+        }
+    }
+}
+
+/// Trim the captured output of a command substitution before it is spliced into a word.
+///
+/// Like bash, all trailing newlines are removed, while other trailing whitespace and the
+/// newlines inside the output are kept. Output with only newlines becomes empty.
+pub fn trim_command_output(output: &[u8]) -> &[u8] {
+    let len = output
+        .iter()
+        .rposition(|&b| b != b'\n')
+        .map_or(0, |i| i + 1);
+    &output[..len]
+}
+
+/// Run the command of `$(command)` and return its output, see `trim_command_output`.
+///
+/// Like a subshell, changes to variables, options and the working directory are discarded
+/// when it finishes, while `$?` becomes its status.
+fn substitute_command(command: &str, env: &mut ExecEnv) -> OsString {
+    // The command runs with the shared environment like any command line, so the
+    // environment is moved out of the borrow of the caller while it runs.
+    let placeholder = ExecEnv::new(env.base_dirs.clone());
+    let shared = Rc::new(RefCell::new(std::mem::replace(env, placeholder)));
+    let snapshot = shared.borrow().snapshot();
+
+    let mut history = FileHistory::new();
+    let context = ExecContext::new(&mut history);
+    let (output, status) = crate::get_input_and_capture(command, Rc::clone(&shared), context);
+
+    *env = Rc::into_inner(shared)
+        .expect("the environment is not kept by the command")
+        .into_inner();
+    env.restore(snapshot);
+    env.last_status = status;
+    env.substitution_status = Some(status);
+    OsStr::from_bytes(trim_command_output(&output)).to_owned()
+}
+
+/// A process of `<(command)` or `>(command)`, see `substitute_process`.
+#[derive(Debug)]
+pub struct ProcessSubstitution {
+    pub pid: pid_t,
+    /// The end of the pipe which the command opens as `/dev/fd/N`. It is closed once the
+    /// command starts, so that the process sees the end of its input.
+    pub fd: OwnedFd,
+}
+
+/// Start the command of `<(command)` in a child of the shell with its stdout connected to
+/// a pipe, or the command of `>(command)` with its stdin connected to it, and return the
+/// path of the other end of the pipe, like `/dev/fd/63`.
+///
+/// The child is a copy of the shell, so the command sees its variables like a subshell.
+/// The process is added to `ExecEnv::process_substitutions`.
+fn substitute_process(command: &str, output: bool, env: &mut ExecEnv) -> Result<OsString, String> {
+    let error = |e: io::Error| format!("can't start process substitution: {}", e);
+    let (reader, writer) = io::pipe().map_err(error)?;
+    let (fd, child_fd, target): (OwnedFd, OwnedFd, _) = if output {
+        (writer.into(), reader.into(), libc::STDIN_FILENO)
+    } else {
+        (reader.into(), writer.into(), libc::STDOUT_FILENO)
+    };
+    // The command needs the fd, which is closed on exec by default
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, 0) } == -1 {
+        return Err(error(io::Error::last_os_error()));
+    }
+
+    // Output waiting in the buffer would be written twice
+    let _ = io::stdout().flush();
+    let pid = unsafe { libc::fork() };
+    if pid == -1 {
+        return Err(error(io::Error::last_os_error()));
+    }
+    if pid == 0 {
+        drop(fd);
+        if unsafe { libc::dup2(child_fd.as_raw_fd(), target) } == -1 {
+            unsafe { libc::_exit(1) };
+        }
+        drop(child_fd);
+        let status = run_in_child(command, env);
+        let _ = io::stdout().flush();
+        unsafe { libc::_exit(status) };
+    }
+
+    let path = format!("/dev/fd/{}", fd.as_raw_fd());
+    env.process_substitutions
+        .push(ProcessSubstitution { pid, fd });
+    Ok(OsString::from(path))
+}
+
+/// Run a command line in a forked child of the shell and return its status. The child has
+/// no job control and no jobs of the shell.
+fn run_in_child(command: &str, env: &mut ExecEnv) -> i32 {
+    let placeholder = ExecEnv::new(env.base_dirs.clone());
+    let mut child_env = std::mem::replace(env, placeholder);
+    child_env.job_control = None;
+    child_env.jobs = JobTable::new();
+    child_env.pipe_in = None;
+    child_env.pipe_out = None;
+    child_env.process_substitutions.clear();
+    let shared = Rc::new(RefCell::new(child_env));
+
+    let mut history = FileHistory::new();
+    let context = ExecContext::new(&mut history);
+    match crate::get_input_and_run(command, Rc::clone(&shared), context) {
+        CommandResult::Exit(status) => status,
+        CommandResult::Normal => shared.borrow().last_status,
+    }
+}
+
+/// Expand and evaluate the expression of `$((expr))`, see `arith`.
+fn evaluate_arith(expr: &Word, env: &mut ExecEnv) -> Result<i64, String> {
+    let expr = expand_value(expr, env)?;
+    let lookup = |name: &str| {
+        env.get_var_or_env(name)
+            .map(|value| value.to_string_lossy().into_owned())
+    };
+    arith::evaluate(&expr.to_string_lossy(), &lookup)
+}
+
+/// Expand a word into zero or more fields. Returns the error message if an expansion fails,
+/// e.g. division by 0 in `$((...))`.
+pub fn expand_word(word: &Word, env: &mut ExecEnv) -> Result<Vec<OsString>, String> {
+    expand_parts(word, env, true)
+}
+
+/// Expand a word into exactly one value without field splitting, e.g. the value of an
+/// assignment.
+pub fn expand_value(word: &Word, env: &mut ExecEnv) -> Result<OsString, String> {
+    Ok(expand_parts(word, env, false)?.join(OsStr::new(" ")))
+}
+
+/// Expand a word into a pattern without splitting it. Quoted characters are escaped with
+/// `escape`, so that they only match themselves, while unquoted ones keep their meaning,
+/// e.g. on the right of `==` in `[[ ... ]]`.
+pub fn expand_pattern(
+    word: &Word,
+    env: &mut ExecEnv,
+    escape: fn(&mut Vec<u8>, &[u8]),
+) -> Result<Vec<u8>, String> {
+    let mut pattern = Vec::new();
+    for part in &word.parts {
+        let (value, quoted) = match part {
+            WordPart::Text { value, quoted } => (OsString::from(value), *quoted),
+            WordPart::Param { quoted, .. }
+            | WordPart::ParamOp { quoted, .. }
+            | WordPart::Command { quoted, .. }
+            | WordPart::Arith { quoted, .. } => {
+                let word = Word {
+                    parts: vec![part.clone()],
+                };
+                (expand_value(&word, env)?, *quoted)
+            }
+            WordPart::Process { .. } => {
+                let word = Word {
+                    parts: vec![part.clone()],
+                };
+                (expand_value(&word, env)?, true)
+            }
+        };
+        if quoted {
+            escape(&mut pattern, value.as_bytes());
+        } else {
+            pattern.extend_from_slice(value.as_bytes());
+        }
+    }
+    Ok(pattern)
+}
+
+/// Expand the value of an assignment. For `name+=value`, the value is appended to the
+/// current value of the shell variable, or the environment variable if there is no such
+/// shell variable.
+pub fn expand_assignment(assignment: &Assignment, env: &mut ExecEnv) -> Result<OsString, String> {
+    let value = expand_value(&assignment.value, env)?;
+    if !assignment.append {
+        return Ok(value);
+    }
+
+    let mut current = env.get_var_or_env(&assignment.name).unwrap_or_default();
+    current.push(value);
+    Ok(current)
+}
+
+/// A command after expansion.
+#[derive(Debug)]
+pub enum ExpandedCommand {
+    Command(RawCommand),
+    /// There is no command name, e.g. `> file`, `name=value` or a word which expands to
+    /// nothing.
+    Redirect(Redirect),
+}
+
+fn expand_redirect(data: &ParseData, env: &mut ExecEnv) -> Result<Redirect, String> {
+    let mut redirect = Redirect::new();
+    redirect.set_multios(env.options.multios);
+    for rword in &data.redirect {
+        let rfrag = &rword.fragment;
+        if rfrag.here_string || rfrag.heredoc {
+            // Like bash, the text is not split. The body of a here-document already ends
+            // with a newline.
+            let mut text = expand_value(&rword.target, env)?.into_vec();
+            if rfrag.here_string {
+                text.push(b'\n');
+            }
+            let mut input_redirect = InputRedirect::text(text);
+            input_redirect.set_fd(rfrag.fd);
+            redirect.push_input(input_redirect);
+            continue;
+        }
+
+        let mut targets = expand_word(&rword.target, env)?;
+        if targets.len() != 1 {
+            return Err(format!("{}: ambiguous redirect", rword.target.to_text()));
+        }
+        let target = targets.pop().unwrap();
+
+        if rfrag.dup {
+            match target.to_str().and_then(|fd| fd.parse().ok()) {
+                Some(fd) if rfrag.is_input => {
+                    let mut input_redirect = InputRedirect::dup(fd);
+                    input_redirect.set_fd(rfrag.fd);
+                    redirect.push_input(input_redirect);
+                    continue;
+                }
+                Some(fd) => {
+                    redirect.push_dup(rfrag.fd, fd);
+                    continue;
+                }
+                // `>&file` is `&>file`
+                None if !rfrag.is_input && rfrag.fd == 1 => {}
+                None => return Err(format!("{}: ambiguous redirect", target.display())),
+            }
+        }
+        let filename = PathBuf::from(target);
+        if rfrag.is_input {
+            let mut input_redirect = InputRedirect::new(filename);
+            input_redirect.set_fd(rfrag.fd);
+            redirect.push_input(input_redirect);
+        } else {
+            let mut output_redirect = OutputRedirect::new(filename);
+            output_redirect.set_append(rfrag.append);
+            output_redirect.set_fd(rfrag.fd);
+            redirect.push_output(output_redirect);
+            if rfrag.both || rfrag.dup {
+                redirect.push_dup(2, 1);
+            }
+        }
+    }
+    Ok(redirect)
+}
+
+/// Expand a parsed command.
+pub fn expand_command(data: ParseData, env: &mut ExecEnv) -> Result<ExpandedCommand, String> {
+    env.substitution_status = None;
+    let mut fields = Vec::new();
+    for word in &data.words {
+        fields.extend(expand_word(word, env)?);
+    }
+    let mut fields = fields.into_iter();
+
+    let redirect = expand_redirect(&data, env)?;
+    let cmd = match fields.next() {
+        Some(cmd) => cmd,
+        None => {
+            // Without a command, assignments set shell variables one by one, so later ones
+            // see the earlier ones, e.g. `A=1 B=$A`.
+            for assignment in &data.assignments {
+                let value = expand_assignment(assignment, env)?;
+                env.set_var(
+                    assignment.name.clone(),
+                    value.to_string_lossy().into_owned(),
+                );
+            }
+            return Ok(ExpandedCommand::Redirect(redirect));
+        }
+    };
+
+    let mut raw_cmd = RawCommand::new(cmd, fields.collect(), redirect);
+    raw_cmd.assignments = data
+        .assignments
+        .iter()
+        .map(|assignment| Ok((assignment.name.clone(), expand_assignment(assignment, env)?)))
+        .collect::<Result<_, String>>()?;
+
+    Ok(ExpandedCommand::Command(raw_cmd))
+}
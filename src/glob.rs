@@ -0,0 +1,280 @@
+//! Pathname expansion.
+//!
+//! Patterns are bytes where a backslash escapes the next byte, so that quoted characters can
+//! be kept literal after they are mixed with unquoted ones in a field.
+
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::Path,
+};
+
+/// Returns true if `b` has a special meaning in a pattern, and must be escaped to match
+/// itself.
+pub fn is_special(b: u8) -> bool {
+    matches!(b, b'*' | b'?' | b'[' | b']' | b'\\')
+}
+
+/// Push `s` to `pattern` so that it only matches itself.
+pub fn escape_into(pattern: &mut Vec<u8>, s: &[u8]) {
+    for &b in s {
+        if is_special(b) {
+            pattern.push(b'\\');
+        }
+        pattern.push(b);
+    }
+}
+
+/// Returns true if `s` contains a wildcard which is not escaped: `*`, `?`, or `[` which
+/// starts a bracket expression.
+pub fn has_wildcard(s: &[u8]) -> bool {
+    let mut escaped = false;
+    for (i, &b) in s.iter().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'*' | b'?' => return true,
+            b'[' if match_bracket(&s[i..], 0).is_some() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Remove the escapes of a pattern without wildcards.
+fn unescape(pattern: &[u8]) -> Vec<u8> {
+    let mut s = Vec::with_capacity(pattern.len());
+    let mut escaped = false;
+    for &b in pattern {
+        if b == b'\\' && !escaped {
+            escaped = true;
+            continue;
+        }
+        escaped = false;
+        s.push(b);
+    }
+    s
+}
+
+/// Decode the character at the start of `s`, returns its code and its length in bytes.
+///
+/// A byte which is not valid UTF-8 is a character by itself, with a code after all the
+/// characters, so that it only matches itself.
+fn decode(s: &[u8]) -> (u32, usize) {
+    let chunk = s[..s.len().min(4)].utf8_chunks().next();
+    match chunk.and_then(|chunk| chunk.valid().chars().next()) {
+        Some(c) => (c as u32, c.len_utf8()),
+        None => (0x110000 + u32::from(s[0]), 1),
+    }
+}
+
+/// Decode a character in a bracket expression, which may be escaped.
+fn decode_escaped(s: &[u8]) -> Option<(u32, usize)> {
+    match s {
+        [b'\\', rest @ ..] if !rest.is_empty() => {
+            let (c, len) = decode(rest);
+            Some((c, len + 1))
+        }
+        [] => None,
+        _ => Some(decode(s)),
+    }
+}
+
+/// Returns true if `c` is in the POSIX character class `name`, such as `alpha` in
+/// `[[:alpha:]]`, or `None` if there is no such class.
+fn in_class(name: &[u8], c: u32) -> Option<bool> {
+    // Bytes which are not valid UTF-8 are in no class
+    let c = char::from_u32(c).unwrap_or('\0');
+    Some(match name {
+        b"alpha" => c.is_alphabetic(),
+        b"digit" => c.is_ascii_digit(),
+        b"alnum" => c.is_alphanumeric(),
+        b"upper" => c.is_uppercase(),
+        b"lower" => c.is_lowercase(),
+        b"space" => c.is_whitespace(),
+        b"blank" => matches!(c, ' ' | '\t'),
+        b"punct" => c.is_ascii_punctuation(),
+        b"print" => !c.is_control(),
+        b"graph" => !c.is_control() && !c.is_whitespace(),
+        b"cntrl" => c.is_control() && c != '\0',
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+/// Match `c` against the character class at the start of `pattern`, like `[:alpha:]`.
+/// Returns whether it matches and the length of the class, or `None` if it is not a class,
+/// in which case `[` is a member by itself.
+///
+/// Like bash, an unknown class like `[:foo:]` matches nothing.
+fn match_class(pattern: &[u8], c: u32) -> Option<(bool, usize)> {
+    let rest = pattern.strip_prefix(b"[:")?;
+    let end = rest.windows(2).position(|w| w == b":]")?;
+    let name = &rest[..end];
+    if !name.iter().all(u8::is_ascii_lowercase) {
+        return None;
+    }
+    Some((in_class(name, c).unwrap_or(false), end + 4))
+}
+
+/// Match `c` against the bracket expression at the start of `pattern`, such as `[a-z]`,
+/// `[!abc]` or `[[:digit:]_]`. A `]` right after `[` or `[!` is a member, not the end.
+///
+/// Returns whether it matches and the length of the expression, or `None` if there is no
+/// `]` to end it, in which case `[` only matches itself like bash.
+fn match_bracket(pattern: &[u8], c: u32) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some(b'!' | b'^'));
+    if negate {
+        i += 1;
+    }
+    let start = i;
+    let mut matched = false;
+    loop {
+        if pattern.get(i) == Some(&b']') && i > start {
+            return Some((matched != negate, i + 1));
+        }
+        if let Some((in_class, len)) = match_class(&pattern[i..], c) {
+            i += len;
+            matched |= in_class;
+            continue;
+        }
+        let (low, len) = decode_escaped(&pattern[i..])?;
+        i += len;
+        // `-` at the end is a member
+        if pattern.get(i) == Some(&b'-') && pattern.get(i + 1).is_some_and(|&b| b != b']') {
+            let (high, len) = decode_escaped(&pattern[i + 1..])?;
+            i += len + 1;
+            matched |= (low..=high).contains(&c);
+        } else {
+            matched |= low == c;
+        }
+    }
+}
+
+/// Returns true if `name` matches `pattern`, where `*` matches any string, `?` matches any
+/// character, and `[...]` matches a character in it.
+pub fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    // Where to go back to when the rest doesn't match: the position after the last `*`,
+    // and the position in `name` it matches up to.
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        let (c, len) = decode(&name[n..]);
+        let char_bytes = &name[n..n + len];
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                backtrack = Some((p, n));
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match match_bracket(&pattern[p..], c) {
+                Some((true, bracket_len)) => Some(bracket_len),
+                Some((false, _)) => None,
+                None => (c == u32::from(b'[')).then_some(1),
+            },
+            Some(b'\\') => pattern[p + 1..].starts_with(char_bytes).then_some(len + 1),
+            Some(_) => pattern[p..].starts_with(char_bytes).then_some(len),
+            None => None,
+        };
+        if let Some(step) = step {
+            p += step;
+            n += len;
+            continue;
+        }
+
+        // The `*` matches one more character
+        match backtrack {
+            Some((star_p, star_n)) => {
+                let n_next = star_n + decode(&name[star_n..]).1;
+                p = star_p;
+                n = n_next;
+                backtrack = Some((star_p, n_next));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+fn join(dir: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut path = dir.to_vec();
+    if !path.is_empty() && !path.ends_with(b"/") {
+        path.push(b'/');
+    }
+    path.extend_from_slice(name);
+    path
+}
+
+/// Names in `dir` which match `pattern`. Like bash, names starting with `.` only match if
+/// the pattern starts with `.`.
+fn match_dir(dir: &[u8], pattern: &[u8]) -> Vec<Vec<u8>> {
+    let path = if dir.is_empty() {
+        Path::new(".")
+    } else {
+        Path::new(OsStr::from_bytes(dir))
+    };
+    let Ok(entries) = path.read_dir() else {
+        return Vec::new();
+    };
+    let hidden = pattern.starts_with(b".") || pattern.starts_with(b"\\.");
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().into_vec())
+        .filter(|name| (hidden || !name.starts_with(b".")) && matches(pattern, name))
+        .map(|name| join(dir, &name))
+        .collect()
+}
+
+/// Expand a pattern into the paths which match it, relative to the working directory,
+/// sorted. Returns nothing if no path matches.
+///
+/// Each component between `/` is matched against the names in a directory, so `*/*.rs`
+/// matches files in the subdirectories. A trailing `/` only matches directories.
+pub fn expand(pattern: &[u8]) -> Vec<OsString> {
+    let mut paths = vec![if pattern.starts_with(b"/") {
+        b"/".to_vec()
+    } else {
+        Vec::new()
+    }];
+    let dir_only = pattern.ends_with(b"/");
+    // Whether the last component is literal, so the paths are not known to exist
+    let mut unchecked = false;
+
+    for component in pattern.split(|&b| b == b'/').filter(|c| !c.is_empty()) {
+        if has_wildcard(component) {
+            paths = paths
+                .iter()
+                .flat_map(|dir| match_dir(dir, component))
+                .collect();
+            unchecked = false;
+        } else {
+            let name = unescape(component);
+            for path in &mut paths {
+                *path = join(path, &name);
+            }
+            unchecked = true;
+        }
+        if paths.is_empty() {
+            return Vec::new();
+        }
+    }
+
+    let mut paths: Vec<_> = paths
+        .into_iter()
+        .map(OsString::from_vec)
+        .filter(|path| {
+            let path = Path::new(path);
+            (!unchecked || path.symlink_metadata().is_ok()) && (!dir_only || path.is_dir())
+        })
+        .map(|mut path| {
+            if dir_only {
+                path.push("/");
+            }
+            path
+        })
+        .collect();
+    paths.sort_unstable();
+    paths
+}
@@ -0,0 +1,306 @@
+//! Adding entries to the history, and writing the history file safely when several sessions
+//! share it.
+//!
+//! rustyline locks the history file while writing, but `save` truncates the file before the
+//! lock is taken, so a session may destroy the history being written by another one. Writes
+//! are serialized with a lock on `<histfile>.lock` here instead.
+//!
+//! A long history file is loaded lazily: the most recent entries are read from the end of
+//! the file before the first prompt, and the whole file is loaded in the background, see
+//! `HistoryLoader`.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+use rustyline::{
+    Config,
+    history::{FileHistory, History},
+};
+
+/// The header of history files where newlines and backslashes in entries are escaped.
+const FILE_VERSION_V2: &[u8] = b"#V2\n";
+
+/// Size of the first block read from the end of the file by `read_recent_entries`, the
+/// following blocks are twice as large as the previous one.
+const RECENT_BLOCK_SIZE: u64 = 16 * 1024;
+
+/// An advisory lock on a history file, which is released when dropped.
+pub struct HistoryLock {
+    _file: File,
+}
+
+impl HistoryLock {
+    /// Wait until the lock of the history file at `path` is acquired.
+    pub fn lock(path: &Path) -> io::Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(PathBuf::from(lock_path))?;
+
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } == 0 {
+                return Ok(Self { _file: file });
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Write the whole history to the file, replacing its content.
+///
+/// If the lock file can't be created, e.g. the directory is not writable, the history is
+/// written without the lock.
+pub fn save_history(history: &mut FileHistory, path: &Path) -> rustyline::Result<()> {
+    let _lock = HistoryLock::lock(path);
+    history.save(path)
+}
+
+/// Append the new entries of this session to the file. Entries added by other sessions
+/// since the file was loaded are kept, so sessions sharing the file don't lose entries.
+///
+/// If the lock file can't be created, the history is written without the lock.
+pub fn append_history(history: &mut FileHistory, path: &Path) -> rustyline::Result<()> {
+    let _lock = HistoryLock::lock(path);
+    history.append(path)
+}
+
+/// Undo the escaping of a history file of version 2. A line with an unknown escape is kept
+/// as it is, like rustyline does.
+fn unescape_entry(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => entry.push('\n'),
+                Some('\\') => entry.push('\\'),
+                _ => return line.to_owned(),
+            },
+            _ => entry.push(c),
+        }
+    }
+    entry
+}
+
+/// Read the last `count` entries of a history file written by rustyline, or fewer if the
+/// file is shorter.
+///
+/// The file is read backwards from its end, so this takes about the same time however long
+/// the file is.
+pub fn read_recent_entries<R: Read + Seek>(file: &mut R, count: usize) -> io::Result<Vec<String>> {
+    let mut header = Vec::with_capacity(FILE_VERSION_V2.len());
+    file.by_ref()
+        .take(FILE_VERSION_V2.len() as u64)
+        .read_to_end(&mut header)?;
+    let v2 = header == FILE_VERSION_V2;
+
+    let mut start = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+    let mut block_size = RECENT_BLOCK_SIZE;
+    loop {
+        let block_start = start.saturating_sub(block_size);
+        let mut block = vec![0; (start - block_start) as usize];
+        file.seek(SeekFrom::Start(block_start))?;
+        file.read_exact(&mut block)?;
+        block.append(&mut tail);
+        tail = block;
+        start = block_start;
+        block_size *= 2;
+
+        // The first line may be cut, unless the tail starts at the beginning of the file.
+        let mut lines = tail.split(|&b| b == b'\n');
+        if start > 0 || v2 {
+            lines.next();
+        }
+        let lines: Vec<_> = lines.filter(|line| !line.is_empty()).collect();
+        if lines.len() < count && start > 0 {
+            continue;
+        }
+
+        return lines[lines.len().saturating_sub(count)..]
+            .iter()
+            .map(|&line| {
+                let line = str::from_utf8(line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok(if v2 {
+                    unescape_entry(line)
+                } else {
+                    line.to_owned()
+                })
+            })
+            .collect();
+    }
+}
+
+/// Loads the whole history file in a background thread.
+///
+/// Parsing a history file with hundreds of thousands of lines visibly delays the first
+/// prompt, so only the most recent entries are loaded before it, which is enough for the
+/// Up arrow and hints. The history loaded in the background replaces them before the first
+/// line is run, so the history list, history expansion and saving see the whole file.
+pub struct HistoryLoader {
+    handle: JoinHandle<rustyline::Result<FileHistory>>,
+}
+
+impl HistoryLoader {
+    /// Add the most recent entries of the history file at `path` to `history`, which is
+    /// empty, and start loading the whole file. `config` is the configuration of the
+    /// editor, the loaded history is created with it.
+    ///
+    /// Errors when reading the recent entries are ignored, they are reported when the whole
+    /// file is loaded.
+    pub fn start(history: &mut FileHistory, path: &Path, config: &Config) -> Self {
+        if let Ok(mut file) = File::open(path)
+            && let Ok(entries) = read_recent_entries(&mut file, config.max_history_size())
+        {
+            for entry in entries {
+                let _ = history.add_owned(entry);
+            }
+        }
+
+        let path = path.to_path_buf();
+        let config = config.clone();
+        let handle = thread::spawn(move || {
+            let mut history = FileHistory::with_config(&config);
+            history.load(&path)?;
+            Ok(history)
+        });
+        Self { handle }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Wait until the file is loaded, and replace `history` with it.
+    ///
+    /// No entries may be added to `history` since `start`, since they would be lost. If
+    /// the file can't be loaded, `history` is cleared.
+    pub fn finish(self, history: &mut FileHistory) -> rustyline::Result<()> {
+        let loaded = self
+            .handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("history loading panicked").into()));
+        match loaded {
+            Ok(loaded) => {
+                *history = loaded;
+                Ok(())
+            }
+            Err(e) => {
+                // The recent entries are not marked as loaded from the file, so they must
+                // not be kept, or they would be appended to it again.
+                history.clear()?;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Add a line read from the user to the history. Blank lines are skipped, and so is a line
+/// which is the same as the previous entry if `ignore_dups` is set.
+///
+/// The history itself should be created with `history_ignore_dups(false)`, otherwise
+/// duplicates are always ignored by rustyline.
+///
+/// Returns true if the line is added.
+pub fn add_entry(
+    history: &mut FileHistory,
+    line: &str,
+    ignore_dups: bool,
+) -> rustyline::Result<bool> {
+    if line.trim().is_empty() {
+        return Ok(false);
+    }
+    if ignore_dups && history.iter().next_back().is_some_and(|last| last == line) {
+        return Ok(false);
+    }
+    history.add(line)
+}
+
+/// Find the entry of an event designator after `!`, such as `!`, `12`, `-2` or `ls`.
+fn find_event<'a>(history: &'a FileHistory, event: &str) -> Option<&'a String> {
+    let len = history.len();
+    let index = match event {
+        "!" => len.checked_sub(1)?,
+        _ => match event.parse::<isize>() {
+            Ok(n) if n > 0 => n as usize - 1,
+            Ok(n) if n < 0 => len.checked_sub(n.unsigned_abs())?,
+            Ok(_) => return None,
+            Err(_) => {
+                return history.iter().rev().find(|entry| entry.starts_with(event));
+            }
+        },
+    };
+    history.iter().nth(index)
+}
+
+/// Perform history expansion on a line, like bash:
+///
+/// - `!!`: the previous entry
+/// - `!n`: entry `n`
+/// - `!-n`: the `n`-th previous entry
+/// - `!string`: the most recent entry starting with `string`
+///
+/// `!` is kept as it is if it is quoted by single quotes or a backslash, or followed by a
+/// blank, `=` or `(`, or the end of the line.
+///
+/// Returns the event that can't be found as an error.
+pub fn expand_history(line: &str, history: &FileHistory) -> Result<String, String> {
+    let mut expanded = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    let mut single_quote = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => single_quote = !single_quote,
+            '\\' if !single_quote => {
+                expanded.push(c);
+                if let Some(c) = chars.next() {
+                    expanded.push(c);
+                }
+                continue;
+            }
+            '!' if !single_quote => {
+                let event = match chars.peek() {
+                    None => None,
+                    Some(&c) if c.is_whitespace() || matches!(c, '=' | '(') => None,
+                    Some('!') => {
+                        chars.next();
+                        Some(String::from("!"))
+                    }
+                    Some(_) => {
+                        let mut event = String::new();
+                        while let Some(c) = chars.next_if(|&c| {
+                            !c.is_whitespace() && !matches!(c, '\'' | '"' | ';' | '|' | '&')
+                        }) {
+                            event.push(c);
+                        }
+                        Some(event)
+                    }
+                };
+                if let Some(event) = event {
+                    match find_event(history, &event) {
+                        Some(entry) => expanded.push_str(entry),
+                        None => return Err(format!("!{}", event)),
+                    }
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        expanded.push(c);
+    }
+
+    Ok(expanded)
+}
@@ -0,0 +1,507 @@
+//! Job control.
+//!
+//! When the shell is interactive and reads from its controlling terminal, the external
+//! commands of a pipeline run in their own process group, which owns the terminal while
+//! the job runs in the foreground. So Ctrl-Z stops the job instead of the shell, and the
+//! stopped job is kept in the job table.
+//!
+//! Jobs in the table which finish are reported before the next prompt, or right away with
+//! `set -o notify`.
+
+use std::{
+    ffi::CStr,
+    io::{self, Read},
+    os::fd::{AsRawFd, IntoRawFd, OwnedFd, RawFd},
+    ptr,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+    },
+    thread,
+};
+
+use libc::pid_t;
+
+/// Signals which are ignored by the shell with job control. Children restore the default
+/// dispositions, see `execution::process`.
+pub const JOB_CONTROL_SIGNALS: &[libc::c_int] = &[libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    Running,
+    Stopped,
+    /// All processes have exited, with the wait status of the last one. The job is removed
+    /// once this is reported, see `JobTable::take_finished`.
+    Done(libc::c_int),
+}
+
+impl JobState {
+    /// The state as shown by bash, such as `Stopped`, `Done`, `Exit 3` or `Killed`.
+    pub fn describe(&self) -> String {
+        let status = match self {
+            JobState::Running => return String::from("Running"),
+            JobState::Stopped => return String::from("Stopped"),
+            JobState::Done(status) => *status,
+        };
+        if libc::WIFSIGNALED(status) {
+            let signal = libc::WTERMSIG(status);
+            let name = unsafe { libc::strsignal(signal) };
+            let mut description = if name.is_null() {
+                format!("Signal {}", signal)
+            } else {
+                unsafe { CStr::from_ptr(name) }
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            if libc::WCOREDUMP(status) {
+                description.push_str(" (core dumped)");
+            }
+            description
+        } else {
+            match libc::WEXITSTATUS(status) {
+                0 => String::from("Done"),
+                code => format!("Exit {}", code),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// `%1`, `%2`, ...
+    pub id: usize,
+    pub pgid: pid_t,
+    /// The command line, as shown by `jobs`.
+    pub command: String,
+    pub state: JobState,
+    /// Processes of the job which have not been waited for.
+    pub pids: Vec<pid_t>,
+    /// The last process of the pipeline, whose status is the status of the job.
+    pub last_pid: pid_t,
+    /// The wait status of `last_pid` once it has exited.
+    pub last_status: libc::c_int,
+}
+
+/// The jobs of the shell. It is shared with the thread which reports finished jobs right
+/// away with `set -o notify`, see `Notifier`.
+#[derive(Debug, Clone, Default)]
+pub struct JobTable {
+    jobs: Arc<Mutex<Vec<Job>>>,
+}
+
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Job>> {
+        // A panic while the table is locked leaves it consistent.
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a job, returns its id. Like bash, the id is one more than the largest id in use.
+    pub fn add(&self, pgid: pid_t, command: String, pids: Vec<pid_t>, state: JobState) -> usize {
+        let mut jobs = self.lock();
+        let id = jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let last_pid = pids.last().copied().unwrap_or(pgid);
+        jobs.push(Job {
+            id,
+            pgid,
+            command,
+            state,
+            pids,
+            last_pid,
+            last_status: 0,
+        });
+        id
+    }
+
+    pub fn get(&self, id: usize) -> Option<Job> {
+        self.lock().iter().find(|job| job.id == id).cloned()
+    }
+
+    pub fn remove(&self, id: usize) -> Option<Job> {
+        let mut jobs = self.lock();
+        let index = jobs.iter().position(|job| job.id == id)?;
+        Some(jobs.remove(index))
+    }
+
+    /// Put back a job taken with `remove`, with the same id. It becomes the current job.
+    pub fn restore(&self, job: Job) {
+        self.lock().push(job);
+    }
+
+    /// The id of the job named by `spec`. Like bash, `%N` is job `N`, `%`, `%%` and `%+`
+    /// are the current job, and `%-` is the previous one.
+    pub fn find(&self, spec: &str) -> Option<usize> {
+        let jobs = self.lock();
+        let from_end = match spec.strip_prefix('%')? {
+            "" | "%" | "+" => 1,
+            "-" => 2,
+            id => {
+                let id: usize = id.parse().ok()?;
+                return jobs.iter().any(|job| job.id == id).then_some(id);
+            }
+        };
+        jobs.len().checked_sub(from_end).map(|index| jobs[index].id)
+    }
+
+    /// A snapshot of the jobs, in the order they are added.
+    pub fn jobs(&self) -> Vec<Job> {
+        self.lock().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    /// The warning printed by `exit` when there are jobs, stopped jobs first.
+    pub fn exit_warning(&self) -> Option<&'static str> {
+        let jobs = self.lock();
+        if jobs.iter().any(|job| job.state == JobState::Stopped) {
+            Some("There are stopped jobs.")
+        } else if !jobs.is_empty() {
+            Some("There are running jobs.")
+        } else {
+            None
+        }
+    }
+
+    /// The line reporting the state of the job `id`, like `[1]+  Stopped  sleep 10`. Like
+    /// bash, `+` marks the current job, which is the most recent one, and `-` the previous
+    /// one.
+    pub fn status_line(&self, id: usize) -> Option<String> {
+        let jobs = self.lock();
+        let index = jobs.iter().position(|job| job.id == id)?;
+        let marker = match jobs.len() - index {
+            1 => '+',
+            2 => '-',
+            _ => ' ',
+        };
+        let job = &jobs[index];
+        Some(format!(
+            "[{}]{}  {:<24}{}",
+            job.id,
+            marker,
+            job.state.describe(),
+            job.command
+        ))
+    }
+
+    /// Apply the wait status of the process `pid`, as returned by `waitpid`. Returns false
+    /// if the process is not in any job.
+    pub fn update(&self, pid: pid_t, status: libc::c_int) -> bool {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.iter_mut().find(|job| job.pids.contains(&pid)) else {
+            return false;
+        };
+        if libc::WIFSTOPPED(status) {
+            job.state = JobState::Stopped;
+        } else if libc::WIFCONTINUED(status) {
+            job.state = JobState::Running;
+        } else {
+            job.pids.retain(|&p| p != pid);
+            // The last process may exit before the others.
+            if pid == job.last_pid {
+                job.last_status = status;
+            }
+            if job.pids.is_empty() {
+                job.state = JobState::Done(job.last_status);
+            }
+        }
+        true
+    }
+
+    /// Wait for the processes of the jobs without blocking, and update their states.
+    pub fn reap(&self) {
+        let pids: Vec<pid_t> = self
+            .lock()
+            .iter()
+            .flat_map(|job| job.pids.clone())
+            .collect();
+        for pid in pids {
+            let mut status = 0;
+            let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+            if unsafe { libc::waitpid(pid, &mut status, flags) } == pid {
+                self.update(pid, status);
+            }
+        }
+    }
+
+    /// Remove the jobs which are done, returns the lines reporting them.
+    pub fn take_finished(&self) -> Vec<String> {
+        let finished: Vec<usize> = self
+            .lock()
+            .iter()
+            .filter(|job| matches!(job.state, JobState::Done(_)))
+            .map(|job| job.id)
+            .collect();
+        finished
+            .into_iter()
+            .filter_map(|id| {
+                let line = self.status_line(id);
+                self.remove(id);
+                line
+            })
+            .collect()
+    }
+}
+
+/// The lowest fd of the terminal kept by `JobControl`, like bash, so it is out of the way of
+/// the fds used by redirections.
+const TTY_FD: RawFd = 255;
+
+/// The controlling terminal of an interactive shell, which is given to foreground jobs.
+#[derive(Debug, Clone, Copy)]
+pub struct JobControl {
+    pub tty: RawFd,
+    pub shell_pgid: pid_t,
+}
+
+impl JobControl {
+    /// Put the shell in its own process group in the foreground of the terminal `tty`.
+    ///
+    /// Returns `None` if `tty` is not the controlling terminal of the shell, then there is
+    /// no job control.
+    ///
+    /// The terminal is kept in a fd of its own, since children give it to their job after
+    /// their stdin may be replaced by a pipe or a redirection.
+    pub fn init(tty: RawFd) -> Option<Self> {
+        unsafe {
+            if libc::isatty(tty) == 0 {
+                return None;
+            }
+            let tty = libc::fcntl(tty, libc::F_DUPFD_CLOEXEC, TTY_FD);
+            if tty == -1 {
+                return None;
+            }
+            let control = Self::take_foreground(tty);
+            if control.is_none() {
+                libc::close(tty);
+            }
+            control
+        }
+    }
+
+    fn take_foreground(tty: RawFd) -> Option<Self> {
+        unsafe {
+            // Like bash, wait until the shell is started in the foreground.
+            loop {
+                let foreground = libc::tcgetpgrp(tty);
+                if foreground == -1 {
+                    return None;
+                }
+                if foreground == libc::getpgrp() {
+                    break;
+                }
+                libc::kill(-libc::getpgrp(), libc::SIGTTIN);
+            }
+
+            for &signal in JOB_CONTROL_SIGNALS {
+                libc::signal(signal, libc::SIG_IGN);
+            }
+            // A session leader is already the leader of its process group
+            let pid = libc::getpid();
+            if libc::getpgrp() != pid && libc::setpgid(0, 0) == -1 {
+                return None;
+            }
+            let control = Self {
+                tty,
+                shell_pgid: pid,
+            };
+            control.give_terminal(pid).ok()?;
+            Some(control)
+        }
+    }
+
+    /// Make `pgid` the foreground process group of the terminal.
+    pub fn give_terminal(&self, pgid: pid_t) -> io::Result<()> {
+        if unsafe { libc::tcsetpgrp(self.tty, pgid) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Make the shell the foreground process group of the terminal again.
+    pub fn take_terminal(&self) -> io::Result<()> {
+        self.give_terminal(self.shell_pgid)
+    }
+}
+
+/// How a foreground job stopped running.
+#[derive(Debug)]
+pub enum JobWait {
+    /// All processes have exited, with the wait status of the last one. `interrupted` is
+    /// true if the shell got `SIGINT` while waiting, see `catch_interrupt`.
+    Done {
+        status: io::Result<libc::c_int>,
+        interrupted: bool,
+    },
+    /// The job is stopped by the signal, with the processes which have not been waited for.
+    Stopped {
+        signal: libc::c_int,
+        pids: Vec<pid_t>,
+    },
+}
+
+/// Set by the `SIGINT` handler, see `catch_interrupt`.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// The thread which waits for foreground jobs.
+static WAITING_THREAD: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    // The signal may be delivered to any thread, but only the one which gets it is
+    // interrupted, so it is passed on to the thread in `waitpid`.
+    unsafe {
+        let thread = WAITING_THREAD.load(Ordering::Relaxed) as libc::pthread_t;
+        if libc::pthread_self() != thread {
+            let errno = *libc::__errno_location();
+            libc::pthread_kill(thread, libc::SIGINT);
+            *libc::__errno_location() = errno;
+        }
+    }
+}
+
+/// Catch `SIGINT` in an interactive shell, so that Ctrl-C stops waiting for a foreground
+/// job instead of killing the shell. It must be called from the thread which runs
+/// commands.
+///
+/// Children get the default disposition back when they exec.
+pub fn catch_interrupt() -> io::Result<()> {
+    WAITING_THREAD.store(unsafe { libc::pthread_self() } as u64, Ordering::Relaxed);
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigint as *const () as libc::sighandler_t;
+        // Without `SA_RESTART`, so `waitpid` returns.
+        action.sa_flags = 0;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGINT, &action, ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the processes of a foreground job until they exit or the job is stopped.
+///
+/// `pgid` is the process group of the job if it has its own. Then the terminal only sends
+/// `SIGINT` to the job, so one which the shell gets, e.g. from `kill`, is sent to the job
+/// too. Without it, the processes are in the group of the shell and get the same signals,
+/// and they can't be stopped apart from the shell.
+///
+/// The processes are still waited for after `SIGINT`, so none of them is left behind.
+pub fn wait_for_job(pids: &[pid_t], pgid: Option<pid_t>) -> JobWait {
+    let mut last = Ok(0);
+    let mut interrupted = false;
+    let flags = if pgid.is_some() { libc::WUNTRACED } else { 0 };
+    for (index, &pid) in pids.iter().enumerate() {
+        let mut status = 0;
+        let ret = loop {
+            let ret = unsafe { libc::waitpid(pid, &mut status, flags) };
+            if ret == -1 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                if INTERRUPTED.swap(false, Ordering::Relaxed) {
+                    interrupted = true;
+                    if let Some(pgid) = pgid {
+                        unsafe { libc::kill(-pgid, libc::SIGINT) };
+                    }
+                }
+                continue;
+            }
+            break ret;
+        };
+        if ret == -1 {
+            last = Err(io::Error::last_os_error());
+            continue;
+        }
+        if libc::WIFSTOPPED(status) {
+            return JobWait::Stopped {
+                signal: libc::WSTOPSIG(status),
+                pids: pids[index..].to_vec(),
+            };
+        }
+        last = Ok(status);
+    }
+    JobWait::Done {
+        status: last,
+        interrupted,
+    }
+}
+
+/// The write end of the pipe written by the `SIGCHLD` handler, see `Notifier`.
+static SIGCHLD_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_sigchld(_: libc::c_int) {
+    let fd = SIGCHLD_PIPE.load(Ordering::Relaxed);
+    if fd != -1 {
+        // Only async-signal-safe functions may be called here, and errno must be kept.
+        unsafe {
+            let errno = *libc::__errno_location();
+            libc::write(fd, [0u8].as_ptr().cast(), 1);
+            *libc::__errno_location() = errno;
+        }
+    }
+}
+
+/// Reports finished jobs right away with `set -o notify`, instead of before the next
+/// prompt.
+///
+/// A `SIGCHLD` handler wakes a thread, which reaps the jobs and passes the lines to `print`.
+/// The line editor prints them above the line being edited and draws it again, so the
+/// input is not mixed with them.
+pub struct Notifier {
+    enabled: Arc<AtomicBool>,
+}
+
+impl Notifier {
+    pub fn start<F>(jobs: JobTable, mut print: F) -> io::Result<Self>
+    where
+        F: FnMut(String) + Send + 'static,
+    {
+        let (mut reader, writer) = io::pipe()?;
+        let writer = OwnedFd::from(writer);
+        // The handler must never block, a full pipe already wakes the thread.
+        unsafe {
+            let flags = libc::fcntl(writer.as_raw_fd(), libc::F_GETFL);
+            libc::fcntl(writer.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK);
+        }
+        SIGCHLD_PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigchld as *const () as libc::sighandler_t;
+            // Interrupted system calls are restarted, most of them anyway.
+            action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGCHLD, &action, ptr::null_mut()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let enabled = Arc::new(AtomicBool::new(false));
+        let thread_enabled = Arc::clone(&enabled);
+        thread::spawn(move || {
+            let mut buf = [0; 64];
+            while let Ok(n) = reader.read(&mut buf)
+                && n > 0
+            {
+                if !thread_enabled.load(Ordering::Relaxed) {
+                    continue;
+                }
+                jobs.reap();
+                for line in jobs.take_finished() {
+                    print(format!("{}\n", line));
+                }
+            }
+        });
+        Ok(Self { enabled })
+    }
+
+    /// Set by the main loop from `set -o notify` before each prompt.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
@@ -1,13 +1,31 @@
+pub mod arith;
+pub mod brace;
 pub mod builtin;
 pub mod completion;
+pub mod conditional;
+pub mod config;
 pub mod env;
 pub mod execution;
+pub mod expand;
+pub mod glob;
+pub mod history;
+pub mod job;
 pub mod parse;
+pub mod printf;
+pub mod prompt;
+pub mod quote;
 pub mod redirect;
+pub mod shell;
+pub mod suggest;
+pub mod terminal;
+pub mod test;
+
+pub use quote::{quote, quote_os};
+pub use shell::Shell;
 
 use std::{
     cell::{Ref, RefCell},
-    fs::DirBuilder,
+    io::{self, PipeWriter, Read},
     path::PathBuf,
     rc::Rc,
 };
@@ -31,22 +49,8 @@ pub fn get_histfile_env() -> Option<PathBuf> {
     std::env::var_os("HISTFILE").map(PathBuf::from)
 }
 
-pub fn get_histfile_path(env: Ref<ExecEnv>) -> PathBuf {
-    let opt_histfile_path = env.histfile_env.clone();
-
-    match opt_histfile_path {
-        Some(path) => path,
-        None => {
-            let path = env.base_dirs.data_local_dir();
-            if !path.exists() {
-                DirBuilder::new().recursive(true).create(path).unwrap(); // TODO: handle error
-            }
-            env.base_dirs
-                .data_local_dir()
-                .to_owned()
-                .join("mysh_history")
-        }
-    }
+pub fn get_config_path(env: Ref<ExecEnv>) -> PathBuf {
+    env.base_dirs.config_dir().join("mysh").join("config.toml")
 }
 
 pub fn get_input_and_run(
@@ -54,6 +58,58 @@ pub fn get_input_and_run(
     env: Rc<RefCell<ExecEnv>>,
     history: ExecContext,
 ) -> CommandResult {
-    let exec = parse::parse_command(input);
-    execution::execute_command_chain(exec, env, history)
+    parse_and_run(input, env, history, None)
+}
+
+/// Run a command line with its stdout captured, returns the output and the status.
+///
+/// The command runs without job control, so it can't be stopped while the output is read.
+/// `exit` only ends the command line, its status is returned.
+pub fn get_input_and_capture(
+    input: &str,
+    env: Rc<RefCell<ExecEnv>>,
+    history: ExecContext,
+) -> (Vec<u8>, i32) {
+    let (mut reader, writer) = match io::pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            eprintln!("mysh: can't create a pipe: {}", e);
+            return (Vec::new(), 1);
+        }
+    };
+    // Read while the command runs, otherwise it blocks once the pipe is full.
+    let output = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = reader.read_to_end(&mut output);
+        output
+    });
+
+    let job_control = env.borrow_mut().job_control.take();
+    let ret = parse_and_run(input, Rc::clone(&env), history, Some(writer));
+    env.borrow_mut().job_control = job_control;
+
+    let output = output.join().unwrap_or_default();
+    let status = match ret {
+        CommandResult::Exit(status) => status,
+        CommandResult::Normal => env.borrow().last_status,
+    };
+    (output, status)
+}
+
+fn parse_and_run(
+    input: &str,
+    env: Rc<RefCell<ExecEnv>>,
+    history: ExecContext,
+    output: Option<PipeWriter>,
+) -> CommandResult {
+    let parsed = parse::parse_command(input, &env.borrow().aliases);
+    match parsed {
+        Ok(exec) => execution::execute_command_chain_to(input.trim(), exec, env, history, output),
+        Err(msg) => {
+            // Like bash, a syntax error gives 2.
+            eprintln!("mysh: {}", msg);
+            env.borrow_mut().last_status = 2;
+            CommandResult::Normal
+        }
+    }
 }
@@ -1,23 +1,151 @@
 use std::{
     cell::RefCell,
-    io::{self, Write},
+    ffi::OsString,
+    fs,
+    io::{self, IsTerminal, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
     rc::Rc,
 };
 
 use mysh::{
     completion::ShellCompleter,
-    env::{ExecContext, ExecEnv},
+    config,
+    env::{ExecContext, ExecEnv, Hooks},
     execution::result::CommandResult,
+    history::HistoryLoader,
+    job::{self, JobControl, Notifier},
+    parse,
+    terminal::TerminalModes,
 };
-use rustyline::{CompletionType, Editor, error::ReadlineError};
+use rustyline::{
+    CompletionType, Config, EditMode, Editor, ExternalPrinter, config::Configurer,
+    error::ReadlineError, history::FileHistory,
+};
+
+const USAGE: &str = "\
+Usage: mysh [option]...
+       mysh [option]... -c command [argument]...
+
+Options:
+  -c command       read commands from the command string, the remaining arguments
+                   are set as the positional parameters
+      --posix      follow bash (POSIX) instead of zsh where they differ
+      --no-config  don't load the configuration file
+      --dump-config
+                   print the configuration after applying the configuration file and
+                   the options, then exit
+      --dump-parse command
+                   print how the command line is parsed without running it, then exit
+  -h, --help       print this help message and exit
+  -V, --version    print version information and exit
+";
+
+/// Command line arguments.
+struct Args {
+    mode: Mode,
+    posix: bool,
+    load_config: bool,
+    /// `login(1)` starts a login shell with `-` before its name, like `-mysh`.
+    login: bool,
+}
+
+/// What `main` should do after looking at the command line arguments.
+enum Mode {
+    Interactive,
+    Command {
+        command: String,
+        shell_name: OsString,
+        params: Vec<OsString>,
+    },
+    Help,
+    Version,
+    DumpConfig,
+    DumpParse(String),
+}
+
+/// Arguments are `OsString`, since positional parameters may not be valid UTF-8.
+fn parse_args<I: Iterator<Item = OsString>>(mut args: I) -> anyhow::Result<Args> {
+    let shell_name = args.next().unwrap_or_else(|| OsString::from("mysh"));
+    let login = shell_name.as_encoded_bytes().starts_with(b"-");
+
+    let mut mode = Mode::Interactive;
+    let mut posix = false;
+    let mut load_config = true;
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-h" | "--help") => {
+                return Ok(Args {
+                    mode: Mode::Help,
+                    posix,
+                    load_config,
+                    login,
+                });
+            }
+            Some("-V" | "--version") => mode = Mode::Version,
+            Some("--dump-config") => mode = Mode::DumpConfig,
+            Some("--dump-parse") => {
+                let command = args.next().ok_or_else(|| {
+                    anyhow::anyhow!("mysh: --dump-parse: option requires an argument\n{}", USAGE)
+                })?;
+                let command = command.into_string().map_err(|_| {
+                    anyhow::anyhow!("mysh: --dump-parse: command line is not valid UTF-8\n")
+                })?;
+                mode = Mode::DumpParse(command);
+            }
+            Some("--posix") => posix = true,
+            Some("--no-config") => load_config = false,
+            Some("-c") => {
+                let command = args.next().ok_or_else(|| {
+                    anyhow::anyhow!("mysh: -c: option requires an argument\n{}", USAGE)
+                })?;
+                let command = command.into_string().map_err(|_| {
+                    anyhow::anyhow!("mysh: -c: command string is not valid UTF-8\n")
+                })?;
+                // Everything after the command string is not an option.
+                let params = args.collect();
+                return Ok(Args {
+                    mode: Mode::Command {
+                        command,
+                        shell_name,
+                        params,
+                    },
+                    posix,
+                    load_config,
+                    login,
+                });
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "mysh: {}: invalid option\n{}",
+                    arg.display(),
+                    USAGE
+                ));
+            }
+        }
+    }
+
+    Ok(Args {
+        mode,
+        posix,
+        load_config,
+        login,
+    })
+}
 
 fn main() -> anyhow::Result<()> {
-    let mut rl = Editor::with_config(
-        rustyline::Config::builder()
-            .completion_show_all_if_ambiguous(true)
-            .completion_type(CompletionType::List)
-            .build(),
-    )?;
+    let Args {
+        mode,
+        posix,
+        load_config,
+        login,
+    } = match parse_args(std::env::args_os()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprint!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
     let path_env = mysh::get_path_env();
     let histfile_env = mysh::get_histfile_env();
@@ -27,54 +155,363 @@ fn main() -> anyhow::Result<()> {
         histfile_env,
         base_dirs,
     )));
+    if load_config {
+        load_config_file(&env);
+    }
+    // Options from the command line override the configuration file.
+    if posix {
+        env.borrow_mut().options.posix = true;
+    }
+    env.borrow_mut().login = login;
 
-    let completer = ShellCompleter::new(Rc::clone(&env));
-    rl.set_helper(Some(completer));
+    let status = match mode {
+        Mode::Help => {
+            print!("{}", USAGE);
+            0
+        }
+        Mode::Version => {
+            println!("mysh {}", env!("CARGO_PKG_VERSION"));
+            0
+        }
+        Mode::DumpConfig => {
+            print!("{}", config::dump_config(&env.borrow()));
+            0
+        }
+        // Like running it, a syntax error gives 2
+        Mode::DumpParse(command) => match parse::dump_parse(&command) {
+            Ok(dump) => {
+                print!("{}", dump);
+                0
+            }
+            Err(msg) => {
+                eprintln!("mysh: {}", msg);
+                2
+            }
+        },
+        Mode::Command {
+            command,
+            shell_name,
+            params,
+        } => run_command_string(&command, shell_name, params, env)?,
+        Mode::Interactive => run_interactive(env)?,
+    };
+    io::stdout().flush()?;
+    std::process::exit(status);
+}
 
+/// Load the configuration file if it exists. Problems are reported as warnings, so that a
+/// broken configuration never prevents the shell from starting.
+fn load_config_file(env: &Rc<RefCell<ExecEnv>>) {
+    let path = mysh::get_config_path(env.borrow());
+    let config = match fs::read_to_string(&path) {
+        Ok(config) => config,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("mysh: {}: {}", path.display(), e);
+            return;
+        }
+    };
+
+    for warning in config::load_config(&mut env.borrow_mut(), &config) {
+        eprintln!("mysh: {}: {}", path.display(), warning);
+    }
+}
+
+/// Replace the recent entries of the history with the whole history file once `loader` has
+/// loaded it, or wait until it is loaded if `wait` is set.
+fn update_history(
+    rl: &mut Editor<ShellCompleter, FileHistory>,
+    loader: &mut Option<HistoryLoader>,
+    histfile_path: &mut Option<PathBuf>,
+    wait: bool,
+) {
+    if !wait && !loader.as_ref().is_some_and(HistoryLoader::is_finished) {
+        return;
+    }
+    if let Some(loader) = loader.take()
+        && let Some(path) = histfile_path.as_deref()
+        && !finish_loading_history(rl, loader, path)
     {
-        let histfile_path = mysh::get_histfile_path(env.borrow());
-        if rl.load_history(&histfile_path).is_err() {
-            rl.save_history(&histfile_path)?;
+        *histfile_path = None;
+    }
+}
+
+/// Wait until the history file is loaded by `loader`. If it exists but can't be loaded, it
+/// is moved to `<name>.bad` so that saving the history later doesn't destroy it, and the
+/// history starts empty.
+///
+/// Returns false if the history must not be saved to the file, since it can't be moved.
+fn finish_loading_history(
+    rl: &mut Editor<ShellCompleter, FileHistory>,
+    loader: HistoryLoader,
+    path: &Path,
+) -> bool {
+    let e = match loader.finish(rl.history_mut()) {
+        Ok(()) => return true,
+        Err(ReadlineError::Io(e)) if e.kind() == io::ErrorKind::NotFound => return true,
+        Err(e) => e,
+    };
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bad");
+    let backup = PathBuf::from(backup);
+    match fs::rename(path, &backup) {
+        Ok(()) => {
+            eprintln!(
+                "mysh: {}: can't load history: {}, moved to {}",
+                path.display(),
+                e,
+                backup.display()
+            );
+            true
         }
+        Err(rename_error) => {
+            eprintln!(
+                "mysh: {}: can't load history: {}, and can't move it to {}: {}",
+                path.display(),
+                e,
+                backup.display(),
+                rename_error
+            );
+            false
+        }
+    }
+}
+
+fn run_command_string(
+    command: &str,
+    shell_name: OsString,
+    params: Vec<OsString>,
+    env: Rc<RefCell<ExecEnv>>,
+) -> anyhow::Result<i32> {
+    env.borrow_mut().set_positional_params(shell_name, params);
+
+    // History is not recorded when running a command string, but builtins still need one.
+    let mut history = FileHistory::new();
+    let context = ExecContext::new(&mut history);
+    let ret = mysh::get_input_and_run(command, Rc::clone(&env), context);
+    io::stdout().flush()?;
+
+    match ret {
+        CommandResult::Exit(status) => Ok(status),
+        CommandResult::Normal => Ok(env.borrow().last_status),
     }
+}
+
+/// Returns true if lines typed in a terminal should be read without the line editor, since
+/// `TERM` is `dumb` or stdout is not a terminal. The editor would write escape sequences to
+/// the output, which can't be shown there.
+///
+/// If stdin is not a terminal, rustyline already reads lines without editing or a prompt.
+fn is_plain_terminal() -> bool {
+    io::stdin().is_terminal()
+        && (std::env::var_os("TERM").is_some_and(|term| term == "dumb")
+            || !io::stdout().is_terminal())
+}
+
+/// Read a line without the line editor, like a plain reader. A line ending with a backslash
+/// or in quotes continues on the next line, as the editor does.
+fn read_plain_line(prompt: &str) -> rustyline::Result<String> {
+    let mut stdout = io::stdout();
+    stdout.write_all(prompt.as_bytes())?;
+    stdout.flush()?;
 
+    let mut line = String::new();
     loop {
-        let readline = rl.readline("$ ");
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Err(ReadlineError::Eof);
+        }
+        if line.ends_with('\n') {
+            line.pop();
+        }
+        if !mysh::parse::is_incomplete(&line) {
+            return Ok(line);
+        }
+        line.push('\n');
+    }
+}
+
+/// Switch the edit mode of the editor to the one selected by the `vi` option.
+fn set_edit_mode(rl: &mut Editor<ShellCompleter, FileHistory>, env: &ExecEnv) {
+    let mode = if env.options.vi {
+        EditMode::Vi
+    } else {
+        EditMode::Emacs
+    };
+    rl.set_edit_mode(mode);
+}
+
+/// Start reporting finished jobs while a line is edited, for `set -o notify`.
+///
+/// The external printer of rustyline makes the editor wait for the terminal even when keys
+/// are already read into its buffer, so typed-ahead input lags. It is only created once
+/// `notify` is turned on.
+fn start_notifier(rl: &mut Editor<ShellCompleter, FileHistory>, env: &ExecEnv) -> Option<Notifier> {
+    env.job_control?;
+    let mut printer = rl.create_external_printer().ok()?;
+    Notifier::start(env.jobs.clone(), move |line| {
+        let _ = printer.print(line);
+    })
+    .ok()
+}
+
+/// Run `$PROMPT_COMMAND` before the prompt like bash, e.g. to update the title of the
+/// terminal. `$?` is kept, so that it is still the status of the last command line.
+fn run_prompt_command(
+    history: &mut FileHistory,
+    env: &Rc<RefCell<ExecEnv>>,
+) -> anyhow::Result<CommandResult> {
+    let command = env.borrow().get_var_or_env("PROMPT_COMMAND");
+    let Some(command) = command.filter(|command| !command.is_empty()) else {
+        return Ok(CommandResult::Normal);
+    };
+    let status = env.borrow().last_status;
+    let context = ExecContext::new(history);
+    let ret = mysh::get_input_and_run(&command.to_string_lossy(), Rc::clone(env), context);
+    io::stdout().flush()?;
+    env.borrow_mut().last_status = status;
+    Ok(ret)
+}
+
+fn run_interactive(env: Rc<RefCell<ExecEnv>>) -> anyhow::Result<i32> {
+    env.borrow_mut().interactive = true;
+    env.borrow_mut().job_control = JobControl::init(io::stdin().as_raw_fd());
+    let config = Config::builder()
+        .completion_show_all_if_ambiguous(true)
+        .completion_type(CompletionType::List)
+        // Duplicates are handled by `history::add_entry`
+        .history_ignore_dups(false)?
+        .build();
+    let mut rl = Editor::with_config(config.clone())?;
+    // After the editor, which has its own handler, but only needs it while a line is read
+    // in raw mode, where Ctrl-C is a key.
+    if let Err(e) = job::catch_interrupt() {
+        eprintln!("mysh: can't catch SIGINT: {}", e);
+    }
+
+    let completer = ShellCompleter::new(Rc::clone(&env));
+    rl.set_helper(Some(completer));
+
+    // Without a usable history file, the shell still runs, but history is not saved.
+    let mut histfile_path = match env.borrow().histfile_path() {
+        Ok(path) => {
+            if let Some(histfile_env) = &env.borrow().histfile_env
+                && *histfile_env != path
+            {
+                eprintln!(
+                    "mysh: {}: can't be written, using {} instead",
+                    histfile_env.display(),
+                    path.display()
+                );
+            }
+            Some(path)
+        }
+        Err(e) => {
+            eprintln!(
+                "mysh: no usable history file, history will not be saved: {}",
+                e
+            );
+            None
+        }
+    };
+    let mut loader = histfile_path
+        .as_deref()
+        .map(|path| HistoryLoader::start(rl.history_mut(), path, &config));
+
+    let plain = is_plain_terminal();
+    let mut notifier = None;
+    let mut terminal = TerminalModes::save(io::stdin().as_raw_fd());
+    let status = loop {
+        // Like bash, jobs which have finished are reported before the prompt.
+        let jobs = env.borrow().jobs.clone();
+        jobs.reap();
+        for line in jobs.take_finished() {
+            eprintln!("{}", line);
+        }
+        let notify = env.borrow().options.notify;
+        if notify && notifier.is_none() && !plain {
+            notifier = start_notifier(&mut rl, &env.borrow());
+        }
+        if let Some(notifier) = &notifier {
+            notifier.set_enabled(notify);
+        }
+        if let CommandResult::Exit(status) = run_prompt_command(rl.history_mut(), &env)? {
+            break status;
+        }
+        // `set -o vi` and `set -o emacs` take effect from the next line.
+        set_edit_mode(&mut rl, &env.borrow());
+        update_history(&mut rl, &mut loader, &mut histfile_path, false);
+        let prompt = mysh::prompt::render_prompt(&env.borrow());
+        let readline = if plain {
+            read_plain_line(&prompt)
+        } else {
+            rl.readline(&prompt)
+        };
         let ret = match readline {
             Ok(line) => {
-                rl.add_history_entry(line.as_str())?;
+                // The history must be loaded before a line is added to it.
+                update_history(&mut rl, &mut loader, &mut histfile_path, true);
+                // Modes may be changed by the user, e.g. `stty` in another terminal.
+                if terminal.is_some() {
+                    terminal = TerminalModes::save(io::stdin().as_raw_fd());
+                }
+                // TODO: make `ignore_dups` configurable with `HISTCONTROL`
+                if env.borrow().options.history {
+                    mysh::history::add_entry(rl.history_mut(), &line, true)?;
+                }
+                Hooks::pre_exec(&env, &line);
                 let context = ExecContext::new(rl.history_mut());
                 let ret = mysh::get_input_and_run(&line, Rc::clone(&env), context);
                 io::stdout().flush()?;
+                let status = match ret {
+                    CommandResult::Exit(status) => status,
+                    CommandResult::Normal => env.borrow().last_status,
+                };
+                Hooks::post_exec(&env, &line, status);
+                // Commands may leave the terminal unusable, e.g. `stty -echo`.
+                if let Some(terminal) = &terminal
+                    && let Err(e) = terminal.restore()
+                {
+                    eprintln!("mysh: can't restore the terminal: {}", e);
+                }
                 ret
             }
             Err(ReadlineError::Interrupted) => {
                 // When Ctrl-C is pressed, bash and zsh just set return code to 130 (INT).
                 // We follow their behavior here.
-
-                // TODO: Return code is not implemented yet.
+                env.borrow_mut().last_status = 128 + libc::SIGINT;
                 CommandResult::Normal
             }
             Err(ReadlineError::Eof) => {
                 // When Ctrl-D is pressed, bash and zsh just exit the shell.
                 // While bash prints "exit" before exiting, zsh does not.
-                // We follow zsh's behavior here.
-                CommandResult::Exit
+                // See `ShellOptions`.
+                if !env.borrow_mut().confirm_exit() {
+                    continue;
+                }
+                if env.borrow().options.posix {
+                    eprintln!("exit");
+                }
+                CommandResult::Exit(env.borrow().last_status)
             }
             Err(e) => {
                 return Err(anyhow::anyhow!(e));
             }
         };
 
-        if ret == CommandResult::Exit {
-            break;
+        if let CommandResult::Exit(status) = ret {
+            break status;
         }
-    }
+    };
 
+    // Other sessions may have written to the file since it was loaded, appending keeps
+    // their entries.
+    update_history(&mut rl, &mut loader, &mut histfile_path, true);
+    if let Some(histfile_path) = &histfile_path
+        && let Err(e) = mysh::history::append_history(rl.history_mut(), histfile_path)
     {
-        let histfile_path = mysh::get_histfile_path(env.borrow());
-        rl.save_history(&histfile_path)?;
+        eprintln!("mysh: {}: {}", histfile_path.display(), e);
     }
 
-    Ok(())
+    Ok(status)
 }
@@ -1,57 +1,319 @@
-use std::{collections::VecDeque, path::PathBuf};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Write,
+    iter::Peekable,
+    str::Chars,
+};
 
 use crate::{
-    execution::data::{CommandDescriptor, RawCommand},
-    redirect::{InputRedirect, OutputRedirect, Redirect, RedirectParseFragment, RedirectParseInfo},
+    brace,
+    execution::data::CommandDescriptor,
+    redirect::{RedirectParseFragment, RedirectParseInfo},
 };
 
-pub(crate) struct ParseData {
-    pub first_arg: Option<String>,
-    pub arguments: Vec<String>,
-    pub redirect: Redirect,
+/// A part of a word, which remembers whether it was quoted.
+///
+/// Quoting matters after parsing: unquoted parameters are split into multiple fields,
+/// while quoted ones are not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordPart {
+    /// Literal text. `quoted` is true if it comes from quotes or a backslash escape.
+    Text { value: String, quoted: bool },
+    /// Parameter expansion such as `$1`, `$@` or `${name}`.
+    Param { name: String, quoted: bool },
+    /// Parameter expansion with an operator, such as `${name:-word}`.
+    ParamOp {
+        name: String,
+        op: ParamOperator,
+        word: Word,
+        quoted: bool,
+    },
+    /// Command substitution `$(command)`. The command is kept as text, since it is parsed
+    /// when it runs.
+    Command { command: String, quoted: bool },
+    /// Arithmetic expansion `$((expr))`. The expression is expanded like a word in double
+    /// quotes, then evaluated, see `arith`.
+    Arith { expr: Word, quoted: bool },
+    /// Process substitution `<(command)`, or `>(command)` if `output` is true. It is never
+    /// in quotes, and the command is kept as text like `Command`.
+    Process { command: String, output: bool },
 }
 
-#[derive(Debug)]
-pub enum ParseFragment {
-    Argument(String),
-    Redirect(RedirectParseFragment),
-    Pipe,
+/// The operator of a `WordPart::ParamOp`. The parameter is null if it is unset or empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamOperator {
+    /// `${name:-word}` expands to `word` if the parameter is null.
+    UseDefault,
+    /// `${name:=word}` assigns `word` to the variable if it is null, then expands to it.
+    AssignDefault,
+    /// `${name:+word}` expands to `word` if the parameter is not null, otherwise nothing.
+    UseAlternative,
 }
 
-fn parse(mut fragments: VecDeque<ParseFragment>) -> VecDeque<CommandDescriptor> {
-    fn add_to_chain<F>(
-        exec_chain: &mut VecDeque<CommandDescriptor>,
-        data: ParseData,
-        constructor: F,
-    ) where
-        F: FnOnce(RawCommand) -> CommandDescriptor,
-    {
-        let exec = match RawCommand::from_parse_data(data) {
-            Some(exec) => exec,
-            None => return, // empty command, ignore
-        };
-        exec_chain.push_back(constructor(exec));
+impl ParamOperator {
+    fn as_str(self) -> &'static str {
+        match self {
+            ParamOperator::UseDefault => ":-",
+            ParamOperator::AssignDefault => ":=",
+            ParamOperator::UseAlternative => ":+",
+        }
     }
+}
 
-    let mut exec_chain: VecDeque<CommandDescriptor> = VecDeque::new();
+/// A word before expansion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    pub fn new() -> Self {
+        Self { parts: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
 
-    let mut partial_fragments = Vec::new();
-    while matches!(
-        fragments.front(),
-        Some(&ParseFragment::Argument(_) | &ParseFragment::Redirect(_))
+    pub fn clear(&mut self) {
+        self.parts.clear();
+    }
+
+    /// Push a character, merging it into the last text part if the quoting is the same.
+    pub fn push(&mut self, c: char, quoted: bool) {
+        if let Some(WordPart::Text { value, quoted: q }) = self.parts.last_mut()
+            && *q == quoted
+        {
+            value.push(c);
+            return;
+        }
+        self.parts.push(WordPart::Text {
+            value: c.to_string(),
+            quoted,
+        });
+    }
+
+    /// Push a run of characters which ends with `c`, merging it into the last text part if
+    /// the quoting is the same.
+    ///
+    /// The rest of the run is taken from `chars` while `in_run` returns true. Its length is
+    /// counted first, so that the text is allocated once instead of growing for every
+    /// character.
+    fn push_run(
+        &mut self,
+        c: char,
+        chars: &mut Peekable<Chars>,
+        in_run: impl Fn(char) -> bool,
+        quoted: bool,
     ) {
-        partial_fragments.push(fragments.pop_front().unwrap());
+        let mut len = c.len_utf8();
+        let mut lookahead = chars.clone();
+        while let Some(next) = lookahead.next_if(|&next| in_run(next)) {
+            len += next.len_utf8();
+        }
+
+        if let Some(WordPart::Text { value, quoted: q }) = self.parts.last_mut()
+            && *q == quoted
+        {
+            value.reserve(len);
+            value.push(c);
+            value.extend(std::iter::from_fn(|| chars.next_if(|&next| in_run(next))));
+            return;
+        }
+        let mut value = String::with_capacity(len);
+        value.push(c);
+        value.extend(std::iter::from_fn(|| chars.next_if(|&next| in_run(next))));
+        self.parts.push(WordPart::Text { value, quoted });
+    }
+
+    pub fn push_param(&mut self, name: String, quoted: bool) {
+        self.parts.push(WordPart::Param { name, quoted });
+    }
+
+    /// An unquoted word, such as an operator of `[[ ... ]]`.
+    fn unquoted(value: String) -> Self {
+        Self {
+            parts: vec![WordPart::Text {
+                value,
+                quoted: false,
+            }],
+        }
+    }
+
+    /// Mark the word as quoted, so that `''` still produces an empty word.
+    pub fn mark_quoted(&mut self) {
+        if self.parts.is_empty() {
+            self.parts.push(WordPart::Text {
+                value: String::new(),
+                quoted: true,
+            });
+        }
+    }
+
+    /// Returns the text if the word is a single unquoted text, which is the only case that
+    /// can be parsed as a fd.
+    pub fn as_unquoted_text(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [
+                WordPart::Text {
+                    value,
+                    quoted: false,
+                },
+            ] => Some(value),
+            _ => None,
+        }
     }
 
-    add_to_chain(
-        &mut exec_chain,
-        parse_to_data(partial_fragments),
-        CommandDescriptor::Begin,
-    );
+    /// Same as `to_text`, but moves the text out if there is a single text part.
+    pub fn into_text(mut self) -> String {
+        match self.parts.as_mut_slice() {
+            [WordPart::Text { value, .. }] => std::mem::take(value),
+            _ => self.to_text(),
+        }
+    }
+
+    /// Returns the word as it would look without quotes. Parameters are written back as
+    /// `$name`, or `${name:-word}` if there is an operator, commands as `$(command)`,
+    /// arithmetic as `$((expr))` and process substitutions as `<(command)`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                WordPart::Text { value, .. } => text.push_str(value),
+                WordPart::Param { name, .. } => {
+                    text.push('$');
+                    text.push_str(name);
+                }
+                WordPart::ParamOp { name, op, word, .. } => {
+                    text.push_str("${");
+                    text.push_str(name);
+                    text.push_str(op.as_str());
+                    text.push_str(&word.to_text());
+                    text.push('}');
+                }
+                WordPart::Command { command, .. } => {
+                    text.push_str("$(");
+                    text.push_str(command);
+                    text.push(')');
+                }
+                WordPart::Arith { expr, .. } => {
+                    text.push_str("$((");
+                    text.push_str(&expr.to_text());
+                    text.push_str("))");
+                }
+                WordPart::Process { command, output } => {
+                    text.push(if *output { '>' } else { '<' });
+                    text.push('(');
+                    text.push_str(command);
+                    text.push(')');
+                }
+            }
+        }
+        text
+    }
+}
 
-    while let Some(ParseFragment::Pipe) = fragments.front() {
-        fragments.pop_front();
+/// `name=value` or `name+=value` before a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+    /// `name+=value` appends the value to the current one.
+    pub append: bool,
+}
 
+/// Returns true if `name` can be used as a variable name.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Assignment {
+    /// Try to split a word like `name=value`. The name and `=` must not be quoted.
+    pub fn from_word(word: &Word) -> Option<Self> {
+        let (first, rest) = word.parts.split_first()?;
+        let WordPart::Text {
+            value,
+            quoted: false,
+        } = first
+        else {
+            return None;
+        };
+        let (name, value) = value.split_once('=')?;
+        let (name, append) = match name.strip_suffix('+') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        if !is_valid_name(name) {
+            return None;
+        }
+
+        let mut parts = Vec::with_capacity(word.parts.len());
+        if !value.is_empty() {
+            parts.push(WordPart::Text {
+                value: value.to_owned(),
+                quoted: false,
+            });
+        }
+        parts.extend_from_slice(rest);
+        Some(Self {
+            name: name.to_owned(),
+            value: Word { parts },
+            append,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RedirectWord {
+    pub fragment: RedirectParseFragment,
+    pub target: Word,
+}
+
+/// A command before expansion.
+#[derive(Debug, Clone, Default)]
+pub struct ParseData {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirect: Vec<RedirectWord>,
+    /// The pipeline starting with this command begins with `!`, which negates its status.
+    pub negate: bool,
+}
+
+#[derive(Debug)]
+pub enum ParseFragment {
+    Argument(Word),
+    Redirect(RedirectParseFragment),
+    Pipe,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `;`
+    Semicolon,
+    /// `&` at the end of a pipeline
+    Background,
+}
+
+/// Split the fragments into the commands of pipelines joined by `&&`, `||`, `;` and `&`.
+///
+/// Like bash, an operator without a command on either side is a syntax error, except that
+/// empty commands after `;` and `&` are skipped, like `echo a;; echo b` or `sleep 1 &`.
+fn parse_chain(
+    mut fragments: VecDeque<ParseFragment>,
+) -> Result<VecDeque<CommandDescriptor>, String> {
+    // `!` only negates a pipeline as a word of its own at its start, so `echo !` and
+    // `a | ! b` keep it as an argument
+    fn next_command(fragments: &mut VecDeque<ParseFragment>, pipeline_start: bool) -> ParseData {
+        let mut negate = false;
+        while pipeline_start
+            && let Some(ParseFragment::Argument(word)) = fragments.front()
+            && word.as_unquoted_text() == Some("!")
+        {
+            fragments.pop_front();
+            negate = !negate;
+        }
         let mut partial_fragments = Vec::new();
         while matches!(
             fragments.front(),
@@ -59,58 +321,93 @@ fn parse(mut fragments: VecDeque<ParseFragment>) -> VecDeque<CommandDescriptor>
         ) {
             partial_fragments.push(fragments.pop_front().unwrap());
         }
+        let mut data = parse_to_data(partial_fragments);
+        data.negate = negate;
+        data
+    }
 
-        add_to_chain(
-            &mut exec_chain,
-            parse_to_data(partial_fragments),
-            CommandDescriptor::Pipe,
+    // A command with only redirections, like `> file`, still creates the files, and one with
+    // only assignments sets the variables. `!` alone has the status 1, like bash.
+    let is_empty = |data: &ParseData| {
+        data.words.is_empty()
+            && data.redirect.is_empty()
+            && data.assignments.is_empty()
+            && !data.negate
+    };
+
+    let token = |fragment: &ParseFragment| match fragment {
+        ParseFragment::Pipe => "|",
+        ParseFragment::And => "&&",
+        ParseFragment::Or => "||",
+        ParseFragment::Semicolon => ";",
+        ParseFragment::Background => "&",
+        _ => unreachable!("Commands end at an operator."),
+    };
+
+    let mut exec_chain: VecDeque<CommandDescriptor> = VecDeque::new();
+    let mut descriptor: fn(ParseData) -> CommandDescriptor = CommandDescriptor::Begin;
+    // Set after `|`, `&&` and `||`, which need a command after them
+    let mut needs_command = false;
+    // `sleep 1 &; echo` is an error, like bash
+    let mut after_background = false;
+    let mut pipeline_start = true;
+    loop {
+        let data = next_command(&mut fragments, pipeline_start);
+        let operator = fragments.pop_front();
+        if is_empty(&data) {
+            return match operator {
+                Some(ParseFragment::Semicolon) if !needs_command && !after_background => continue,
+                Some(operator) => Err(format!(
+                    "syntax error near unexpected token `{}'",
+                    token(&operator)
+                )),
+                None if needs_command => Err(String::from("syntax error: unexpected end of file")),
+                None => Ok(exec_chain),
+            };
+        }
+        exec_chain.push_back(descriptor(data));
+        descriptor = match operator {
+            None => return Ok(exec_chain),
+            Some(ParseFragment::Pipe) => CommandDescriptor::Pipe,
+            Some(ParseFragment::And) => CommandDescriptor::And,
+            Some(ParseFragment::Or) => CommandDescriptor::Or,
+            Some(ParseFragment::Semicolon) => CommandDescriptor::Next,
+            Some(ParseFragment::Background) => {
+                exec_chain.push_back(CommandDescriptor::Background);
+                CommandDescriptor::Next
+            }
+            Some(_) => unreachable!("Commands end at an operator."),
+        };
+        needs_command = !matches!(
+            operator,
+            Some(ParseFragment::Semicolon | ParseFragment::Background)
         );
+        after_background = matches!(operator, Some(ParseFragment::Background));
+        pipeline_start = !matches!(operator, Some(ParseFragment::Pipe));
     }
-
-    exec_chain
 }
 
 // use `Result<ParseData, Error>` later
 fn parse_to_data(fragments: Vec<ParseFragment>) -> ParseData {
-    let mut first_arg: Option<String> = None;
-    let mut arguments: Vec<String> = Vec::new();
-    let mut redirect = Redirect::new();
+    let mut data = ParseData::default();
     let mut redirect_pending: Option<RedirectParseFragment> = None;
 
-    fn update_args(first_arg: &mut Option<String>, arguments: &mut Vec<String>, arg: String) {
-        if first_arg.is_none() {
-            first_arg.replace(arg);
-        } else {
-            arguments.push(arg);
-        }
-    }
-
-    fn add_redirect(redirect: &mut Redirect, rfrag: RedirectParseFragment, next_frag: String) {
-        if rfrag.is_input {
-            let mut input_redirect = InputRedirect::new(PathBuf::from(next_frag));
-            input_redirect.set_fd(rfrag.fd);
-            redirect.push_input(input_redirect);
-        } else {
-            let mut output_redirect = OutputRedirect::new(PathBuf::from(next_frag));
-            output_redirect.set_append(rfrag.append);
-            output_redirect.set_fd(rfrag.fd);
-            redirect.push_output(output_redirect);
-        }
-    }
-
     for frag in fragments {
         match frag {
             ParseFragment::Argument(arg) => {
                 match redirect_pending.take() {
                     // normal argument
                     None => {
-                        update_args(&mut first_arg, &mut arguments, arg);
+                        data.words.push(arg);
                     }
                     // filename for redirect
                     Some(rfrag) => {
                         // Sepcial condition when parsing "> file echo value"
                         // For zsh, this will redirect stdout to file, then execute "echo value"
-                        add_redirect(&mut redirect, rfrag, arg);
+                        data.redirect.push(RedirectWord {
+                            fragment: rfrag,
+                            target: arg,
+                        });
                     }
                 }
             }
@@ -119,78 +416,493 @@ fn parse_to_data(fragments: Vec<ParseFragment>) -> ParseData {
                 // This is a syntax error in real shell, but we just ignore it here.
                 redirect_pending.replace(rfrag);
             }
-            ParseFragment::Pipe => {
+            ParseFragment::Pipe
+            | ParseFragment::And
+            | ParseFragment::Or
+            | ParseFragment::Semicolon
+            | ParseFragment::Background => {
                 // This should not happen.
-                unreachable!("Pipe should be handled in this function.");
+                unreachable!("Operators should be handled in parse_chain.");
+            }
+        }
+    }
+
+    // `name=value` before the command name are assignments for the command. If there is no
+    // command after them, they set shell variables.
+    let assignments: Vec<_> = data.words.iter().map_while(Assignment::from_word).collect();
+    data.words.drain(..assignments.len());
+    data.assignments = assignments;
+
+    // Braces are expanded in the words of a command, but not in assignments or in
+    // `[[ ... ]]`, like bash
+    if data.words.first().and_then(Word::as_unquoted_text) != Some("[[") {
+        data.words = data.words.iter().flat_map(brace::expand).collect();
+    }
+
+    data
+}
+
+/// Try to parse the name of a parameter after `$`: a positional or special parameter, or the
+/// name of a variable.
+fn parse_param_name(chars: &mut Peekable<Chars>) -> Option<String> {
+    match chars.peek() {
+        Some(&c) if c.is_ascii_digit() || matches!(c, '#' | '@' | '*' | '$' | '-' | '?') => {
+            chars.next();
+            Some(c.to_string())
+        }
+        Some(&c) if c.is_ascii_alphabetic() || c == '_' => {
+            // The whole name is read, so `$PPIDX` is the variable `PPIDX`
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|&c| c.is_ascii_alphanumeric() || c == '_') {
+                name.push(c);
+            }
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+const UNTERMINATED_BRACE: &str = "unexpected EOF while looking for matching `}'";
+const UNTERMINATED_PAREN: &str = "unexpected EOF while looking for matching `)'";
+
+/// Parse what follows `$` and push it to `word`: `${...}`, `$((...))`, `$(...)`, a
+/// parameter, or a literal `$` if none of them follows.
+fn parse_dollar(chars: &mut Peekable<Chars>, word: &mut Word, quoted: bool) -> Result<(), String> {
+    if chars.next_if_eq(&'{').is_some() {
+        word.parts.push(parse_braced_param(chars, quoted)?);
+        return Ok(());
+    }
+    if chars.next_if_eq(&'(').is_some() {
+        if chars.next_if_eq(&'(').is_some() {
+            let expr = parse_arith(chars)?;
+            word.parts.push(WordPart::Arith { expr, quoted });
+            return Ok(());
+        }
+        let command = parse_command_substitution(chars)?;
+        word.parts.push(WordPart::Command { command, quoted });
+        return Ok(());
+    }
+    match parse_param_name(chars) {
+        Some(name) => word.push_param(name, quoted),
+        None => word.push('$', quoted),
+    }
+    Ok(())
+}
+
+/// Parse `${...}` after the `{`.
+///
+/// Unlike `$10`, which is `$1` followed by `0`, all digits are read in `${10}`.
+fn parse_braced_param(chars: &mut Peekable<Chars>, quoted: bool) -> Result<WordPart, String> {
+    let name = match chars.peek() {
+        Some(c) if c.is_ascii_digit() => {
+            std::iter::from_fn(|| chars.next_if(char::is_ascii_digit)).collect()
+        }
+        _ => parse_param_name(chars).unwrap_or_default(),
+    };
+
+    let op = match (chars.next(), chars.peek()) {
+        (Some('}'), _) if !name.is_empty() => return Ok(WordPart::Param { name, quoted }),
+        (Some(':'), Some('-')) if !name.is_empty() => ParamOperator::UseDefault,
+        (Some(':'), Some('=')) if !name.is_empty() => ParamOperator::AssignDefault,
+        (Some(':'), Some('+')) if !name.is_empty() => ParamOperator::UseAlternative,
+        (Some(c), _) => {
+            // Read the rest for the message, like bash
+            let mut rest = String::new();
+            let mut next = Some(c);
+            loop {
+                match next {
+                    Some('}') => break,
+                    Some(c) => rest.push(c),
+                    None => return Err(UNTERMINATED_BRACE.to_owned()),
+                }
+                next = chars.next();
+            }
+            return Err(format!("${{{name}{rest}}}: bad substitution"));
+        }
+        (None, _) => return Err(UNTERMINATED_BRACE.to_owned()),
+    };
+    chars.next();
+    if op == ParamOperator::AssignDefault && !is_valid_name(&name) {
+        return Err(format!("${name}: cannot assign in this way"));
+    }
+
+    let word = parse_braced_word(chars, quoted)?;
+    Ok(WordPart::ParamOp {
+        name,
+        op,
+        word,
+        quoted,
+    })
+}
+
+/// Read the command of `$(...)`, `<(...)` or `>(...)` after the `(` up to the matching `)`.
+///
+/// Parentheses are balanced, so `$(echo $(echo hi))` is one substitution, while quotes and
+/// backslashes are skipped over, so `$(echo ")")` is too. The text is kept as it is.
+fn parse_command_substitution(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut command = String::new();
+    let mut depth = 0;
+    let mut single_quote = false;
+    let mut double_quote = false;
+    loop {
+        let c = chars.next().ok_or_else(|| UNTERMINATED_PAREN.to_owned())?;
+        match c {
+            '\'' if !double_quote => single_quote = !single_quote,
+            _ if single_quote => {}
+            '\\' => {
+                command.push(c);
+                let c = chars.next().ok_or_else(|| UNTERMINATED_PAREN.to_owned())?;
+                command.push(c);
+                continue;
+            }
+            '"' => double_quote = !double_quote,
+            // A nested substitution in double quotes has quotes of its own
+            '$' if double_quote && chars.peek() == Some(&'(') => {
+                chars.next();
+                command.push_str("$(");
+                command.push_str(&parse_command_substitution(chars)?);
+                command.push(')');
+                continue;
+            }
+            _ if double_quote => {}
+            '(' => depth += 1,
+            ')' if depth == 0 => return Ok(command),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        command.push(c);
+    }
+}
+
+/// Parse the expression of `$((...))` after the `((` up to the matching `))`. Like in
+/// double quotes, parameters and commands are expanded, and a backslash only escapes
+/// special characters.
+fn parse_arith(chars: &mut Peekable<Chars>) -> Result<Word, String> {
+    let mut expr = Word::new();
+    // Parentheses of the expression itself, like `$(((1 + 2) * 3))`
+    let mut depth = 0;
+    loop {
+        let c = chars.next().ok_or_else(|| UNTERMINATED_PAREN.to_owned())?;
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                return match chars.next() {
+                    Some(')') => Ok(expr),
+                    Some(c) => Err(format!("syntax error near unexpected token `{}'", c)),
+                    None => Err(UNTERMINATED_PAREN.to_owned()),
+                };
+            }
+            ')' => depth -= 1,
+            '$' => {
+                parse_dollar(chars, &mut expr, true)?;
+                continue;
+            }
+            '\\' => {
+                let c = chars.next().ok_or_else(|| UNTERMINATED_PAREN.to_owned())?;
+                if !matches!(c, '"' | '\\' | '$' | '`') {
+                    expr.push('\\', true);
+                }
+                expr.push(c, true);
+                continue;
+            }
+            _ => {}
+        }
+        expr.push(c, true);
+    }
+}
+
+/// Parse the word of `${name:-word}` up to the matching `}`. The word may contain quotes
+/// and other expansions, including nested `${...}`.
+fn parse_braced_word(chars: &mut Peekable<Chars>, quoted: bool) -> Result<Word, String> {
+    let mut word = Word::new();
+    let mut single_quote = false;
+    let mut double_quote = false;
+    loop {
+        let c = chars.next().ok_or_else(|| UNTERMINATED_BRACE.to_owned())?;
+        if single_quote {
+            if c == '\'' {
+                single_quote = false;
+            } else {
+                word.push(c, true);
+            }
+            continue;
+        }
+
+        let in_quotes = quoted || double_quote;
+        match c {
+            '}' if !double_quote => return Ok(word),
+            '\\' => {
+                let c = chars.next().ok_or_else(|| UNTERMINATED_BRACE.to_owned())?;
+                // Like in double quotes, a backslash is kept unless it escapes a special
+                // character.
+                if in_quotes && !matches!(c, '"' | '\\' | '$' | '`' | '}') {
+                    word.push('\\', true);
+                }
+                word.push(c, true);
+            }
+            // Single quotes are literal in double quotes
+            '\'' if !in_quotes => {
+                single_quote = true;
+                word.mark_quoted();
+            }
+            '"' => {
+                double_quote = !double_quote;
+                word.mark_quoted();
             }
+            '$' => parse_dollar(chars, &mut word, in_quotes)?,
+            _ => word.push(c, in_quotes),
+        }
+    }
+}
+
+/// Returns true if `c` has no special meaning outside quotes.
+fn is_plain(c: char) -> bool {
+    !c.is_whitespace() && !matches!(c, '\\' | '\'' | '"' | '$' | '>' | '<' | '|' | '&' | ';')
+}
+
+/// Returns true if the input ends with a backslash outside single quotes, which continues
+/// the line, a quote is not closed, or a here-document has no delimiter yet, so more input
+/// should be read before running it.
+pub fn is_incomplete(input: &str) -> bool {
+    let mut single_quote = false;
+    let mut double_quote = false;
+    let mut backslash = false;
+    // A backslash in a comment doesn't continue the line
+    let mut comment = false;
+    let mut word_start = true;
+    for c in input.chars() {
+        if backslash {
+            backslash = false;
+            word_start = false;
+            continue;
+        }
+        if comment {
+            comment = c != '\n';
+            word_start = !comment;
+            continue;
+        }
+        let quoted = single_quote || double_quote;
+        let at_word_start = std::mem::replace(
+            &mut word_start,
+            !quoted && (c.is_whitespace() || matches!(c, '|' | '&')),
+        );
+        match c {
+            '#' if !quoted && at_word_start => comment = true,
+            '\'' if !double_quote => single_quote = !single_quote,
+            '"' if !single_quote => double_quote = !double_quote,
+            '\\' if !single_quote => backslash = true,
+            _ => {}
+        }
+    }
+    if backslash {
+        return true;
+    }
+    let mut unterminated = false;
+    parse_fragments(input, &mut unterminated).is_ok() && unterminated
+}
+
+/// Update the state of `[[ ... ]]` after a word is added to `fragments`.
+///
+/// `[[` starts a conditional command if it is the first word of a command, and `]]` ends
+/// it. Inside it, the word after `=~` is a regular expression, see `parse_to_fragments`.
+fn update_conditional(
+    fragments: &VecDeque<ParseFragment>,
+    conditional: &mut bool,
+    regex_word: &mut bool,
+) {
+    let last = |i: usize| match fragments.iter().rev().nth(i) {
+        Some(ParseFragment::Argument(word)) => word.as_unquoted_text(),
+        _ => None,
+    };
+    if *conditional {
+        *conditional = last(0) != Some("]]");
+        *regex_word = *conditional && last(0) == Some("=~");
+    } else if last(0) == Some("[[") {
+        // `[[` is the command name, which may come after `!`
+        let before = fragments
+            .iter()
+            .rev()
+            .skip(1)
+            .find(|fragment| match fragment {
+                ParseFragment::Argument(word) => word.as_unquoted_text() != Some("!"),
+                _ => true,
+            });
+        *conditional = matches!(
+            before,
+            None | Some(
+                ParseFragment::Pipe
+                    | ParseFragment::And
+                    | ParseFragment::Or
+                    | ParseFragment::Semicolon
+                    | ParseFragment::Background
+            )
+        );
+    }
+}
+
+/// Parse the body of a here-document from the lines in `chars`, up to a line equal to
+/// `delimiter`. Returns the body as a word, and whether the delimiter was missing.
+///
+/// Like bash, the body is literal if any part of the delimiter is quoted, like `<< 'EOF'`.
+/// Otherwise parameters, commands and arithmetic are expanded, and a backslash only escapes
+/// `$`, `` ` ``, `\` and newline, like in double quotes without `"` being special.
+fn parse_heredoc(
+    chars: &mut Peekable<Chars>,
+    delimiter: &Word,
+    strip_tabs: bool,
+) -> Result<(Word, bool), String> {
+    let literal = delimiter.parts.iter().any(|part| match part {
+        WordPart::Text { quoted, .. } => *quoted,
+        _ => false,
+    });
+    let delimiter = delimiter.to_text();
+
+    let mut body = String::new();
+    let mut unterminated = true;
+    while chars.peek().is_some() {
+        let mut line: String = std::iter::from_fn(|| chars.next_if(|&c| c != '\n')).collect();
+        chars.next();
+        if strip_tabs {
+            line = line.trim_start_matches('\t').to_owned();
+        }
+        if line == delimiter {
+            unterminated = false;
+            break;
+        }
+        body.push_str(&line);
+        body.push('\n');
+    }
+
+    let mut word = Word::new();
+    word.mark_quoted();
+    if literal {
+        for c in body.chars() {
+            word.push(c, true);
+        }
+        return Ok((word, unterminated));
+    }
+    let mut body = body.chars().peekable();
+    while let Some(c) = body.next() {
+        match c {
+            '\\' => match body.next() {
+                Some('\n') => {}
+                Some(c @ ('$' | '`' | '\\')) => word.push(c, true),
+                Some(c) => {
+                    word.push('\\', true);
+                    word.push(c, true);
+                }
+                None => word.push('\\', true),
+            },
+            '$' => parse_dollar(&mut body, &mut word, true)?,
+            _ => word.push_run(c, &mut body, |c| !matches!(c, '\\' | '$'), true),
         }
     }
+    Ok((word, unterminated))
+}
 
-    ParseData {
-        first_arg,
-        arguments,
-        redirect,
+/// Read the bodies of the here-documents in `fragments` after the first `from`, which is
+/// moved past them. The delimiter after each `<<` is replaced with the body. Returns true if
+/// the input ended before a delimiter.
+fn read_heredocs(
+    fragments: &mut VecDeque<ParseFragment>,
+    from: &mut usize,
+    chars: &mut Peekable<Chars>,
+) -> Result<bool, String> {
+    let mut unterminated = false;
+    while *from < fragments.len() {
+        let strip_tabs = match &fragments[*from] {
+            ParseFragment::Redirect(frag) if frag.heredoc => frag.strip_tabs,
+            _ => {
+                *from += 1;
+                continue;
+            }
+        };
+        let Some(ParseFragment::Argument(delimiter)) = fragments.get_mut(*from + 1) else {
+            return Err(String::from("syntax error near unexpected token `newline'"));
+        };
+        let (body, missing) = parse_heredoc(chars, delimiter, strip_tabs)?;
+        *delimiter = body;
+        unterminated |= missing;
+        *from += 2;
     }
+    Ok(unterminated)
+}
+
+/// Split the input into fragments. Returns the syntax error if there is an unterminated or
+/// invalid `${...}`, or an unterminated `[[`.
+///
+/// The lines after a command with here-documents are their bodies. If the input ends
+/// before a delimiter, the body is taken as it is, like bash.
+pub fn parse_to_fragments(input: &str) -> Result<VecDeque<ParseFragment>, String> {
+    parse_fragments(input, &mut false)
 }
 
-/// TODO: handle multi-line input
-pub(crate) fn parse_to_fragments(input: &str) -> VecDeque<ParseFragment> {
-    let mut fragments: VecDeque<ParseFragment> = VecDeque::new();
+/// Same as `parse_to_fragments`, and sets `unterminated` if a here-document has no
+/// delimiter.
+fn parse_fragments(
+    input: &str,
+    unterminated: &mut bool,
+) -> Result<VecDeque<ParseFragment>, String> {
+    // Most words are separated by whitespace, so the fragments rarely grow for long lines
+    let words = input.bytes().filter(u8::is_ascii_whitespace).count() + 1;
+    let mut fragments: VecDeque<ParseFragment> = VecDeque::with_capacity(words);
     // To build the current fragment
-    let mut str_builder = String::new();
+    let mut word = Word::new();
     // To handle single quotes
     let mut single_quote = false;
     // To handle double quotes
     let mut double_quote = false;
     // To handle backslashes
-    // TODO: handle backslashes with newline
     let mut backslash = false;
     // To handle redirections
     let mut redirect_info: Option<RedirectParseInfo> = None;
+    // To handle `[[ ... ]]`, see `update_conditional`
+    let mut conditional = false;
+    let mut regex_word = false;
+    // The fragments before it have no here-documents waiting for their bodies
+    let mut heredocs = 0;
 
-    fn update_args(fragments: &mut VecDeque<ParseFragment>, str_builder: &mut String) {
-        if str_builder.is_empty() {
+    fn update_args(fragments: &mut VecDeque<ParseFragment>, word: &mut Word) {
+        if word.is_empty() {
             return;
         }
-        fragments.push_back(ParseFragment::Argument(str_builder.clone()));
-        str_builder.clear();
+        fragments.push_back(ParseFragment::Argument(std::mem::take(word)));
     }
 
     fn add_redirect(
         fragments: &mut VecDeque<ParseFragment>,
         info: &RedirectParseInfo,
-        str_builder: &mut String,
+        word: &mut Word,
     ) {
-        let frag = RedirectParseFragment::build(info, str_builder.clone());
-        str_builder.clear();
+        let frag = RedirectParseFragment::build(info, std::mem::take(word).into_text());
         fragments.push_back(ParseFragment::Redirect(frag));
     }
 
-    for c in input.chars() {
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
         // Handle single quotes
         if single_quote {
             if c == '\'' {
                 single_quote = false;
                 continue;
             }
-            str_builder.push(c);
+            word.push_run(c, &mut chars, |c| c != '\'', true);
             continue;
         }
 
         if double_quote {
             // Within double quotes, a backslash only escapes certain special characters:
             // `"`, `\`, `$`, ```, and newline. For all other characters, the backslash is treated
-            // literally.
-            //
-            // "\n" is still newline, but we don't handle it now.
+            // literally. An escaped newline continues the line, so both are removed.
             if backslash {
                 match c {
-                    '"' | '\\' | '$' | '`' => {}
+                    '\n' => {}
+                    '"' | '\\' | '$' | '`' => word.push(c, true),
                     _ => {
-                        str_builder.push('\\');
+                        word.push('\\', true);
+                        word.push(c, true);
                     }
                 }
-                str_builder.push(c);
                 backslash = false;
                 continue;
             }
@@ -198,17 +910,46 @@ pub(crate) fn parse_to_fragments(input: &str) -> VecDeque<ParseFragment> {
             match c {
                 '"' => double_quote = false,
                 '\\' => backslash = true,
-                _ => str_builder.push(c),
+                '$' => parse_dollar(&mut chars, &mut word, true)?,
+                _ => word.push_run(c, &mut chars, |c| !matches!(c, '"' | '\\' | '$'), true),
             }
             continue;
         }
 
         if backslash {
-            str_builder.push(c);
+            // An escaped newline continues the line, so both are removed.
+            if c != '\n' {
+                word.push(c, true);
+            }
             backslash = false;
             continue;
         }
 
+        // Inside `[[ ... ]]`, operators are words, and `<` and `>` compare strings. Like bash,
+        // they are kept in the regular expression after `=~`, such as `(a|b)`.
+        if conditional && !regex_word {
+            match c {
+                '(' | ')' | '<' | '>' => {
+                    update_args(&mut fragments, &mut word);
+                    fragments.push_back(ParseFragment::Argument(Word::unquoted(c.to_string())));
+                    continue;
+                }
+                '&' | '|' => {
+                    update_args(&mut fragments, &mut word);
+                    let mut op = c.to_string();
+                    if chars.next_if_eq(&c).is_some() {
+                        op.push(c);
+                    }
+                    fragments.push_back(ParseFragment::Argument(Word::unquoted(op)));
+                    continue;
+                }
+                _ => {}
+            }
+        } else if regex_word && matches!(c, '(' | ')' | '<' | '>' | '&' | '|') {
+            word.push(c, false);
+            continue;
+        }
+
         // Things after `>` or `<`
         // for example:
         // ```
@@ -220,17 +961,14 @@ pub(crate) fn parse_to_fragments(input: &str) -> VecDeque<ParseFragment> {
         // ```
         if let Some(info) = redirect_info.as_mut() {
             match c {
-                '\\' => backslash = true,
-                '\'' => single_quote = true,
-                '\"' => double_quote = true,
                 '>' => {
                     if info.is_input {
                         // Previous is input redirection
                         // This only occurs when the input is "<>"
                         // We parse it as two separate redirections
-                        add_redirect(&mut fragments, info, &mut str_builder);
+                        add_redirect(&mut fragments, info, &mut word);
                         redirect_info = Some(RedirectParseInfo::new_output());
-                        // We don't need to parse fd again, because str_builder is cleared
+                        // We don't need to parse fd again, because word is cleared
                     } else if info.append_pending {
                         // probably `>>`
                         info.append = true;
@@ -241,95 +979,439 @@ pub(crate) fn parse_to_fragments(input: &str) -> VecDeque<ParseFragment> {
                         //
                         // !info.append_pending => a new output redirection
                         // This only occurs when the input is ">>>"
-                        add_redirect(&mut fragments, info, &mut str_builder);
+                        add_redirect(&mut fragments, info, &mut word);
                         redirect_info = Some(RedirectParseInfo::new_output());
                     }
-                    str_builder.push(c); // for RedirectParseFragment.value
+                    word.push(c, false); // for RedirectParseFragment.value
+                    continue;
+                }
+                '&' if !info.dup && !info.here_string && !info.heredoc => {
+                    // `>&1` or `<&0`
+                    info.dup = true;
+                    info.append_pending = false;
+                    word.push(c, false); // for RedirectParseFragment.value
                     continue;
                 }
                 '<' => {
                     // A new input redirection, we don't care about previous one.
-                    add_redirect(&mut fragments, info, &mut str_builder);
+                    add_redirect(&mut fragments, info, &mut word);
                     redirect_info = Some(RedirectParseInfo::new_input());
-                    str_builder.push(c); // for RedirectParseFragment.value
-                    continue;
-                }
-                _ if c.is_whitespace() => {
-                    add_redirect(&mut fragments, info, &mut str_builder);
-                    redirect_info = None;
+                    word.push(c, false); // for RedirectParseFragment.value
                     continue;
                 }
                 _ => {
-                    add_redirect(&mut fragments, info, &mut str_builder);
+                    // The redirection symbol ends here, the character is handled as usual.
+                    add_redirect(&mut fragments, info, &mut word);
                     redirect_info = None;
-                    str_builder.push(c);
-                    continue;
                 }
             }
-            continue;
         }
 
         fn try_parse_redirect_fd(
             fragments: &mut VecDeque<ParseFragment>,
-            str_builder: &mut String,
+            word: &mut Word,
             redirect_info: &mut RedirectParseInfo,
         ) {
-            if !str_builder.is_empty() {
-                let maybe_fd = str_builder.parse::<i32>();
-                if let Ok(fd) = maybe_fd {
-                    // can parse as fd, make it as redirect fd
-                    redirect_info.fd = Some(fd);
-                } else {
-                    // probably "value>"
-                    // In this case, we treat it as normal argument
-                    let redirect_symbol = str_builder.pop().unwrap(); // should be '>' or '<'
-                    update_args(fragments, str_builder);
-                    str_builder.push(redirect_symbol);
-                }
-                // We don't clear str_builder here, because we may need it later
+            if word.is_empty() {
+                return;
+            }
+            // A quoted word is never a fd, e.g. `'2'>file`
+            match word.as_unquoted_text().map(str::parse::<i32>) {
+                // can parse as fd, make it as redirect fd
+                // We don't clear word here, because it is a part of RedirectParseFragment.value
+                Some(Ok(fd)) => redirect_info.fd = Some(fd),
+                // probably "value>"
+                // In this case, we treat it as normal argument
+                _ => update_args(fragments, word),
             }
         }
 
         match c {
             '\\' => backslash = true,
-            '\'' => single_quote = true,
-            '"' => double_quote = true,
+            '\'' => {
+                single_quote = true;
+                word.mark_quoted();
+            }
+            '"' => {
+                double_quote = true;
+                word.mark_quoted();
+            }
+            '$' => parse_dollar(&mut chars, &mut word, false)?,
+            '<' | '>' if chars.next_if_eq(&'(').is_some() => {
+                let command = parse_command_substitution(&mut chars)?;
+                let output = c == '>';
+                word.parts.push(WordPart::Process { command, output });
+            }
             '>' => {
                 let mut info = RedirectParseInfo::new_output();
-                try_parse_redirect_fd(&mut fragments, &mut str_builder, &mut info);
+                try_parse_redirect_fd(&mut fragments, &mut word, &mut info);
                 redirect_info = Some(info);
-                str_builder.push(c); // for RedirectParseFragment.value
+                word.push(c, false); // for RedirectParseFragment.value
             }
             '<' => {
                 let mut info = RedirectParseInfo::new_input();
-                try_parse_redirect_fd(&mut fragments, &mut str_builder, &mut info);
+                try_parse_redirect_fd(&mut fragments, &mut word, &mut info);
+                word.push(c, false); // for RedirectParseFragment.value
+                // `<<< text`, or a here-document `<< EOF` or `<<- EOF`
+                let mut lookahead = chars.clone();
+                match (lookahead.next(), lookahead.next()) {
+                    (Some('<'), Some('<')) => {
+                        chars.nth(1);
+                        info.here_string = true;
+                        word.push('<', false);
+                        word.push('<', false);
+                    }
+                    (Some('<'), next) => {
+                        chars.next();
+                        info.heredoc = true;
+                        word.push('<', false);
+                        if next == Some('-') {
+                            chars.next();
+                            info.strip_tabs = true;
+                            word.push('-', false);
+                        }
+                    }
+                    _ => {}
+                }
                 redirect_info = Some(info);
-                str_builder.push(c); // for RedirectParseFragment.value
+            }
+            '&' if chars.next_if_eq(&'&').is_some() => {
+                update_args(&mut fragments, &mut word);
+                fragments.push_back(ParseFragment::And);
+            }
+            '&' if chars.peek() == Some(&'>') => {
+                // `&>file` or `&>>file`
+                update_args(&mut fragments, &mut word);
+                let mut info = RedirectParseInfo::new_output();
+                info.both = true;
+                redirect_info = Some(info);
+                chars.next();
+                word.push(c, false); // for RedirectParseFragment.value
+                word.push('>', false);
+            }
+            '&' => {
+                update_args(&mut fragments, &mut word);
+                update_conditional(&fragments, &mut conditional, &mut regex_word);
+                fragments.push_back(ParseFragment::Background);
+            }
+            // An unquoted `#` at the start of a word begins a comment, which runs to the end
+            // of the line, while `foo#bar` keeps it
+            '#' if word.is_empty() => while chars.next_if(|&c| c != '\n').is_some() {},
+            ';' => {
+                update_args(&mut fragments, &mut word);
+                update_conditional(&fragments, &mut conditional, &mut regex_word);
+                if conditional {
+                    return Err(String::from(
+                        "syntax error in conditional expression: unexpected token `;'",
+                    ));
+                }
+                fragments.push_back(ParseFragment::Semicolon);
             }
             '|' => {
-                // TODO: || should be operator OR in shell, but we don't support it now,
-                // so we just treat it as two separate pipes.
-                update_args(&mut fragments, &mut str_builder);
-                fragments.push_back(ParseFragment::Pipe);
+                update_args(&mut fragments, &mut word);
+                if chars.next_if_eq(&'|').is_some() {
+                    fragments.push_back(ParseFragment::Or);
+                } else {
+                    fragments.push_back(ParseFragment::Pipe);
+                }
             }
             _ if c.is_whitespace() => {
-                update_args(&mut fragments, &mut str_builder);
+                update_args(&mut fragments, &mut word);
+                update_conditional(&fragments, &mut conditional, &mut regex_word);
+                // The bodies of here-documents start on the next line
+                if c == '\n' {
+                    *unterminated |= read_heredocs(&mut fragments, &mut heredocs, &mut chars)?;
+                }
             }
-            _ => str_builder.push(c),
+            _ if conditional && !regex_word => {
+                let in_run = |c| is_plain(c) && !matches!(c, '(' | ')');
+                word.push_run(c, &mut chars, in_run, false);
+            }
+            _ => word.push_run(c, &mut chars, is_plain, false),
         }
     }
 
+    // There is no more input to continue the line, so the backslash is kept like zsh.
+    // Interactively, `is_incomplete` asks for more input instead.
+    if backslash {
+        word.push('\\', true);
+    }
+
     // Don't forget the last fragment
     if let Some(info) = redirect_info.as_mut() {
-        add_redirect(&mut fragments, info, &mut str_builder);
+        add_redirect(&mut fragments, info, &mut word);
+    }
+
+    update_args(&mut fragments, &mut word);
+    update_conditional(&fragments, &mut conditional, &mut regex_word);
+    // Without more input, an unterminated quote ends with it. Interactively,
+    // `is_incomplete` asks for more input instead.
+    *unterminated |= single_quote || double_quote;
+    *unterminated |= read_heredocs(&mut fragments, &mut heredocs, &mut chars)?;
+    if conditional {
+        return Err(String::from(
+            "unexpected EOF while looking for matching `]]'",
+        ));
+    }
+
+    Ok(fragments)
+}
+
+/// Parse a command line into a pipeline, expanding the `aliases`. Returns the syntax error
+/// if it is invalid.
+pub(crate) fn parse_command(
+    input: &str,
+    aliases: &HashMap<String, String>,
+) -> Result<VecDeque<CommandDescriptor>, String> {
+    let mut fragments = parse_to_fragments(input)?;
+    if !aliases.is_empty() {
+        fragments = expand_aliases(fragments, aliases, &mut Vec::new())?;
+    }
+    parse_chain(fragments)
+}
+
+/// Replace the names of aliases at the start of commands with the fragments of their
+/// values. A quoted name, like `\ll`, is not expanded.
+///
+/// Like bash, an alias is not expanded again in its own value, so `alias ls='ls -F'`
+/// doesn't loop, and if the value ends with a blank the next word is expanded too, like
+/// `alias sudo='sudo '`. `expanding` are the names of the aliases being expanded.
+fn expand_aliases(
+    fragments: VecDeque<ParseFragment>,
+    aliases: &HashMap<String, String>,
+    expanding: &mut Vec<String>,
+) -> Result<VecDeque<ParseFragment>, String> {
+    let mut expanded = VecDeque::with_capacity(fragments.len());
+    let mut command_start = true;
+    for fragment in fragments {
+        let alias = match &fragment {
+            ParseFragment::Argument(word) if command_start => word
+                .as_unquoted_text()
+                .filter(|name| !expanding.iter().any(|n| n == name))
+                .and_then(|name| aliases.get_key_value(name)),
+            _ => None,
+        };
+        if let Some((name, value)) = alias {
+            expanding.push(name.clone());
+            let value_fragments = parse_to_fragments(value)
+                .and_then(|fragments| expand_aliases(fragments, aliases, expanding));
+            expanding.pop();
+            expanded.extend(value_fragments?);
+            command_start = value.ends_with([' ', '\t']);
+            continue;
+        }
+        command_start = match &fragment {
+            // `!` is not a command name, so `! ll` is expanded
+            ParseFragment::Argument(word) => command_start && word.as_unquoted_text() == Some("!"),
+            ParseFragment::Redirect(_) => false,
+            _ => true,
+        };
+        expanded.push_back(fragment);
+    }
+    Ok(expanded)
+}
+
+/// A redirection of a `ParsedCommand`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedRedirect {
+    pub fd: i32,
+    pub is_input: bool,
+    pub append: bool,
+    /// `fd` becomes a copy of the fd `target`, like `2>&1`.
+    pub dup: bool,
+    /// Both stdout and stderr are redirected, like `&>file`.
+    pub both: bool,
+    /// The target is the text to read, like `<<< text`.
+    pub here_string: bool,
+    /// The target is the body of a here-document, like `<< EOF`.
+    pub heredoc: bool,
+    pub target: Word,
+}
+
+/// How a command is joined to the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    /// `|`, in the same pipeline.
+    Pipe,
+    /// `&&`, it runs if the pipeline before succeeds.
+    And,
+    /// `||`, it runs if the pipeline before fails.
+    Or,
+    /// `;`, it runs after the pipeline before.
+    Semicolon,
+}
+
+impl Connector {
+    fn as_str(self) -> &'static str {
+        match self {
+            Connector::Pipe => "|",
+            Connector::And => "&&",
+            Connector::Or => "||",
+            Connector::Semicolon => ";",
+        }
     }
+}
 
-    update_args(&mut fragments, &mut str_builder);
+/// A command as it is parsed, before expansion.
+///
+/// This is a view of the parser output for tools which analyze or rewrite command lines
+/// without executing them. Words keep their quoting, use `Word::to_text` for the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedCommand {
+    /// `None` for the first command.
+    pub connector: Option<Connector>,
+    /// The pipeline starting with this command begins with `!`, which negates its status.
+    pub negated: bool,
+    /// `name=value` before the command name.
+    pub assignments: Vec<Assignment>,
+    /// `None` if there are only redirections, like `> file`.
+    pub name: Option<Word>,
+    pub args: Vec<Word>,
+    /// Redirections in the order they appear.
+    pub redirects: Vec<ParsedRedirect>,
+    /// The pipeline ends with `&` after this command, and runs in the background.
+    pub background: bool,
+}
 
-    fragments
+impl ParsedCommand {
+    fn from_data(connector: Option<Connector>, data: &ParseData) -> Self {
+        let (name, args) = match data.words.split_first() {
+            Some((name, args)) => (Some(name.clone()), args.to_vec()),
+            None => (None, Vec::new()),
+        };
+        Self {
+            connector,
+            negated: data.negate,
+            assignments: data.assignments.clone(),
+            name,
+            args,
+            redirects: data
+                .redirect
+                .iter()
+                .map(|r| ParsedRedirect {
+                    fd: r.fragment.fd,
+                    is_input: r.fragment.is_input,
+                    append: r.fragment.append,
+                    dup: r.fragment.dup,
+                    both: r.fragment.both,
+                    here_string: r.fragment.here_string,
+                    heredoc: r.fragment.heredoc,
+                    target: r.target.clone(),
+                })
+                .collect(),
+            background: false,
+        }
+    }
 }
 
-pub(crate) fn parse_command(input: &str) -> VecDeque<CommandDescriptor> {
-    let fragments = parse_to_fragments(input);
-    parse(fragments)
+/// Parse a command line into the commands of its pipelines, without executing anything.
+/// Returns the syntax error if it is invalid.
+pub fn parse(input: &str) -> Result<Vec<ParsedCommand>, String> {
+    let chain = parse_command(input, &HashMap::new())?;
+    let mut commands: Vec<ParsedCommand> = Vec::new();
+    for desc in &chain {
+        commands.push(match desc {
+            CommandDescriptor::Begin(data) => ParsedCommand::from_data(None, data),
+            CommandDescriptor::Pipe(data) => ParsedCommand::from_data(Some(Connector::Pipe), data),
+            CommandDescriptor::And(data) => ParsedCommand::from_data(Some(Connector::And), data),
+            CommandDescriptor::Or(data) => ParsedCommand::from_data(Some(Connector::Or), data),
+            CommandDescriptor::Next(data) => {
+                ParsedCommand::from_data(Some(Connector::Semicolon), data)
+            }
+            CommandDescriptor::Background => {
+                let last = commands.last_mut().expect("`&` follows a command.");
+                last.background = true;
+                continue;
+            }
+        });
+    }
+    Ok(commands)
+}
+
+/// Write the parts of a word for `dump_parse`, showing their quoting, like
+/// `Text("a b", quoted) Param(1)`.
+fn dump_word(word: &Word) -> String {
+    let quoted = |quoted: bool| if quoted { ", quoted" } else { "" };
+    let parts: Vec<_> = word
+        .parts
+        .iter()
+        .map(|part| match part {
+            WordPart::Text { value, quoted: q } => format!("Text({:?}{})", value, quoted(*q)),
+            WordPart::Param { name, quoted: q } => format!("Param({}{})", name, quoted(*q)),
+            WordPart::ParamOp {
+                name,
+                op,
+                word,
+                quoted: q,
+            } => format!(
+                "ParamOp({} {} [{}]{})",
+                name,
+                op.as_str(),
+                dump_word(word),
+                quoted(*q)
+            ),
+            WordPart::Command { command, quoted: q } => {
+                format!("Command({:?}{})", command, quoted(*q))
+            }
+            WordPart::Arith { expr, quoted: q } => {
+                format!("Arith([{}]{})", dump_word(expr), quoted(*q))
+            }
+            WordPart::Process { command, output } => {
+                let op = if *output { ">" } else { "<" };
+                format!("Process({}{:?})", op, command)
+            }
+        })
+        .collect();
+    parts.join(" ")
+}
+
+/// Write the parse result of a command line, one line for each assignment, word and
+/// redirection of each command, for `mysh --dump-parse`. Returns the syntax error if it is
+/// invalid.
+pub fn dump_parse(input: &str) -> Result<String, String> {
+    let mut dump = String::new();
+    for (i, command) in parse(input)?.iter().enumerate() {
+        match command.connector {
+            Some(connector) => writeln!(dump, "{} command {}", connector.as_str(), i + 1),
+            None => writeln!(dump, "command {}", i + 1),
+        }
+        .unwrap();
+        if command.negated {
+            writeln!(dump, "  negated").unwrap();
+        }
+        for assignment in &command.assignments {
+            let op = if assignment.append { "+=" } else { "=" };
+            let value = dump_word(&assignment.value);
+            writeln!(dump, "  assignment {}{} {}", assignment.name, op, value).unwrap();
+        }
+        if let Some(name) = &command.name {
+            writeln!(dump, "  name {}", dump_word(name)).unwrap();
+        }
+        for arg in &command.args {
+            writeln!(dump, "  argument {}", dump_word(arg)).unwrap();
+        }
+        for redirect in &command.redirects {
+            let op = match (redirect.is_input, redirect.append, redirect.both) {
+                _ if redirect.here_string => "<<<",
+                _ if redirect.heredoc => "<<",
+                (true, _, _) => "<",
+                (false, false, false) => ">",
+                (false, true, false) => ">>",
+                (false, false, true) => "&>",
+                (false, true, true) => "&>>",
+            };
+            let fd = if redirect.both {
+                String::new()
+            } else {
+                redirect.fd.to_string()
+            };
+            let dup = if redirect.dup { "&" } else { "" };
+            let target = dump_word(&redirect.target);
+            writeln!(dump, "  redirect {}{}{} {}", fd, op, dup, target).unwrap();
+        }
+        if command.background {
+            writeln!(dump, "  background").unwrap();
+        }
+    }
+    Ok(dump)
 }
@@ -0,0 +1,376 @@
+//! Formatting of the `printf` builtin.
+//!
+//! Supported conversions are `%s`, `%b`, `%q`, `%c`, `%d`, `%i`, `%u`, `%o`, `%x`, `%X`,
+//! `%(fmt)T` and `%%`, with the flags `-`, `0`, `+` and space, a width and a precision.
+//! Like bash, the format is reused until all the arguments are consumed, and missing
+//! arguments are taken as an empty string or zero.
+
+use std::{
+    ffi::{CString, OsString},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::quote_os;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    /// Pad `body` to the width. `prefix` is a sign, which stays before zeros.
+    fn pad(&self, prefix: &[u8], body: &[u8], out: &mut Vec<u8>) {
+        let len = prefix.len() + body.len();
+        let fill = self.width.saturating_sub(len);
+        if self.left {
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(body);
+            out.resize(out.len() + fill, b' ');
+        } else if self.zero {
+            out.extend_from_slice(prefix);
+            out.resize(out.len() + fill, b'0');
+            out.extend_from_slice(body);
+        } else {
+            out.resize(out.len() + fill, b' ');
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(body);
+        }
+    }
+
+    fn pad_str(&self, s: &[u8], out: &mut Vec<u8>) {
+        let s = match self.precision {
+            Some(precision) if precision < s.len() => &s[..precision],
+            _ => s,
+        };
+        Spec {
+            zero: false,
+            ..*self
+        }
+        .pad(b"", s, out);
+    }
+}
+
+/// Parse a numeric argument like `strtol` with base 0: a leading `0x` is hexadecimal and
+/// a leading `0` is octal. Like bash, `'c` or `"c` is the code of the character `c`.
+fn parse_number(arg: &[u8]) -> Result<i64, ()> {
+    if let [b'\'' | b'"', rest @ ..] = arg {
+        let s = String::from_utf8_lossy(rest);
+        return Ok(s.chars().next().map_or(0, |c| c as i64));
+    }
+    let s = std::str::from_utf8(arg).map_err(|_| ())?.trim_start();
+    if s.is_empty() {
+        return Ok(0);
+    }
+    let (negative, digits) = match s.as_bytes()[0] {
+        b'-' => (true, &s[1..]),
+        b'+' => (false, &s[1..]),
+        _ => (false, s),
+    };
+    let (radix, digits) = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (8, &digits[1..])
+    } else {
+        (10, digits)
+    };
+    let value = i64::from_str_radix(digits, radix).map_err(|_| ())?;
+    Ok(if negative { -value } else { value })
+}
+
+/// Expand a backslash escape at the start of `bytes`, which follows a backslash. Returns
+/// the number of bytes used.
+///
+/// In `%b` arguments, octal escapes are written as `\0nnn`, while in the format they are
+/// `\nnn`.
+fn escape(bytes: &[u8], in_argument: bool, out: &mut Vec<u8>) -> usize {
+    let Some(&c) = bytes.first() else {
+        out.push(b'\\');
+        return 0;
+    };
+    let byte = match c {
+        b'\\' => b'\\',
+        b'a' => 0x07,
+        b'b' => 0x08,
+        b'e' | b'E' => 0x1b,
+        b'f' => 0x0c,
+        b'n' => b'\n',
+        b'r' => b'\r',
+        b't' => b'\t',
+        b'v' => 0x0b,
+        b'"' if !in_argument => b'"',
+        b'\'' if !in_argument => b'\'',
+        b'0'..=b'7' => {
+            let skip = usize::from(in_argument && c == b'0');
+            let digits = bytes[skip..]
+                .iter()
+                .take(3)
+                .take_while(|b| matches!(b, b'0'..=b'7'))
+                .count();
+            let value = bytes[skip..skip + digits]
+                .iter()
+                .fold(0u32, |value, b| value * 8 + u32::from(b - b'0'));
+            out.push(value as u8);
+            return skip + digits;
+        }
+        _ => {
+            out.push(b'\\');
+            out.push(c);
+            return 1;
+        }
+    };
+    out.push(byte);
+    1
+}
+
+/// Expand the backslash escapes of a `%b` argument.
+fn expand_escapes(arg: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(arg.len());
+    let mut i = 0;
+    while i < arg.len() {
+        if arg[i] == b'\\' {
+            i += 1 + escape(&arg[i + 1..], true, &mut out);
+        } else {
+            out.push(arg[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Seconds since the epoch.
+fn seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+/// Format a time in seconds since the epoch with `strftime`, in the local time zone.
+fn format_time(format: &[u8], time: i64) -> Vec<u8> {
+    // Like bash, an empty format is the time of the locale
+    let format = if format.is_empty() { b"%X" } else { format };
+    let format = format.split(|&c| c == 0).next().unwrap_or_default();
+    let Ok(format) = CString::new(format) else {
+        return Vec::new();
+    };
+    let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+    let time = time as libc::time_t;
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return Vec::new();
+    }
+
+    // `strftime` returns 0 if the buffer is too small, or if the output is empty
+    let mut buf = vec![0u8; 256];
+    loop {
+        let len =
+            unsafe { libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm) };
+        if len > 0 || buf.len() >= 64 * 1024 {
+            buf.truncate(len);
+            return buf;
+        }
+        buf.resize(buf.len() * 4, 0);
+    }
+}
+
+/// Format `args` with `format`. Errors, such as an invalid number, are added to `errors`
+/// and the output goes on, like bash.
+///
+/// `start_time` is the time when the shell started, which is given to `%(fmt)T` as `-2`,
+/// while `-1` is the current time.
+pub fn format(
+    format: &[u8],
+    args: &[OsString],
+    start_time: SystemTime,
+    errors: &mut Vec<String>,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut args = args.iter().map(|arg| arg.as_bytes()).peekable();
+    loop {
+        match format_once(format, &mut args, start_time, &mut out, errors) {
+            Some(true) if args.peek().is_some() => {}
+            _ => return out,
+        }
+    }
+}
+
+/// Format with `format` once. Returns true if any argument is consumed, or `None` if the
+/// format is invalid, then formatting stops.
+fn format_once<'a>(
+    format: &[u8],
+    args: &mut impl Iterator<Item = &'a [u8]>,
+    start_time: SystemTime,
+    out: &mut Vec<u8>,
+    errors: &mut Vec<String>,
+) -> Option<bool> {
+    let mut consumed = false;
+    let mut next_arg = || {
+        let arg = args.next();
+        consumed |= arg.is_some();
+        arg.unwrap_or_default()
+    };
+
+    let mut i = 0;
+    while i < format.len() {
+        match format[i] {
+            b'\\' => {
+                i += 1 + escape(&format[i + 1..], false, out);
+                continue;
+            }
+            b'%' => {}
+            c => {
+                out.push(c);
+                i += 1;
+                continue;
+            }
+        }
+
+        let start = i;
+        i += 1;
+        let mut spec = Spec::default();
+        while let Some(&flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        while let Some(&digit @ b'0'..=b'9') = format.get(i) {
+            spec.width = spec.width * 10 + usize::from(digit - b'0');
+            i += 1;
+        }
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            let mut precision = 0;
+            while let Some(&digit @ b'0'..=b'9') = format.get(i) {
+                precision = precision * 10 + usize::from(digit - b'0');
+                i += 1;
+            }
+            spec.precision = Some(precision);
+        }
+
+        // The format of the time in `%(fmt)T`
+        let mut time_format = None;
+        if format.get(i) == Some(&b'(') {
+            let Some(len) = format[i + 1..].iter().position(|&c| c == b')') else {
+                errors.push(format!(
+                    "printf: {}: missing format character",
+                    String::from_utf8_lossy(&format[start..])
+                ));
+                return None;
+            };
+            time_format = Some(&format[i + 1..i + 1 + len]);
+            i += len + 2;
+        }
+
+        let Some(&conversion) = format.get(i) else {
+            errors.push(format!(
+                "printf: {}: missing format character",
+                String::from_utf8_lossy(&format[start..])
+            ));
+            return None;
+        };
+        i += 1;
+        if let Some(time_format) = time_format {
+            if conversion != b'T' {
+                errors.push(format!(
+                    "printf: `{}': invalid time format specification",
+                    conversion as char
+                ));
+                return None;
+            }
+            // Without an argument, it is the current time
+            let arg = next_arg();
+            let time = match parse_number(arg) {
+                _ if arg.is_empty() => -1,
+                Ok(time) => time,
+                Err(()) => {
+                    errors.push(format!(
+                        "printf: {}: invalid number",
+                        String::from_utf8_lossy(arg)
+                    ));
+                    0
+                }
+            };
+            let time = match time {
+                -1 => seconds(SystemTime::now()),
+                -2 => seconds(start_time),
+                time => time,
+            };
+            spec.pad_str(&format_time(time_format, time), out);
+            continue;
+        }
+        match conversion {
+            b'%' => out.push(b'%'),
+            b's' => spec.pad_str(next_arg(), out),
+            b'b' => spec.pad_str(&expand_escapes(next_arg()), out),
+            b'q' => {
+                let arg = OsString::from_vec(next_arg().to_vec());
+                spec.pad_str(quote_os(&arg).as_bytes(), out);
+            }
+            b'c' => {
+                let arg = next_arg();
+                spec.pad_str(&arg[..arg.len().min(1)], out);
+            }
+            b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+                let arg = next_arg();
+                let value = parse_number(arg).unwrap_or_else(|()| {
+                    errors.push(format!(
+                        "printf: {}: invalid number",
+                        String::from_utf8_lossy(arg)
+                    ));
+                    0
+                });
+                format_number(value, conversion, spec, out);
+            }
+            _ => {
+                errors.push(format!(
+                    "printf: %{}: invalid format character",
+                    conversion as char
+                ));
+                return None;
+            }
+        }
+    }
+    Some(consumed)
+}
+
+fn format_number(value: i64, conversion: u8, mut spec: Spec, out: &mut Vec<u8>) {
+    // Like bash, negative numbers are unsigned 64-bit numbers with `%u`, `%o` and `%x`.
+    let (sign, digits) = match conversion {
+        b'd' | b'i' => {
+            let sign = if value < 0 {
+                "-"
+            } else if spec.plus {
+                "+"
+            } else if spec.space {
+                " "
+            } else {
+                ""
+            };
+            (sign, value.unsigned_abs().to_string())
+        }
+        b'u' => ("", (value as u64).to_string()),
+        b'o' => ("", format!("{:o}", value as u64)),
+        b'x' => ("", format!("{:x}", value as u64)),
+        _ => ("", format!("{:X}", value as u64)),
+    };
+    // The precision is the minimum number of digits, then the `0` flag is ignored.
+    let digits = match spec.precision {
+        Some(precision) => {
+            spec.zero = false;
+            format!("{:0>precision$}", digits)
+        }
+        None => digits,
+    };
+    spec.pad(sign.as_bytes(), digits.as_bytes(), out);
+}
@@ -0,0 +1,73 @@
+//! Rendering of the prompt from `PS1`.
+//!
+//! Supported escapes, like bash:
+//!
+//! - `\j`: the number of jobs managed by the shell
+//! - `\w`: the working directory, with `$HOME` abbreviated as `~`
+//! - `\$`: `#` for root, otherwise `$`
+//! - `\\`: a backslash
+//!
+//! Other escapes are kept as they are.
+
+use std::path::Path;
+
+use crate::env::ExecEnv;
+
+/// The prompt when `PS1` is not set.
+pub const DEFAULT_PS1: &str = "$ ";
+
+/// Number of jobs for `\j`.
+fn job_count(env: &ExecEnv) -> usize {
+    env.jobs.len()
+}
+
+fn working_dir(env: &ExecEnv) -> String {
+    let pwd = if env.pwd.as_os_str().is_empty() {
+        match std::env::current_dir() {
+            Ok(cwd) => cwd,
+            Err(_) => return String::from("."),
+        }
+    } else {
+        env.pwd.clone()
+    };
+
+    if let Some(home) = std::env::var_os("HOME")
+        && let Ok(rest) = pwd.strip_prefix(Path::new(&home))
+    {
+        if rest.as_os_str().is_empty() {
+            return String::from("~");
+        }
+        return format!("~/{}", rest.display());
+    }
+    pwd.display().to_string()
+}
+
+/// Render the prompt from `PS1`, or `DEFAULT_PS1` if it is not set.
+pub fn render_prompt(env: &ExecEnv) -> String {
+    let ps1 = env.get_var("PS1").unwrap_or(DEFAULT_PS1);
+    let mut prompt = String::with_capacity(ps1.len());
+
+    let mut chars = ps1.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            prompt.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('j') => prompt.push_str(&job_count(env).to_string()),
+            Some('w') => prompt.push_str(&working_dir(env)),
+            Some('$') => prompt.push(if unsafe { libc::geteuid() } == 0 {
+                '#'
+            } else {
+                '$'
+            }),
+            Some('\\') => prompt.push('\\'),
+            Some(c) => {
+                prompt.push('\\');
+                prompt.push(c);
+            }
+            None => prompt.push('\\'),
+        }
+    }
+    prompt
+}
@@ -0,0 +1,62 @@
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+};
+
+/// Characters that can be written without any quoting.
+///
+/// Characters like `=`, `~`, `%`, `{`, `}` and `#` are left out on purpose, since they have
+/// special meaning in some positions (assignments, tilde expansion, job specs, etc.).
+fn is_safe_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'_' | b'-' | b'.' | b'/' | b':' | b',' | b'@' | b'+')
+}
+
+/// Quote bytes so that the result is parsed back as exactly one word.
+///
+/// The word is wrapped in single quotes, and every single quote inside the word is written
+/// as `'\''`: close the quote, an escaped quote, then open the quote again.
+fn quote_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len() + 2);
+    out.push(b'\'');
+    for &b in bytes {
+        if b == b'\'' {
+            out.extend_from_slice(b"'\\''");
+        } else {
+            out.push(b);
+        }
+    }
+    out.push(b'\'');
+    out
+}
+
+fn needs_quote(bytes: &[u8]) -> bool {
+    bytes.is_empty() || !bytes.iter().copied().all(is_safe_byte)
+}
+
+/// Quote a string so that mysh parses it back as exactly the original word.
+///
+/// The string is returned unchanged when it only contains safe characters, otherwise it is
+/// wrapped in single quotes.
+///
+/// ```
+/// assert_eq!(mysh::quote("hello"), "hello");
+/// assert_eq!(mysh::quote("hello world"), "'hello world'");
+/// assert_eq!(mysh::quote("it's"), r"'it'\''s'");
+/// assert_eq!(mysh::quote(""), "''");
+/// ```
+pub fn quote(s: &str) -> Cow<'_, str> {
+    if !needs_quote(s.as_bytes()) {
+        return Cow::Borrowed(s);
+    }
+    // Only ASCII bytes are inserted, so the result is still valid UTF-8.
+    Cow::Owned(String::from_utf8(quote_bytes(s.as_bytes())).unwrap())
+}
+
+/// Same as [`quote`], but works on `OsStr`, which may not be valid UTF-8.
+pub fn quote_os(s: &OsStr) -> Cow<'_, OsStr> {
+    if !needs_quote(s.as_bytes()) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(OsString::from_vec(quote_bytes(s.as_bytes())))
+}
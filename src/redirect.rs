@@ -1,8 +1,9 @@
 use std::{
     fs::File,
-    io::{self, Read, Write},
-    os::fd::{AsRawFd, IntoRawFd},
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd},
     path::PathBuf,
+    thread::{self, JoinHandle},
 };
 
 #[derive(Debug, Clone)]
@@ -28,30 +29,178 @@ impl OutputRedirect {
     pub fn set_fd(&mut self, fd: i32) {
         self.fd = fd;
     }
+
+    /// Open the file, creating it if it doesn't exist.
+    pub fn open(&self) -> io::Result<File> {
+        if self.append {
+            File::options()
+                .create(true)
+                .append(true)
+                .open(&self.filename)
+        } else {
+            File::create(&self.filename)
+        }
+    }
+}
+
+/// Where an input redirection reads from.
+#[derive(Debug, Clone)]
+pub enum InputSource {
+    File(PathBuf),
+    /// The text of a here-string `<<< word` with a newline after it, or the body of a
+    /// here-document.
+    Text(Vec<u8>),
+    /// `<&N`, a copy of fd N.
+    Fd(i32),
 }
 
 #[derive(Debug, Clone)]
 pub struct InputRedirect {
-    pub filename: PathBuf,
+    pub source: InputSource,
     pub fd: i32,
 }
 
 impl InputRedirect {
     pub fn new(filename: PathBuf) -> Self {
-        Self { filename, fd: 0 }
+        Self {
+            source: InputSource::File(filename),
+            fd: 0,
+        }
+    }
+
+    /// A here-string or here-document, see `InputSource::Text`.
+    pub fn text(text: Vec<u8>) -> Self {
+        Self {
+            source: InputSource::Text(text),
+            fd: 0,
+        }
+    }
+
+    /// `<&target`, see `InputSource::Fd`.
+    pub fn dup(target: i32) -> Self {
+        Self {
+            source: InputSource::Fd(target),
+            fd: 0,
+        }
     }
 
     pub fn set_fd(&mut self, fd: i32) {
         self.fd = fd;
     }
+
+    /// Open the file, or a pipe to read the text of a here-string or here-document from.
+    ///
+    /// The text is written to the pipe right away if it fits, otherwise a thread writes it
+    /// while the command reads, and stops if the command exits before reading all of it.
+    /// A duplication gets a copy of the fd of the shell.
+    pub fn open(&self) -> io::Result<File> {
+        let text = match &self.source {
+            InputSource::File(filename) => return File::open(filename),
+            InputSource::Text(text) => text,
+            InputSource::Fd(target) => {
+                check_shell_fd(*target)?;
+                let fd = unsafe { libc::fcntl(*target, libc::F_DUPFD_CLOEXEC, 0) };
+                if fd == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                return Ok(unsafe { File::from_raw_fd(fd) });
+            }
+        };
+        let (reader, mut writer) = io::pipe()?;
+        let text = text.clone();
+        if text.len() <= libc::PIPE_BUF {
+            writer.write_all(&text)?;
+        } else {
+            thread::spawn(move || writer.write_all(&text));
+        }
+        Ok(File::from(OwnedFd::from(reader)))
+    }
+
+    /// Describe an error of `open`, like `file: No such file or directory`.
+    pub fn describe_error(&self, e: &io::Error) -> String {
+        match &self.source {
+            InputSource::File(filename) => {
+                format!("{}: {}", filename.display(), error_message(e))
+            }
+            InputSource::Text(_) => format!("here-string: {}", error_message(e)),
+            InputSource::Fd(target) => format!("{}: {}", target, error_message(e)),
+        }
+    }
+}
+
+/// `N>&M`, which makes `fd` a copy of `target`. `N<&M` is an `InputRedirect` instead.
+#[derive(Debug, Clone)]
+pub struct FdDup {
+    pub fd: i32,
+    pub target: i32,
+    /// The number of output redirections written before it. The order matters, since
+    /// `> file 2>&1` sends both stdout and stderr to the file, while `2>&1 > file` sends
+    /// stderr where stdout was before.
+    pub outputs_before: usize,
+}
+
+/// An output redirection or a duplication, see `Redirect::outputs_in_order`.
+#[derive(Debug, Clone, Copy)]
+pub enum OutputStep<'a> {
+    File(&'a OutputRedirect),
+    Dup(&'a FdDup),
+}
+
+/// Where a redirected fd comes from once the files are opened, see `Redirect::open`.
+#[derive(Debug)]
+pub enum FdSource {
+    File(OwnedFd),
+    /// A copy of another fd.
+    Dup(i32),
+}
+
+/// The message of an I/O error without the `(os error N)` suffix, as shells show it.
+pub fn error_message(e: &io::Error) -> String {
+    let message = e.to_string();
+    match e.raw_os_error() {
+        Some(code) => message
+            .strip_suffix(&format!(" (os error {})", code))
+            .map(str::to_owned)
+            .unwrap_or(message),
+        None => message,
+    }
+}
+
+/// Check that `fd` of the shell can be copied for a duplication. The fds the shell opens for
+/// itself are all close-on-exec, such as saved fds and the pipes of `std::process`, so they
+/// are treated as not open like bash.
+fn check_shell_fd(fd: i32) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 || flags & libc::FD_CLOEXEC != 0 {
+        return Err(io::Error::from_raw_os_error(libc::EBADF));
+    }
+    Ok(())
+}
+
+/// Move an opened file to a fd from `min`, so that it is not replaced by a redirection
+/// applied before it, see `Redirect::open`.
+fn move_high(file: File, min: i32) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, min) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
 }
 
 /// In `bash`, if we try `echo "value" > 1 > 2`, only the last redirection takes effect.
 /// But in `zsh`, both redirections take effect, and `echo` writes to both file descriptors.
+///
+/// Every redirection is kept in order, so that all the files are opened (and truncated)
+/// like both shells do. Applied one by one, the last redirection of an fd replaces the
+/// previous ones. With `multios` set, see `Multios` for how all of them take effect.
+///
+/// Inputs are applied first, then outputs and duplications in the order they are written.
 #[derive(Debug, Clone)]
 pub struct Redirect {
     pub input: Vec<InputRedirect>,
     pub output: Vec<OutputRedirect>,
+    pub dups: Vec<FdDup>,
+    pub multios: bool,
 }
 
 impl Default for Redirect {
@@ -65,28 +214,223 @@ impl Redirect {
         Self {
             input: Vec::new(),
             output: Vec::new(),
+            dups: Vec::new(),
+            multios: false,
         }
     }
 
+    pub fn set_multios(&mut self, multios: bool) {
+        self.multios = multios;
+    }
+
     pub fn push_input(&mut self, redirect: InputRedirect) {
-        for r in &mut self.input {
-            if r.fd == redirect.fd {
-                r.filename = redirect.filename;
-                return;
-            }
-        }
         self.input.push(redirect);
     }
 
     pub fn push_output(&mut self, redirect: OutputRedirect) {
-        for r in &mut self.output {
-            if r.fd == redirect.fd {
-                r.filename = redirect.filename;
-                r.append = redirect.append;
-                return;
+        self.output.push(redirect);
+    }
+
+    /// Make `fd` a copy of `target`, after the output redirections pushed so far.
+    pub fn push_dup(&mut self, fd: i32, target: i32) {
+        self.dups.push(FdDup {
+            fd,
+            target,
+            outputs_before: self.output.len(),
+        });
+    }
+
+    /// Open the files of the redirections, returns the fds to set in the order they are
+    /// applied. Returns the error message with the file name if a file can't be opened, or
+    /// the fd if a duplication copies a fd which is not open, then the command should not
+    /// run.
+    pub fn open(&self) -> Result<Vec<(i32, FdSource)>, String> {
+        let describe =
+            |path: &PathBuf, e: io::Error| format!("{}: {}", path.display(), error_message(&e));
+        // The target is set by a redirection before it, or it must be open in the shell.
+        // Otherwise the command would get whatever is at that fd while it is spawned.
+        let dup = |fds: &[(i32, FdSource)], target: i32| {
+            if !fds.iter().any(|(fd, _)| *fd == target)
+                && let Err(e) = check_shell_fd(target)
+            {
+                return Err(format!(
+                    "redirection error: {}: {}",
+                    target,
+                    error_message(&e)
+                ));
+            }
+            Ok(FdSource::Dup(target))
+        };
+        // Opened files are moved above every redirected fd, so that none of them is at the
+        // fd it is set to, or at a fd set before it is applied, like `10>a 11>b`.
+        let redirected = self.input.iter().map(|r| r.fd);
+        let redirected = redirected.chain(self.output.iter().map(|r| r.fd));
+        let redirected = redirected.chain(self.dups.iter().map(|d| d.fd));
+        let min = redirected
+            .max()
+            .map_or(SAVED_FD_MIN, |fd| SAVED_FD_MIN.max(fd + 1));
+        let mut fds = Vec::new();
+        for input in &self.input {
+            // The copy is made when it is applied, since an earlier input may replace it
+            if let InputSource::Fd(target) = input.source {
+                let source = dup(&fds, target)?;
+                fds.push((input.fd, source));
+                continue;
             }
+            let file = input.open().and_then(|file| move_high(file, min));
+            let file = file.map_err(|e| input.describe_error(&e))?;
+            fds.push((input.fd, FdSource::File(file)));
+        }
+        for step in self.outputs_in_order() {
+            match step {
+                OutputStep::File(output) => {
+                    let file = output.open().and_then(|file| move_high(file, min));
+                    let file = file.map_err(|e| describe(&output.filename, e))?;
+                    fds.push((output.fd, FdSource::File(file)));
+                }
+                OutputStep::Dup(step) => {
+                    let source = dup(&fds, step.target)?;
+                    fds.push((step.fd, source));
+                }
+            }
+        }
+        Ok(fds)
+    }
+
+    /// Returns true if `fd` is redirected to a file or duplicated.
+    pub fn redirects_output(&self, fd: i32) -> bool {
+        self.output.iter().any(|r| r.fd == fd) || self.dups.iter().any(|d| d.fd == fd)
+    }
+
+    /// The output redirections and duplications in the order they are written.
+    pub fn outputs_in_order(&self) -> impl Iterator<Item = OutputStep<'_>> {
+        let dups_before = |index| {
+            self.dups
+                .iter()
+                .filter(move |dup| dup.outputs_before == index)
+                .map(OutputStep::Dup)
+        };
+        self.output
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, output)| {
+                dups_before(index).chain(std::iter::once(OutputStep::File(output)))
+            })
+            .chain(dups_before(self.output.len()))
+    }
+}
+
+/// Take the redirections of fds which are redirected more than once out of `redirects`,
+/// grouped by fd.
+fn take_repeated<T>(redirects: &mut Vec<T>, fd_of: impl Fn(&T) -> i32) -> Vec<(i32, Vec<T>)> {
+    let mut groups: Vec<(i32, Vec<T>)> = Vec::new();
+    for redirect in std::mem::take(redirects) {
+        let fd = fd_of(&redirect);
+        match groups.iter_mut().find(|(group_fd, _)| *group_fd == fd) {
+            Some((_, group)) => group.push(redirect),
+            None => groups.push((fd, vec![redirect])),
+        }
+    }
+
+    let mut repeated = Vec::new();
+    for (fd, mut group) in groups {
+        if group.len() == 1 {
+            redirects.push(group.pop().unwrap());
+        } else {
+            repeated.push((fd, group));
+        }
+    }
+    repeated
+}
+
+/// Copy everything from the pipe to all the files. A file which can't be written is
+/// dropped, while the others still get the output.
+fn tee(mut reader: PipeReader, mut files: Vec<File>) {
+    let mut buf = [0; 8192];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        files.retain_mut(|file| file.write_all(&buf[..n]).is_ok());
+    }
+}
+
+/// Copy the files to the pipe one after another, until the reader is closed.
+fn concat(mut writer: PipeWriter, files: Vec<File>) {
+    for mut file in files {
+        if io::copy(&mut file, &mut writer).is_err() {
+            break;
+        }
+    }
+}
+
+/// Redirections of the same fd with `multios` set, like zsh.
+///
+/// `echo hi > a >> b` writes to both files, and `cat < a < b` reads the files one after
+/// another. The command gets a pipe for each of these fds instead, while a thread of the
+/// shell copies between the pipe and the files.
+#[derive(Debug, Default)]
+pub struct Multios {
+    /// Pipe ends for the command, which replace the redirections of the fds.
+    pub fds: Vec<(i32, OwnedFd)>,
+    pumps: Vec<JoinHandle<()>>,
+}
+
+impl Multios {
+    /// Take the redirections of fds which are redirected more than once out of `redirect`,
+    /// and start copying. Does nothing if `multios` is not set.
+    ///
+    /// Returns an error message if a file can't be opened.
+    pub fn start(redirect: &mut Redirect) -> Result<Self, String> {
+        let mut ret = Self::default();
+        if !redirect.multios {
+            return Ok(ret);
+        }
+
+        for (fd, inputs) in take_repeated(&mut redirect.input, |r| r.fd) {
+            let files = inputs
+                .iter()
+                .map(|r| r.open().map_err(|e| r.describe_error(&e)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let (reader, writer) = io::pipe().map_err(|e| e.to_string())?;
+            ret.fds.push((fd, reader.into()));
+            ret.pumps.push(thread::spawn(move || concat(writer, files)));
+        }
+
+        // Duplications stay after the same outputs which are not taken
+        let fds: Vec<i32> = redirect.output.iter().map(|r| r.fd).collect();
+        let repeated = |fd| fds.iter().filter(|&&other| other == fd).count() > 1;
+        for dup in &mut redirect.dups {
+            dup.outputs_before = fds[..dup.outputs_before]
+                .iter()
+                .filter(|&&fd| !repeated(fd))
+                .count();
+        }
+        for (fd, outputs) in take_repeated(&mut redirect.output, |r| r.fd) {
+            let files = outputs
+                .iter()
+                .map(|r| {
+                    r.open()
+                        .map_err(|e| format!("{}: {}", r.filename.display(), error_message(&e)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let (reader, writer) = io::pipe().map_err(|e| e.to_string())?;
+            ret.fds.push((fd, writer.into()));
+            ret.pumps.push(thread::spawn(move || tee(reader, files)));
+        }
+
+        Ok(ret)
+    }
+
+    /// Wait until everything is copied. The pipe ends in `fds` must be closed first,
+    /// otherwise this never returns.
+    pub fn wait(self) {
+        for pump in self.pumps {
+            let _ = pump.join();
         }
-        self.output.push(redirect);
     }
 }
 
@@ -96,6 +440,16 @@ pub struct RedirectParseInfo {
     pub append_pending: bool,
     pub append: bool,
     pub fd: Option<i32>,
+    /// `>&` or `<&`
+    pub dup: bool,
+    /// `&>` or `&>>`
+    pub both: bool,
+    /// `<<<`
+    pub here_string: bool,
+    /// `<<` or `<<-`
+    pub heredoc: bool,
+    /// `<<-`
+    pub strip_tabs: bool,
 }
 
 impl RedirectParseInfo {
@@ -105,6 +459,11 @@ impl RedirectParseInfo {
             append_pending: true,
             append: false,
             fd: None,
+            dup: false,
+            both: false,
+            here_string: false,
+            heredoc: false,
+            strip_tabs: false,
         }
     }
 
@@ -114,6 +473,11 @@ impl RedirectParseInfo {
             append_pending: false,
             append: false,
             fd: None,
+            dup: false,
+            both: false,
+            here_string: false,
+            heredoc: false,
+            strip_tabs: false,
         }
     }
 }
@@ -123,6 +487,18 @@ pub struct RedirectParseFragment {
     pub is_input: bool,
     pub append: bool,
     pub fd: i32,
+    /// The target is a fd to duplicate, like `2>&1`. Like bash, `>&file` without a fd before
+    /// it is the same as `&>file` if the target is not a number.
+    pub dup: bool,
+    /// Both stdout and stderr go to the file, like `&>file`.
+    pub both: bool,
+    /// The target is the text to read, like `<<< text`.
+    pub here_string: bool,
+    /// The target is the delimiter of a here-document, like `<< EOF`. The parser replaces it
+    /// with the body once the lines after the command are read.
+    pub heredoc: bool,
+    /// Leading tabs are removed from the lines of the here-document, like `<<- EOF`.
+    pub strip_tabs: bool,
     pub value: String,
 }
 
@@ -132,107 +508,133 @@ impl RedirectParseFragment {
             is_input: info.is_input,
             append: info.append,
             fd: info.fd.unwrap_or(if info.is_input { 0 } else { 1 }),
+            dup: info.dup,
+            both: info.both,
+            here_string: info.here_string,
+            heredoc: info.heredoc,
+            strip_tabs: info.strip_tabs,
             value,
         }
     }
 }
 
+/// Lowest fd used to save the original of a redirected fd, so that saved fds never collide
+/// with the fds a user redirects, like bash does.
+const SAVED_FD_MIN: i32 = 10;
+
 #[derive(Debug, Clone, Copy)]
-struct RedirectPair {
-    pub before: i32,
-    pub after: i32,
+struct SavedFd {
+    fd: i32,
+    /// A copy of the original `fd`, or `None` if `fd` was not open.
+    saved: Option<i32>,
 }
 
+/// Redirections of the shell itself, for builtins. The original fds are restored when the
+/// handler is dropped.
 pub struct RedirectHandler {
-    input: Vec<RedirectPair>,
-    output: Vec<RedirectPair>,
+    saved: Vec<SavedFd>,
 }
 
 impl RedirectHandler {
-    unsafe fn swap_fd(before: i32, after: i32) -> Result<(), io::Error> {
-        unsafe {
-            let fd = libc::dup(before);
-            if fd == -1 {
-                return Err(io::Error::last_os_error());
-            }
-            if libc::dup2(after, before) == -1 {
-                return Err(io::Error::last_os_error());
-            }
-            if libc::dup2(fd, after) == -1 {
-                return Err(io::Error::last_os_error());
+    /// Redirect `fd` to `file`, saving the original `fd` first.
+    fn redirect_fd(&mut self, fd: i32, file: OwnedFd) -> io::Result<()> {
+        let file = file.into_raw_fd();
+        if file == fd {
+            // `fd` was not open, since the kernel handed it out for the file
+            self.saved.push(SavedFd { fd, saved: None });
+            return Ok(());
+        }
+
+        let saved = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, SAVED_FD_MIN) };
+        let saved = if saved != -1 {
+            Some(saved)
+        } else {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::EBADF) {
+                unsafe { libc::close(file) };
+                return Err(e);
             }
-            if libc::close(fd) == -1 {
+            None
+        };
+        self.saved.push(SavedFd { fd, saved });
+
+        unsafe {
+            let ret = libc::dup2(file, fd);
+            libc::close(file);
+            if ret == -1 {
                 return Err(io::Error::last_os_error());
             }
         }
         Ok(())
     }
 
-    pub fn new(redirect: &Redirect) -> Self {
-        let mut ret = RedirectHandler {
-            input: Vec::new(),
-            output: Vec::new(),
-        };
+    /// Redirect `fd` to `file` until the handler is dropped, which closes `file`.
+    pub fn push_fd(&mut self, fd: i32, file: OwnedFd) -> io::Result<()> {
+        self.redirect_fd(fd, file)
+    }
 
-        // set input redirection
-        for input_redirect in &redirect.input {
-            if let Ok(file) = File::open(&input_redirect.filename) {
-                let new_fd = file.into_raw_fd();
-                let pair = RedirectPair {
-                    before: input_redirect.fd,
-                    after: new_fd,
-                };
-                ret.input.push(pair);
-                unsafe {
-                    Self::swap_fd(pair.before, pair.after).unwrap();
-                }
-            }
+    /// Make `fd` a copy of `target`, saving the original `fd` first. The error names
+    /// `target`, like `9: Bad file descriptor`.
+    fn duplicate_fd(&mut self, fd: i32, target: i32) -> io::Result<()> {
+        let copy = unsafe { libc::fcntl(target, libc::F_DUPFD_CLOEXEC, SAVED_FD_MIN) };
+        if copy == -1 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("{}: {}", target, error_message(&e)),
+            ));
         }
+        self.redirect_fd(fd, unsafe { OwnedFd::from_raw_fd(copy) })
+    }
 
-        // set output redirection
-        for output_redirect in &redirect.output {
-            if let Ok(file) = if output_redirect.append {
-                File::options()
-                    .create(true)
-                    .append(true)
-                    .open(&output_redirect.filename)
-            } else {
-                File::create(&output_redirect.filename)
-            } {
-                let new_fd = file.into_raw_fd();
-                let pair = RedirectPair {
-                    before: output_redirect.fd,
-                    after: new_fd,
-                };
-                ret.output.push(pair);
-                unsafe {
-                    Self::swap_fd(pair.before, pair.after).unwrap();
-                }
+    /// Apply the redirections opened by `Redirect::open`. If one of them fails, the ones
+    /// applied before it are undone.
+    pub fn new(redirects: Vec<(i32, FdSource)>) -> io::Result<Self> {
+        Self::with_stdout(redirects, None)
+    }
+
+    /// Same as `new`, but fd 1 is set to `stdout` before the redirections, so that `2>&1`
+    /// copies it. It is for the pipe of a builtin in a pipeline.
+    pub fn with_stdout(
+        redirects: Vec<(i32, FdSource)>,
+        stdout: Option<OwnedFd>,
+    ) -> io::Result<Self> {
+        let mut ret = RedirectHandler { saved: Vec::new() };
+        if let Some(stdout) = stdout {
+            ret.push_fd(1, stdout)?;
+        }
+        for (fd, source) in redirects {
+            match source {
+                FdSource::File(file) => ret.redirect_fd(fd, file)?,
+                FdSource::Dup(target) => ret.duplicate_fd(fd, target)?,
             }
         }
-
-        ret
+        Ok(ret)
     }
 }
 
 impl Drop for RedirectHandler {
     fn drop(&mut self) {
-        unsafe fn drop_fd(before: i32, after: i32) -> Result<(), io::Error> {
-            unsafe {
-                if libc::dup2(after, before) == -1 {
-                    return Err(io::Error::last_os_error());
+        // Restore in the reverse order, in case the same fd is redirected more than once.
+        // Errors can't be returned from here, and the shell must keep running, so they are
+        // only reported.
+        for SavedFd { fd, saved } in self.saved.drain(..).rev() {
+            let ret = unsafe {
+                match saved {
+                    Some(saved) => {
+                        let ret = libc::dup2(saved, fd);
+                        libc::close(saved);
+                        ret
+                    }
+                    None => libc::close(fd),
                 }
-                libc::close(after);
-            }
-            Ok(())
-        }
-        unsafe {
-            for input_pair in &self.input {
-                drop_fd(input_pair.before, input_pair.after).unwrap();
-            }
-
-            for output_pair in &self.output {
-                drop_fd(output_pair.before, output_pair.after).unwrap();
+            };
+            if ret == -1 {
+                eprintln!(
+                    "mysh: redirection error: can't restore fd {}: {}",
+                    fd,
+                    error_message(&io::Error::last_os_error())
+                );
             }
         }
     }
@@ -259,7 +661,7 @@ impl BuiltinRedirectHandler {
 
         for input_redirect in &redirect.input {
             if input_redirect.fd == stdin_fd
-                && let Ok(file) = File::open(&input_redirect.filename)
+                && let Ok(file) = input_redirect.open()
             {
                 ret.stdin = Some(file);
             }
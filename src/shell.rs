@@ -0,0 +1,58 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rustyline::history::FileHistory;
+
+use crate::{
+    env::{ExecContext, ExecEnv, Hooks},
+    execution::result::CommandResult,
+};
+
+/// A shell for running command lines from a program, without the line editor.
+///
+/// Like `mysh -c`, history is not recorded, but builtins such as `history` still work on a
+/// history of their own.
+pub struct Shell {
+    env: Rc<RefCell<ExecEnv>>,
+    history: FileHistory,
+}
+
+impl Shell {
+    pub fn new(env: Rc<RefCell<ExecEnv>>) -> Self {
+        Self {
+            env,
+            history: FileHistory::new(),
+        }
+    }
+
+    pub fn env(&self) -> &Rc<RefCell<ExecEnv>> {
+        &self.env
+    }
+
+    /// Run a command line. Its output goes to the stdout of the process.
+    pub fn run(&mut self, input: &str) -> CommandResult {
+        Hooks::pre_exec(&self.env, input);
+        let context = ExecContext::new(&mut self.history);
+        let ret = crate::get_input_and_run(input, Rc::clone(&self.env), context);
+        let status = match ret {
+            CommandResult::Exit(status) => status,
+            CommandResult::Normal => self.env.borrow().last_status,
+        };
+        Hooks::post_exec(&self.env, input, status);
+        ret
+    }
+
+    /// Run a command line and return its stdout and status, see `get_input_and_capture`.
+    ///
+    /// Output which is not valid UTF-8 is converted lossily.
+    pub fn capture(&mut self, input: &str) -> (String, i32) {
+        Hooks::pre_exec(&self.env, input);
+        let context = ExecContext::new(&mut self.history);
+        let (output, status) = crate::get_input_and_capture(input, Rc::clone(&self.env), context);
+        Hooks::post_exec(&self.env, input, status);
+        let output = match String::from_utf8(output) {
+            Ok(output) => output,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        };
+        (output, status)
+    }
+}
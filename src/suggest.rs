@@ -0,0 +1,101 @@
+//! Suggestions for mistyped command names.
+//!
+//! When a command is not found, the closest builtin, alias or executable in `PATH` is
+//! suggested, see `ShellOptions`. The scorer is the edit distance, where swapping two
+//! adjacent characters counts as one edit, so `gti` is one edit from `git`.
+
+use is_executable::IsExecutable;
+
+use crate::env::ExecEnv;
+
+/// Names which are farther than this are not suggested.
+pub const MAX_DISTANCE: usize = 2;
+
+/// At most this many entries of the `PATH` directories are looked at, so a failure doesn't
+/// become slow with a huge `PATH`.
+const MAX_PATH_ENTRIES: usize = 20_000;
+
+/// The edit distance between `a` and `b`, counting insertions, deletions, substitutions and
+/// transpositions of adjacent characters (optimal string alignment).
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Three rows of the table: `prev2` for transpositions, `prev` and `row`.
+    let mut prev2 = vec![0; b.len() + 1];
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(prev2[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut prev2, &mut prev);
+        std::mem::swap(&mut prev, &mut row);
+    }
+    prev[b.len()]
+}
+
+/// Returns the candidate closest to `name`, within `MAX_DISTANCE`. Of candidates at the same
+/// distance, the first one is returned.
+///
+/// The distance must also be less than the length of both names, otherwise any short name
+/// would be close to `.` or `[`.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let len = name.chars().count();
+    candidates
+        .into_iter()
+        .filter(|&candidate| candidate != name)
+        .filter_map(|candidate| {
+            let candidate_len = candidate.chars().count();
+            // The distance is at least the difference of the lengths.
+            if candidate_len.abs_diff(len) > MAX_DISTANCE {
+                return None;
+            }
+            let distance = edit_distance(name, candidate);
+            (distance <= MAX_DISTANCE && distance < len.min(candidate_len))
+                .then_some((distance, candidate))
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Returns the builtin, alias or executable in `PATH` whose name is closest to `name`.
+///
+/// Builtins come first, then aliases, then the directories of `PATH` in order, so a command
+/// which would be run is preferred when several are at the same distance.
+pub fn suggest_command(name: &str, env: &ExecEnv) -> Option<String> {
+    let len = name.chars().count();
+    let mut candidates: Vec<String> = crate::builtin::BUILTIN_COMMANDS
+        .with(|map| map.keys().map(|name| name.to_string()).collect());
+    candidates.sort();
+    let mut aliases: Vec<String> = env.aliases.keys().cloned().collect();
+    aliases.sort();
+    candidates.extend(aliases);
+
+    let entries = env
+        .path_env
+        .iter()
+        .flat_map(|dir| {
+            let names = env.path_cache.entries(dir).to_vec();
+            names.into_iter().map(move |name| (dir, name))
+        })
+        .take(MAX_PATH_ENTRIES);
+    for (dir, file_name) in entries {
+        // Only names which may be close are checked for being executable.
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        if file_name.chars().count().abs_diff(len) <= MAX_DISTANCE
+            && edit_distance(name, file_name) <= MAX_DISTANCE
+            && dir.join(file_name).is_executable()
+        {
+            candidates.push(file_name.to_owned());
+        }
+    }
+
+    closest(name, candidates.iter().map(String::as_str)).map(str::to_owned)
+}
@@ -0,0 +1,49 @@
+//! Saving and restoring the modes of the terminal.
+//!
+//! A child may leave the terminal in a bad state, e.g. `stty -echo` or a crashed curses
+//! program, so the modes saved before running a command line are restored after it.
+
+use std::{io, mem::MaybeUninit, os::fd::RawFd};
+
+/// Modes of a terminal, saved with `tcgetattr`.
+pub struct TerminalModes {
+    fd: RawFd,
+    termios: libc::termios,
+}
+
+fn get_termios(fd: RawFd) -> io::Result<libc::termios> {
+    let mut termios = MaybeUninit::uninit();
+    if unsafe { libc::tcgetattr(fd, termios.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { termios.assume_init() })
+}
+
+fn same_modes(a: &libc::termios, b: &libc::termios) -> bool {
+    a.c_iflag == b.c_iflag
+        && a.c_oflag == b.c_oflag
+        && a.c_cflag == b.c_cflag
+        && a.c_lflag == b.c_lflag
+        && a.c_cc == b.c_cc
+}
+
+impl TerminalModes {
+    /// Save the modes of the terminal, returns `None` if `fd` is not a terminal.
+    pub fn save(fd: RawFd) -> Option<Self> {
+        if unsafe { libc::isatty(fd) } != 1 {
+            return None;
+        }
+        get_termios(fd).ok().map(|termios| Self { fd, termios })
+    }
+
+    /// Restore the saved modes if they have been changed.
+    pub fn restore(&self) -> io::Result<()> {
+        if same_modes(&get_termios(self.fd)?, &self.termios) {
+            return Ok(());
+        }
+        if unsafe { libc::tcsetattr(self.fd, libc::TCSADRAIN, &self.termios) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
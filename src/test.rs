@@ -0,0 +1,215 @@
+//! Expressions of the `test` and `[` builtins.
+//!
+//! Expressions are combined with `!`, `-a`, `-o` and parentheses, where `-a` binds tighter
+//! than `-o`. Like bash, a binary operator is tried before a unary one, so `[ -n = x ]`
+//! compares the strings `-n` and `x`.
+
+use std::{
+    cmp::Ordering,
+    ffi::{CString, OsStr, OsString},
+    fs::Metadata,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, MetadataExt},
+    },
+};
+
+pub(crate) fn is_unary(op: &OsStr) -> bool {
+    matches!(
+        op.as_bytes(),
+        b"-e"
+            | b"-f"
+            | b"-d"
+            | b"-h"
+            | b"-L"
+            | b"-p"
+            | b"-S"
+            | b"-b"
+            | b"-c"
+            | b"-r"
+            | b"-w"
+            | b"-x"
+            | b"-s"
+            | b"-t"
+            | b"-n"
+            | b"-z"
+    )
+}
+
+pub(crate) fn is_binary(op: &OsStr) -> bool {
+    matches!(
+        op.as_bytes(),
+        b"=" | b"=="
+            | b"!="
+            | b"<"
+            | b">"
+            | b"-eq"
+            | b"-ne"
+            | b"-lt"
+            | b"-le"
+            | b"-gt"
+            | b"-ge"
+            | b"-nt"
+            | b"-ot"
+            | b"-ef"
+    )
+}
+
+fn parse_integer(arg: &OsStr) -> Result<i64, String> {
+    arg.to_str()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| format!("{}: integer expression expected", arg.display()))
+}
+
+fn access(path: &OsStr, mode: libc::c_int) -> bool {
+    CString::new(path.as_bytes())
+        .is_ok_and(|path| unsafe { libc::access(path.as_ptr(), mode) } == 0)
+}
+
+pub(crate) fn unary(op: &OsStr, arg: &OsStr) -> Result<bool, String> {
+    let metadata = || std::fs::metadata(arg).ok();
+    let file_type = |check: fn(&Metadata) -> bool| metadata().is_some_and(|m| check(&m));
+    Ok(match op.as_bytes() {
+        b"-n" => !arg.is_empty(),
+        b"-z" => arg.is_empty(),
+        b"-e" => metadata().is_some(),
+        b"-f" => file_type(|m| m.is_file()),
+        b"-d" => file_type(|m| m.is_dir()),
+        b"-p" => file_type(|m| m.file_type().is_fifo()),
+        b"-S" => file_type(|m| m.file_type().is_socket()),
+        b"-b" => file_type(|m| m.file_type().is_block_device()),
+        b"-c" => file_type(|m| m.file_type().is_char_device()),
+        b"-s" => file_type(|m| m.len() > 0),
+        b"-h" | b"-L" => std::fs::symlink_metadata(arg).is_ok_and(|m| m.is_symlink()),
+        b"-r" => access(arg, libc::R_OK),
+        b"-w" => access(arg, libc::W_OK),
+        b"-x" => access(arg, libc::X_OK),
+        b"-t" => {
+            let fd = parse_integer(arg)? as libc::c_int;
+            unsafe { libc::isatty(fd) == 1 }
+        }
+        _ => unreachable!("not a unary operator"),
+    })
+}
+
+/// Compare the modification times of two files. A file which doesn't exist is older than
+/// any file which does, like bash.
+fn compare_mtime(left: &OsStr, right: &OsStr) -> Option<Ordering> {
+    let mtime = |path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (mtime(left), mtime(right)) {
+        (Some(left), Some(right)) => Some(left.cmp(&right)),
+        (Some(_), None) => Some(Ordering::Greater),
+        (None, Some(_)) => Some(Ordering::Less),
+        (None, None) => None,
+    }
+}
+
+pub(crate) fn binary(left: &OsStr, op: &OsStr, right: &OsStr) -> Result<bool, String> {
+    let integers = || Ok::<_, String>((parse_integer(left)?, parse_integer(right)?));
+    Ok(match op.as_bytes() {
+        b"=" | b"==" => left == right,
+        b"!=" => left != right,
+        b"<" => left.as_bytes() < right.as_bytes(),
+        b">" => left.as_bytes() > right.as_bytes(),
+        b"-eq" => integers().map(|(l, r)| l == r)?,
+        b"-ne" => integers().map(|(l, r)| l != r)?,
+        b"-lt" => integers().map(|(l, r)| l < r)?,
+        b"-le" => integers().map(|(l, r)| l <= r)?,
+        b"-gt" => integers().map(|(l, r)| l > r)?,
+        b"-ge" => integers().map(|(l, r)| l >= r)?,
+        b"-nt" => compare_mtime(left, right) == Some(Ordering::Greater),
+        b"-ot" => compare_mtime(left, right) == Some(Ordering::Less),
+        b"-ef" => match (std::fs::metadata(left), std::fs::metadata(right)) {
+            (Ok(left), Ok(right)) => left.dev() == right.dev() && left.ino() == right.ino(),
+            _ => false,
+        },
+        _ => unreachable!("not a binary operator"),
+    })
+}
+
+struct Parser<'a> {
+    args: &'a [OsString],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self, offset: usize) -> Option<&'a OsStr> {
+        self.args.get(self.pos + offset).map(OsString::as_os_str)
+    }
+
+    fn next(&mut self) -> Option<&'a OsStr> {
+        let arg = self.peek(0);
+        self.pos += 1;
+        arg
+    }
+
+    fn or(&mut self) -> Result<bool, String> {
+        let mut value = self.and()?;
+        while self.peek(0).is_some_and(|arg| arg == "-o") {
+            self.pos += 1;
+            // Both sides are parsed, so errors are reported even if the value is known.
+            value |= self.and()?;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<bool, String> {
+        let mut value = self.not()?;
+        while self.peek(0).is_some_and(|arg| arg == "-a") {
+            self.pos += 1;
+            value &= self.not()?;
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> Result<bool, String> {
+        match self.peek(0) {
+            // `! = x` compares `!` and `x`
+            Some(arg) if arg == "!" && !self.peek(1).is_some_and(is_binary) => {
+                self.pos += 1;
+                Ok(!self.not()?)
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        let Some(arg) = self.next() else {
+            return Err(String::from("argument expected"));
+        };
+        if let (Some(op), Some(right)) = (self.peek(0), self.peek(1))
+            && is_binary(op)
+        {
+            self.pos += 2;
+            return binary(arg, op, right);
+        }
+        if arg == "(" && self.peek(0).is_some() {
+            let value = self.or()?;
+            return match self.next() {
+                Some(arg) if arg == ")" => Ok(value),
+                _ => Err(String::from("`)' expected")),
+            };
+        }
+        if is_unary(arg)
+            && let Some(operand) = self.next()
+        {
+            return unary(arg, operand);
+        }
+        // A single string is true if it is not empty
+        Ok(!arg.is_empty())
+    }
+}
+
+/// Evaluate the arguments of `test`. Returns the error message if they are not a valid
+/// expression, e.g. a number is expected but the argument is not a number.
+pub fn evaluate(args: &[OsString]) -> Result<bool, String> {
+    if args.is_empty() {
+        return Ok(false);
+    }
+    let mut parser = Parser { args, pos: 0 };
+    let value = parser.or()?;
+    match parser.peek(0) {
+        Some(arg) => Err(format!("{}: unexpected argument", arg.display())),
+        None => Ok(value),
+    }
+}
@@ -0,0 +1,143 @@
+use mysh::{Shell, arith};
+
+use crate::common::new_env;
+
+mod common;
+
+fn evaluate(expr: &str) -> Result<i64, String> {
+    let lookup = |name: &str| match name {
+        "x" => Some(String::from("4")),
+        "empty" => Some(String::new()),
+        "expr" => Some(String::from("x * 2")),
+        "self" => Some(String::from("self + 1")),
+        _ => None,
+    };
+    arith::evaluate(expr, &lookup)
+}
+
+#[test]
+fn evaluate_operators() {
+    let cases: &[(&str, i64)] = &[
+        ("1 + 2", 3),
+        ("2 + 3 * 4", 14),
+        ("(2 + 3) * 4", 20),
+        ("10 - 2 - 3", 5),
+        ("7 / 2", 3),
+        ("-7 / 2", -3),
+        ("7 % 3", 1),
+        ("-7 % 3", -1),
+        ("2 * -3", -6),
+        ("--3", 3),
+        ("+3", 3),
+        ("!0", 1),
+        ("!5", 0),
+        // Comparisons give 0 or 1
+        ("1 < 2", 1),
+        ("2 < 1", 0),
+        ("2 <= 2", 1),
+        ("3 > 2", 1),
+        ("2 >= 3", 0),
+        ("1 + 1 == 2", 1),
+        ("1 != 1", 0),
+        ("1 && 2", 1),
+        ("1 && 0", 0),
+        ("0 || 3", 1),
+        ("0 || 0", 0),
+        ("1 < 2 && 2 < 3", 1),
+        // Constants
+        ("0x1f", 31),
+        ("010", 8),
+        ("0", 0),
+        ("", 0),
+        ("  42  ", 42),
+        // Overflow wraps around
+        ("9223372036854775807 + 1", i64::MIN),
+        ("-9223372036854775807 - 1", i64::MIN),
+        ("(-9223372036854775807 - 1) / -1", i64::MIN),
+    ];
+    for (expr, expected) in cases {
+        assert_eq!(evaluate(expr), Ok(*expected), "evaluating {:?}", expr);
+    }
+}
+
+#[test]
+fn evaluate_variables() {
+    assert_eq!(evaluate("2 + 3 * x"), Ok(14));
+    assert_eq!(evaluate("x*x"), Ok(16));
+    // Unset and empty variables are 0, and values are expressions
+    assert_eq!(evaluate("unset + 1"), Ok(1));
+    assert_eq!(evaluate("empty + 1"), Ok(1));
+    assert_eq!(evaluate("expr + 1"), Ok(9));
+    assert_eq!(
+        evaluate("self"),
+        Err(String::from(
+            "self + 1: expression recursion level exceeded (error token is \"self + 1\")"
+        ))
+    );
+}
+
+#[test]
+fn evaluate_errors() {
+    assert_eq!(evaluate("1 / 0"), Err(String::from("1 / 0: division by 0")));
+    assert_eq!(
+        evaluate("5 % (x - 4)"),
+        Err(String::from("5 % (x - 4): division by 0"))
+    );
+    // The right side of `&&` and `||` is not evaluated if the value is known
+    assert_eq!(evaluate("0 && 1 / 0"), Ok(0));
+    assert_eq!(evaluate("1 || 1 / 0"), Ok(1));
+
+    assert_eq!(
+        evaluate("1 +"),
+        Err(String::from(
+            "1 +: syntax error: operand expected (error token is \"\")"
+        ))
+    );
+    assert_eq!(
+        evaluate("(1 + 2"),
+        Err(String::from(
+            "(1 + 2: syntax error: `)' expected (error token is \"\")"
+        ))
+    );
+    assert_eq!(
+        evaluate("1 2"),
+        Err(String::from(
+            "1 2: syntax error: invalid arithmetic operator (error token is \"2\")"
+        ))
+    );
+    assert_eq!(
+        evaluate("1 @ 2"),
+        Err(String::from(
+            "1 @ 2: syntax error: invalid arithmetic operator (error token is \"@ 2\")"
+        ))
+    );
+    assert_eq!(
+        evaluate("09"),
+        Err(String::from(
+            "09: value too great for base (error token is \"09\")"
+        ))
+    );
+}
+
+#[test]
+fn arithmetic_expansion() {
+    let mut shell = Shell::new(new_env());
+    shell.run("x=4");
+
+    assert_eq!(
+        shell.capture("echo $((2 + 3 * x))"),
+        (String::from("14\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("echo \"$(( (1 + 2) * $x ))\" $(($(echo 6) / ${y:-2}))"),
+        (String::from("12 3\n"), 0)
+    );
+    // Not split or expanded as a pattern
+    assert_eq!(shell.capture("echo $((1 * 2))"), (String::from("2\n"), 0));
+
+    // An error skips the command
+    assert_eq!(shell.capture("echo $((1 / 0))"), (String::new(), 1));
+    shell.run("y=$((x % 0))");
+    assert_eq!(shell.env().borrow().last_status(), 1);
+    assert_eq!(shell.env().borrow().get_var("y"), None);
+}
@@ -0,0 +1,115 @@
+use mysh::{
+    Shell, brace,
+    parse::{ParseFragment, parse_to_fragments},
+};
+
+use crate::common::new_env;
+
+mod common;
+
+fn expand(s: &str) -> Vec<String> {
+    let mut fragments = parse_to_fragments(s).unwrap();
+    let Some(ParseFragment::Argument(word)) = fragments.pop_front() else {
+        panic!("{:?} is not a word", s);
+    };
+    assert!(fragments.is_empty(), "{:?} is not a single word", s);
+    brace::expand(&word)
+        .iter()
+        .map(|word| word.to_text())
+        .collect()
+}
+
+#[test]
+fn expand_words() {
+    let cases: &[(&str, &[&str])] = &[
+        ("file.{rs,toml}", &["file.rs", "file.toml"]),
+        ("{a,b,c}", &["a", "b", "c"]),
+        ("pre{a,b}post", &["preapost", "prebpost"]),
+        ("{a,b}{1,2}", &["a1", "a2", "b1", "b2"]),
+        ("x{,y}", &["x", "xy"]),
+        // Nested groups
+        ("{a,{b,c}}", &["a", "b", "c"]),
+        ("{a,b{1,2}}z", &["az", "b1z", "b2z"]),
+        ("{{a,b}}", &["{a}", "{b}"]),
+        // Groups without a comma are kept
+        ("{}", &["{}"]),
+        ("{single}", &["{single}"]),
+        ("{single}{a,b}", &["{single}a", "{single}b"]),
+        ("a{b", &["a{b"]),
+        ("a}b,c", &["a}b,c"]),
+        ("{x{a,b}", &["{xa", "{xb"]),
+        // Quoted braces and commas are literal
+        ("'{a,b}'", &["{a,b}"]),
+        ("\\{a,b}", &["{a,b}"]),
+        ("{a\\,b,c}", &["a,b", "c"]),
+        ("{a,'b,c'}", &["a", "b,c"]),
+        ("\"{\"a,b}", &["{a,b}"]),
+        // Ranges
+        ("{1..5}", &["1", "2", "3", "4", "5"]),
+        ("{a..e}", &["a", "b", "c", "d", "e"]),
+        ("{5..1}", &["5", "4", "3", "2", "1"]),
+        ("{e..a..2}", &["e", "c", "a"]),
+        ("{0..10..3}", &["0", "3", "6", "9"]),
+        ("{10..0..-5}", &["10", "5", "0"]),
+        ("{-2..1}", &["-2", "-1", "0", "1"]),
+        ("{3..3}", &["3"]),
+        ("{1..3..0}", &["1", "2", "3"]),
+        ("{01..10..3}", &["01", "04", "07", "10"]),
+        ("{8..010}", &["008", "009", "010"]),
+        ("{-1..01}", &["-1", "00", "01"]),
+        (
+            "{Y..b}",
+            &["Y", "Z", "[", "\\", "]", "^", "_", "`", "a", "b"],
+        ),
+        ("x{1..3}y", &["x1y", "x2y", "x3y"]),
+        ("{1..3}{a..b}", &["1a", "1b", "2a", "2b", "3a", "3b"]),
+        ("{a,{1..2}}", &["a", "1", "2"]),
+        ("{1..2}{x,y}", &["1x", "1y", "2x", "2y"]),
+        // Invalid ranges are kept
+        ("{a..5}", &["{a..5}"]),
+        ("{1..b}", &["{1..b}"]),
+        ("{ab..c}", &["{ab..c}"]),
+        ("{1..}", &["{1..}"]),
+        ("{1..2..x}", &["{1..2..x}"]),
+        ("{1..2..3..4}", &["{1..2..3..4}"]),
+        ("{1...3}", &["{1...3}"]),
+        ("{1..'3'}", &["{1..3}"]),
+        // Parameters are kept for later expansion
+        ("$x{a,b}", &["$xa", "$xb"]),
+        ("{$x,${y:-1,2}}", &["$x", "${y:-1,2}"]),
+    ];
+    for (input, expected) in cases {
+        assert_eq!(expand(input), *expected, "expanding {:?}", input);
+    }
+}
+
+#[test]
+fn expand_commands() {
+    let mut shell = Shell::new(new_env());
+
+    assert_eq!(
+        shell.capture("echo file.{rs,toml}"),
+        (String::from("file.rs file.toml\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("echo '{a,b}' \"{x,y}\" {}"),
+        (String::from("{a,b} {x,y} {}\n"), 0)
+    );
+    // Before parameter expansion, so the value of a variable is not expanded
+    shell.run("v={a,b}");
+    assert_eq!(shell.capture("echo $v"), (String::from("{a,b}\n"), 0));
+    assert_eq!(shell.capture("echo {$v,c}"), (String::from("{a,b} c\n"), 0));
+    // Words of a command are expanded, but not the value of an assignment
+    assert_eq!(
+        shell.capture("echo a={x,y}"),
+        (String::from("a=x a=y\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("echo {1..3}{a..b}"),
+        (String::from("1a 1b 2a 2b 3a 3b\n"), 0)
+    );
+    // The command name can also be expanded
+    assert_eq!(shell.capture("{echo,hi}"), (String::from("hi\n"), 0));
+    // Not in `[[ ... ]]`
+    assert_eq!(shell.capture("[[ {a,b} == '{a,b}' ]]").1, 0);
+}
@@ -1,56 +1,27 @@
 use std::{
     cell::RefCell,
-    fs::File,
-    io::{self, Read, Seek, SeekFrom, Write},
+    ffi::OsString,
+    io::{self, Write},
+    os::unix::{fs::PermissionsExt, process::ExitStatusExt},
+    process::ExitStatus,
     rc::Rc,
 };
 
 use mysh::{
+    Shell,
     completion::ShellCompleter,
-    env::{ExecContext, ExecEnv},
+    env::{ExecContext, ExecEnv, PathEnv},
+    execution::{exit_status_code, result::CommandResult},
     get_input_and_run,
+    job::JobState,
 };
-use rustyline::Editor;
+use rustyline::{Editor, history::History};
 
-use crate::common::TempFile;
+use crate::common::{TempFile, get_print_with_handler, get_print_with_handler_u8, new_env};
 
+#[macro_use]
 mod common;
 
-#[allow(dead_code)]
-fn read_from_temp(file: &mut File) -> String {
-    let mut vec = Vec::new();
-    file.read_to_end(&mut vec).unwrap();
-    String::from_utf8(vec).unwrap()
-}
-
-fn read_from_temp_u8(file: &mut File) -> Vec<u8> {
-    let mut vec = Vec::new();
-    file.read_to_end(&mut vec).unwrap();
-    vec
-}
-
-#[allow(dead_code)]
-fn get_print_with_handler(file: &mut File) -> String {
-    let output = read_from_temp(file);
-    file.seek(SeekFrom::Start(0)).unwrap();
-    file.set_len(0).unwrap();
-    output
-}
-
-fn get_print_with_handler_u8(file: &mut File) -> Vec<u8> {
-    let output = read_from_temp_u8(file);
-    file.seek(SeekFrom::Start(0)).unwrap();
-    file.set_len(0).unwrap();
-    output
-}
-
-macro_rules! execute {
-    ($path:expr, $env:expr, $rl:expr, $str:literal) => {
-        let context = ExecContext::new($rl.history_mut());
-        get_input_and_run(&format!($str, $path.display()), $env.clone(), context);
-    };
-}
-
 #[test]
 fn cd_absolute() {
     let _lock = io::stdout().lock();
@@ -68,6 +39,7 @@ fn cd_absolute() {
     let result = b"/\n";
 
     assert_eq!(output_path, result);
+    assert_eq!(env.borrow().last_status, 0);
 }
 
 #[test]
@@ -119,4 +91,458 @@ hello world
 "#;
 
     assert_eq!(output, result);
+    assert_eq!(env.borrow().last_status, 0);
+}
+
+#[test]
+fn exit_status() {
+    let _lock = io::stdout().lock();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let path = temp_dir.path().join("missing");
+    let base_dirs = directories::BaseDirs::new().expect("Failed to get base directories");
+    let env = Rc::new(RefCell::new(ExecEnv::new(base_dirs)));
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    let mut status = |line: &str| {
+        let context = ExecContext::new(rl.history_mut());
+        get_input_and_run(line, env.clone(), context);
+        env.borrow().last_status
+    };
+
+    assert_eq!(status("type type"), 0);
+    assert_eq!(status("type no-such-command"), 1);
+    assert_eq!(status(&format!("cd {}", path.display())), 1);
+    assert_eq!(status("cd / /"), 1);
+    assert_eq!(status(&format!("history -r {}", path.display())), 1);
+    assert_eq!(status("history -x"), 2);
+    assert_eq!(status("history x"), 2);
+    assert_eq!(status("set -x"), 2);
+    assert_eq!(status("set -o no_such_option"), 2);
+    assert_eq!(status("set +o posix"), 0);
+    assert_eq!(status("builtin no-such-command"), 1);
+    assert_eq!(status("command -x"), 2);
+    assert_eq!(status("no-such-command"), 127);
+
+    // The status of a pipeline is the status of its last command
+    assert_eq!(status("type no-such-command | true"), 0);
+    assert_eq!(status("echo | type no-such-command"), 1);
+    assert_eq!(status("true | false"), 1);
+    assert_eq!(status("false | true"), 0);
+
+    // Like bash, a command killed by a signal gives `128 + signal`
+    assert_eq!(status("sh -c 'exit 300'"), 44);
+    assert_eq!(status("sh -c 'kill -TERM $$'"), 143);
+    assert_eq!(status("sh -c 'kill -KILL $$' | true"), 0);
+    assert_eq!(status("true | sh -c 'kill -KILL $$'"), 137);
+}
+
+#[test]
+fn snapshot_and_restore() {
+    let _lock = io::stdout().lock();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let cwd = std::env::current_dir().unwrap();
+    let pwd = env.borrow().pwd.clone();
+    env.borrow_mut()
+        .set_var(String::from("OUTER"), String::from("1"));
+
+    let snapshot = env.borrow().snapshot();
+    {
+        let mut env = env.borrow_mut();
+        env.set_var(String::from("OUTER"), String::from("2"));
+        env.set_var(String::from("INNER"), String::from("3"));
+        env.positional_params = vec![OsString::from("a")];
+        env.options.posix = true;
+    }
+    execute!(temp_dir.path(), env, rl, "cd {}");
+    env.borrow_mut().last_status = 3;
+    env.borrow_mut().restore(snapshot);
+
+    let env = env.borrow();
+    assert_eq!(env.get_var("OUTER"), Some("1"));
+    assert_eq!(env.get_var("INNER"), None);
+    assert!(env.positional_params.is_empty());
+    assert!(!env.options.posix);
+    assert_eq!(env.pwd, pwd);
+    assert_eq!(std::env::current_dir().unwrap(), cwd);
+    // `$?` of a subshell is seen by the parent
+    assert_eq!(env.last_status, 3);
+}
+
+#[test]
+fn exit_status_code_of_wait_status() {
+    // Wait statuses as returned by `waitpid`
+    assert_eq!(exit_status_code(ExitStatus::from_raw(0)), 0);
+    assert_eq!(exit_status_code(ExitStatus::from_raw(3 << 8)), 3);
+    assert_eq!(exit_status_code(ExitStatus::from_raw(libc::SIGINT)), 130);
+    // Killed with a core dump
+    assert_eq!(
+        exit_status_code(ExitStatus::from_raw(libc::SIGSEGV | 0x80)),
+        139
+    );
+    // Stopped
+    assert_eq!(
+        exit_status_code(ExitStatus::from_raw((libc::SIGTSTP << 8) | 0x7f)),
+        148
+    );
+}
+
+#[test]
+fn history_print_expansion() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-history_print_expansion").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let base_dirs = directories::BaseDirs::new().expect("Failed to get base directories");
+    let env = Rc::new(RefCell::new(ExecEnv::new(base_dirs)));
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    rl.history_mut().add("echo first").unwrap();
+    rl.history_mut().add("ls -l").unwrap();
+
+    execute!(path, env, rl, "history -p '!!' '!e' 'x !-2 y' > {}");
+    assert_eq!(env.borrow().last_status, 0);
+
+    // Nothing is printed or run if an event can't be found
+    execute!(path, env, rl, "history -p '!!' '!nope' >> {}");
+    assert_eq!(env.borrow().last_status, 1);
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "ls -l\necho first\nx echo first y\n");
+    assert_eq!(rl.history().len(), 2);
+}
+
+/// Commands are resolved again each time, so a command which is moved or removed, or a
+/// change of `PATH`, is never resolved to a stale path. This must keep holding once
+/// resolved commands are cached.
+#[test]
+fn type_resolves_moved_command() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-type_resolves_moved_command").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    let command = first.path().join("mysh-test-command");
+    std::fs::write(&command, "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(&command, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let env = new_env();
+    env.borrow_mut().path_env = PathEnv::from_paths(vec![
+        first.path().to_path_buf(),
+        second.path().to_path_buf(),
+    ]);
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "type mysh-test-command > {}");
+    let moved = second.path().join("mysh-test-command");
+    std::fs::rename(&command, &moved).unwrap();
+    execute!(path, env, rl, "type mysh-test-command >> {}");
+    assert_eq!(
+        get_print_with_handler(temp_file.file()),
+        format!(
+            "mysh-test-command is {}\nmysh-test-command is {}\n",
+            command.display(),
+            moved.display()
+        )
+    );
+
+    env.borrow_mut().path_env = PathEnv::from_paths(vec![first.path().to_path_buf()]);
+    execute!(path, env, rl, "type mysh-test-command 2> {}");
+    assert_eq!(env.borrow().last_status, 1);
+
+    env.borrow_mut().path_env = PathEnv::from_paths(vec![second.path().to_path_buf()]);
+    std::fs::remove_file(&moved).unwrap();
+    execute!(path, env, rl, "type mysh-test-command 2> {}");
+    assert_eq!(env.borrow().last_status, 1);
+}
+
+#[test]
+fn exit_with_jobs() {
+    let env = new_env();
+    env.borrow_mut().interactive = true;
+    env.borrow()
+        .jobs
+        .add(1, String::from("vim"), vec![1], JobState::Stopped);
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let mut run = |line: &str| {
+        let context = ExecContext::new(rl.history_mut());
+        get_input_and_run(line, env.clone(), context)
+    };
+
+    // The first attempt is refused, an immediate second one exits
+    assert_eq!(run("exit 3"), CommandResult::Normal);
+    assert_eq!(env.borrow().last_status, 1);
+    assert_eq!(run("exit 3"), CommandResult::Exit(3));
+
+    // Another command in between resets the warning
+    assert_eq!(run("exit"), CommandResult::Normal);
+    assert_eq!(run("true"), CommandResult::Normal);
+    assert_eq!(run("exit"), CommandResult::Normal);
+    assert_eq!(run("exit"), CommandResult::Exit(1));
+
+    // Without job control, `exit` is not refused
+    env.borrow_mut().interactive = false;
+    assert_eq!(run("exit 4"), CommandResult::Exit(4));
+}
+
+#[test]
+fn nul_bytes_in_external_commands() {
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let mut status = |line: &str| {
+        let context = ExecContext::new(rl.history_mut());
+        get_input_and_run(line, env.clone(), context);
+        env.borrow().last_status
+    };
+
+    // NUL can't be passed to `execve`
+    assert_eq!(status("true a\0b"), 126);
+    assert_eq!(status("tr\0ue"), 126);
+    assert_eq!(status("FOO=a\0b true"), 126);
+    assert_eq!(status("true 'a b'"), 0);
+    // Builtins take any bytes
+    assert_eq!(status("type '\0' 2> /dev/null"), 1);
+}
+
+#[test]
+fn export() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-export").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "MYSH_LATER=later > {}");
+    execute!(path, env, rl, "MYSH_HIDDEN=hidden >> {}");
+    execute!(
+        path,
+        env,
+        rl,
+        "export MYSH_EXPORTED=\"a \\\"b\\\"\" MYSH_LATER >> {}"
+    );
+    assert_eq!(env.borrow().last_status(), 0);
+    execute!(path, env, rl, "env >> {}");
+    // Names without a value are listed as they are
+    execute!(path, env, rl, "export MYSH_UNSET >> {}");
+    execute!(path, env, rl, "export >> {}");
+    execute!(path, env, rl, "export 1x MYSH_OK=1 >> {}");
+    assert_eq!(env.borrow().last_status(), 1);
+
+    let output = get_print_with_handler(temp_file.file());
+    let lines: Vec<_> = output.lines().collect();
+    assert!(lines.contains(&"MYSH_EXPORTED=a \"b\""));
+    // Re-exporting keeps the value
+    assert!(lines.contains(&"MYSH_LATER=later"));
+    assert!(!lines.iter().any(|line| line.starts_with("MYSH_HIDDEN")));
+    assert!(lines.contains(&"declare -x MYSH_EXPORTED=\"a \\\"b\\\"\""));
+    assert!(lines.contains(&"declare -x MYSH_UNSET"));
+    assert_eq!(env.borrow().get_var("MYSH_OK"), Some("1"));
+}
+
+#[test]
+fn test_expressions() {
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let mut status = |line: &str| {
+        let context = ExecContext::new(rl.history_mut());
+        get_input_and_run(line, env.clone(), context);
+        env.borrow().last_status
+    };
+
+    assert_eq!(status("test"), 1);
+    assert_eq!(status("test abc"), 0);
+    assert_eq!(status("test ''"), 1);
+    assert_eq!(status("[ -n abc ]"), 0);
+    assert_eq!(status("[ -z abc ]"), 1);
+    assert_eq!(status("[ abc = abc ]"), 0);
+    assert_eq!(status("[ abc != abc ]"), 1);
+    assert_eq!(status("[ 10 -gt 9 ]"), 0);
+    assert_eq!(status("[ 10 -lt 9 ]"), 1);
+    assert_eq!(status("[ ! 1 -eq 1 ]"), 1);
+    assert_eq!(status("[ -d / -a -f / ]"), 1);
+    assert_eq!(status("[ -d / -o -f / ]"), 0);
+    assert_eq!(status("[ ( a = b -o a = a ) -a -n x ]"), 0);
+    // A binary operator comes first
+    assert_eq!(status("[ -n = -n ]"), 0);
+
+    assert_eq!(status("[ a = a"), 2);
+    assert_eq!(status("[ x -eq 1 ]"), 2);
+    assert_eq!(status("[ ( a ]"), 2);
+}
+
+#[test]
+fn test_file_comparisons() {
+    let dir = tempfile::tempdir().unwrap();
+    let old = dir.path().join("old");
+    let new = dir.path().join("new");
+    let missing = dir.path().join("missing");
+    let now = std::time::SystemTime::now();
+    std::fs::File::create(&old)
+        .unwrap()
+        .set_modified(now - std::time::Duration::from_secs(60))
+        .unwrap();
+    std::fs::File::create(&new)
+        .unwrap()
+        .set_modified(now)
+        .unwrap();
+    let link = dir.path().join("link");
+    std::os::unix::fs::symlink(&old, &link).unwrap();
+
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let mut status = |left: &std::path::Path, op: &str, right: &std::path::Path| {
+        let line = format!("[ {} {} {} ]", left.display(), op, right.display());
+        let context = ExecContext::new(rl.history_mut());
+        get_input_and_run(&line, env.clone(), context);
+        env.borrow().last_status
+    };
+
+    assert_eq!(status(&new, "-nt", &old), 0);
+    assert_eq!(status(&old, "-nt", &new), 1);
+    assert_eq!(status(&old, "-ot", &new), 0);
+    assert_eq!(status(&new, "-ot", &old), 1);
+    assert_eq!(status(&new, "-nt", &new), 1);
+    // A file which exists is newer than one which doesn't
+    assert_eq!(status(&old, "-nt", &missing), 0);
+    assert_eq!(status(&missing, "-ot", &old), 0);
+    assert_eq!(status(&missing, "-nt", &missing), 1);
+
+    // `-ef` follows symbolic links
+    assert_eq!(status(&link, "-ef", &old), 0);
+    assert_eq!(status(&old, "-ef", &old), 0);
+    assert_eq!(status(&old, "-ef", &new), 1);
+    assert_eq!(status(&missing, "-ef", &missing), 1);
+}
+
+#[test]
+fn unset() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-unset").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "export MYSH_A=a > {}");
+    execute!(path, env, rl, "MYSH_B=b >> {}");
+    execute!(
+        path,
+        env,
+        rl,
+        "unset MYSH_A MYSH_B MYSH_NO_SUCH_VARIABLE >> {}"
+    );
+    assert_eq!(env.borrow().last_status(), 0);
+    execute!(path, env, rl, "echo [$MYSH_A] [$MYSH_B] >> {}"); // [] []
+    execute!(path, env, rl, "sh -c 'echo [$MYSH_A]' >> {}"); // []
+    // Variables of the environment are hidden, including from commands
+    execute!(path, env, rl, "unset HOME >> {}");
+    execute!(path, env, rl, "echo [$HOME] >> {}"); // []
+    execute!(path, env, rl, "sh -c 'echo [$HOME]' >> {}"); // []
+    execute!(path, env, rl, "unset 1x >> {}");
+    assert_eq!(env.borrow().last_status(), 1);
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "[] []\n[]\n[]\n[]\n");
+    assert!(!env.borrow().exported.contains("MYSH_A"));
+    // The environment of the process is kept
+    assert!(std::env::var_os("HOME").is_some());
+}
+
+#[test]
+fn aliases() {
+    let mut shell = Shell::new(new_env());
+    shell.run("alias say=echo ls='ls -d' twice='echo a; echo b' sudo='env ' q=\"it's\"");
+    assert_eq!(shell.capture("say hi"), (String::from("hi\n"), 0));
+    // Recursion stops at the alias being expanded
+    assert_eq!(shell.capture("ls /"), (String::from("/\n"), 0));
+    assert_eq!(shell.capture("twice | cat"), (String::from("a\nb\n"), 0));
+    // Only command names, and the next word after a value ending with a blank
+    assert_eq!(shell.capture("echo say"), (String::from("say\n"), 0));
+    assert_eq!(shell.capture("sudo say x"), (String::from("x\n"), 0));
+    assert_eq!(
+        shell.capture("! say x || say y"),
+        (String::from("x\ny\n"), 0)
+    );
+    // A quoted name is not expanded
+    assert_eq!(shell.capture("\\say 2>/dev/null").1, 127);
+
+    assert_eq!(
+        shell.capture("alias"),
+        (
+            String::from(
+                "alias ls='ls -d'\n\
+                 alias q='it'\\''s'\n\
+                 alias say='echo'\n\
+                 alias sudo='env '\n\
+                 alias twice='echo a; echo b'\n"
+            ),
+            0
+        )
+    );
+    assert_eq!(
+        shell.capture("alias say nope 2>/dev/null"),
+        (String::from("alias say='echo'\n"), 1)
+    );
+    assert_eq!(shell.capture("alias 'a b=c' 2>/dev/null").1, 1);
+    assert_eq!(
+        shell.capture("type say"),
+        (String::from("say is aliased to `echo'\n"), 0)
+    );
+
+    assert_eq!(shell.capture("unalias say nope 2>/dev/null").1, 1);
+    assert_eq!(shell.capture("say 2>/dev/null").1, 127);
+    shell.run("unalias -a");
+    assert_eq!(shell.capture("alias"), (String::new(), 0));
+}
+
+#[test]
+fn source() {
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("script.msh");
+    std::fs::write(
+        &script,
+        "# set up\nX=hello\nalias greet='echo $X'\n\n  # indented\necho $# $1 \\\n  more\n",
+    )
+    .unwrap();
+    let script = script.display();
+
+    let mut shell = Shell::new(new_env());
+    // Variables and aliases are kept, and the arguments are the positional parameters
+    assert_eq!(
+        shell.capture(&format!("source {} a b", script)),
+        (String::from("2 a more\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("greet; echo $#"),
+        (String::from("hello\n0\n"), 0)
+    );
+    assert_eq!(
+        shell.capture(&format!("X=bye; . {} | tr a-z A-Z", script)),
+        (String::from("0 MORE\n"), 0)
+    );
+
+    std::fs::write(dir.path().join("exit.msh"), "false\nexit 3\necho no\n").unwrap();
+    let exit = dir.path().join("exit.msh");
+    assert_eq!(
+        shell
+            .capture(&format!("source {}; echo no", exit.display()))
+            .1,
+        3
+    );
+
+    // Quotes continue on the next line, but the file can't end in them
+    std::fs::write(dir.path().join("quote.msh"), "echo \"a\nb\"\necho 'c\n").unwrap();
+    let quote = dir.path().join("quote.msh");
+    assert_eq!(
+        shell.capture(&format!("source {}", quote.display())),
+        (String::from("a\nb\n"), 2)
+    );
+    std::fs::write(dir.path().join("backslash.msh"), "echo a\necho b \\").unwrap();
+    let backslash = dir.path().join("backslash.msh");
+    assert_eq!(
+        shell.capture(&format!("source {}", backslash.display())),
+        (String::from("a\n"), 2)
+    );
+
+    let missing = dir.path().join("missing");
+    assert_eq!(shell.capture(&format!("source {}", missing.display())).1, 1);
+    assert_eq!(shell.capture("source").1, 2);
 }
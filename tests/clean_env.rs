@@ -0,0 +1,70 @@
+use std::io;
+
+use mysh::{completion::ShellCompleter, env::ExecContext, get_input_and_run};
+use rustyline::Editor;
+
+use crate::common::{TempFile, get_print_with_handler, new_env};
+
+#[macro_use]
+mod common;
+
+fn sorted_lines(s: &str) -> Vec<&str> {
+    let mut lines: Vec<_> = s.lines().collect();
+    lines.sort_unstable();
+    lines
+}
+
+#[test]
+fn command_clean_env() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-command_clean_env").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "command -i env > {}");
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(sorted_lines(&output), ["PATH=/usr/bin:/bin"]);
+
+    // assignments before `command` apply on top of the clean environment
+    execute!(path, env, rl, "FOO=bar BAZ='a b' command -i env > {}");
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(
+        sorted_lines(&output),
+        ["BAZ=a b", "FOO=bar", "PATH=/usr/bin:/bin"]
+    );
+
+    // including `PATH` itself
+    execute!(path, env, rl, "PATH=/bin command -i -- env > {}");
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(sorted_lines(&output), ["PATH=/bin"]);
+}
+
+#[test]
+fn assignment_without_clean_env() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-assignment_env").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "MYSH_TEST_FOO=bar env > {}");
+    let output = get_print_with_handler(temp_file.file());
+    let lines = sorted_lines(&output);
+    assert!(lines.contains(&"MYSH_TEST_FOO=bar"));
+    assert!(lines.len() > 1);
+}
+
+#[test]
+fn quoted_assignment_is_command_name() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-quoted_assignment").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    // `'FOO=bar'` is not an assignment, so it is run as a command and fails
+    execute!(path, env, rl, "'FOO=bar' env > {}");
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "");
+}
@@ -0,0 +1,1139 @@
+use std::{
+    ffi::OsStr,
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    process::{Command, Output, Stdio},
+};
+
+fn mysh(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .args(args)
+        .output()
+        .expect("Failed to run mysh")
+}
+
+#[test]
+fn version() {
+    let output = mysh(&["--version"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("mysh {}\n", env!("CARGO_PKG_VERSION"))
+    );
+}
+
+#[test]
+fn invalid_option() {
+    let output = mysh(&["--no-such-option"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn dump_parse() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("created");
+    let output = mysh(&[
+        "--dump-parse",
+        &format!(
+            "X+=1 echo 'a b' $1 $(touch {}) 2>&1 | wc -l >> \"$out\"",
+            file.display()
+        ),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "command 1
+  assignment X+= Text(\"1\")
+  name Text(\"echo\")
+  argument Text(\"a b\", quoted)
+  argument Param(1)
+  argument Command(\"touch {}\")
+  redirect 2>& Text(\"1\")
+| command 2
+  name Text(\"wc\")
+  argument Text(\"-l\")
+  redirect 1>> Text(\"\", quoted) Param(out, quoted)
+",
+            file.display()
+        )
+    );
+    // Nothing runs
+    assert!(!file.exists());
+
+    let output = mysh(&["--dump-parse", "echo ${x"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        output.stderr,
+        b"mysh: unexpected EOF while looking for matching `}'\n"
+    );
+}
+
+#[test]
+fn command_string_positional_params() {
+    let output = mysh(&["-c", "echo $1", "foo", "bar"]);
+    assert_eq!(output.stdout, b"foo\n");
+
+    let output = mysh(&["-c", "echo $# \"$2\" $3", "foo", "b  ar"]);
+    assert_eq!(output.stdout, b"2 b  ar\n");
+}
+
+#[test]
+fn command_string_all_params() {
+    let output = mysh(&["-c", "echo \"$@\" x $*", "a  b", "c"]);
+    assert_eq!(output.stdout, b"a  b c x a b c\n");
+}
+
+#[test]
+fn posix_cd_home_not_set() {
+    let output = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "--posix", "-c", "cd"])
+        .env_remove("HOME")
+        .output()
+        .unwrap();
+    assert_eq!(output.stderr, b"cd: HOME not set\n");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "-c", "cd"])
+        .env_remove("HOME")
+        .output()
+        .unwrap();
+    assert_eq!(output.stderr, b"");
+    assert!(output.status.success());
+
+    // `unset` hides the `HOME` of the environment
+    let output = mysh(&["--posix", "-c", "unset HOME && cd"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stderr, b"cd: HOME not set\n");
+}
+
+#[test]
+fn cd_home_shell_variable() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    let script = format!("HOME={} && cd && pwd", dir.display());
+    let output = mysh(&["-c", &script]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("{}\n", dir.display()).as_bytes());
+
+    // An empty `HOME` keeps the working directory
+    let output = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "--posix", "-c", "HOME= && cd && pwd"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("{}\n", dir.display()).as_bytes());
+}
+
+#[test]
+fn set_posix_option() {
+    let output = mysh(&["-c", "set -o"]);
+    assert_eq!(
+        output.stdout,
+        b"posix          off\nmultios        off\nemacs          on\nvi             off\nautocd         off\nsuggest        on\ncorrect        off\nnotify         off\nhistory        on\n"
+    );
+
+    let output = mysh(&["--posix", "-c", "set +o"]);
+    assert_eq!(
+        output.stdout,
+        b"set -o posix\nset +o multios\nset -o emacs\nset +o vi\nset +o autocd\nset -o suggest\nset +o correct\nset +o notify\nset -o history\n"
+    );
+
+    let output = mysh(&["-c", "set -o no_such_option"]);
+    assert_eq!(output.stderr, b"set: no_such_option: invalid option name\n");
+}
+
+#[test]
+fn builtin_only_runs_builtins() {
+    let output = mysh(&["-c", "builtin echo hello"]);
+    assert_eq!(output.stdout, b"hello\n");
+
+    let output = mysh(&["-c", "builtin cat /dev/null"]);
+    assert_eq!(output.stdout, b"");
+    assert_eq!(output.stderr, b"builtin: cat: not a shell builtin\n");
+}
+
+#[test]
+fn append_to_environment_variable() {
+    let output = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "-c", "X+=bar printenv X"])
+        .env("X", "foo")
+        .output()
+        .expect("Failed to run mysh");
+    assert_eq!(output.stdout, b"foobar\n");
+}
+
+#[test]
+fn cd_parent_of_symlink_is_logical() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    std::os::unix::fs::symlink("a/b", dir.path().join("link")).unwrap();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+
+    // `..` removes `link` from `$PWD`, instead of going to the physical parent `a`
+    let script = format!("cd {}/link\ncd ..\npwd\nls\n", dir.path().display());
+    let output = mysh_interactive(&[("HISTFILE", histfile.path().as_os_str())], &script);
+
+    let expected = format!("{}\na\nlink\n", dir.path().display());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+/// Run mysh interactively with the script as stdin.
+fn mysh_interactive(envs: &[(&str, &OsStr)], script: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run mysh");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(script.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn prompt_command() {
+    let output = mysh_interactive(
+        &[("PROMPT_COMMAND", OsStr::new("echo tick"))],
+        "echo a\nfalse\necho $?\nPROMPT_COMMAND='echo tock'\necho b\n",
+    );
+    assert!(output.status.success());
+    // `$?` is not changed by it
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tick\na\ntick\ntick\n1\ntick\ntock\nb\ntock\n"
+    );
+
+    let output = mysh_interactive(&[("PROMPT_COMMAND", OsStr::new("exit 5"))], "echo a\n");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn history_file_fallback() {
+    // Nothing can be created in /proc, even by root
+    let unwritable = OsStr::new("/proc/mysh-tests");
+    let home = tempfile::tempdir().unwrap();
+
+    let output = mysh_interactive(
+        &[
+            ("HISTFILE", unwritable),
+            ("XDG_DATA_HOME", unwritable),
+            ("HOME", home.path().as_os_str()),
+        ],
+        "echo hello\n",
+    );
+    assert_eq!(output.stdout, b"hello\n");
+    let histfile = home.path().join(".mysh_history");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "mysh: /proc/mysh-tests: can't be written, using {} instead\n",
+            histfile.display()
+        )
+    );
+    let history = std::fs::read_to_string(histfile).unwrap();
+    assert!(history.contains("echo hello"));
+}
+
+#[test]
+fn no_usable_history_file() {
+    let unwritable = OsStr::new("/proc/mysh-tests");
+
+    let output = mysh_interactive(
+        &[
+            ("HISTFILE", unwritable),
+            ("XDG_DATA_HOME", unwritable),
+            ("HOME", unwritable),
+            ("TMPDIR", unwritable),
+        ],
+        "echo hello\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("mysh: no usable history file"));
+    assert_eq!(stderr.lines().count(), 1);
+}
+
+#[test]
+fn corrupt_history_file_is_backed_up() {
+    let dir = tempfile::tempdir().unwrap();
+    let histfile = dir.path().join("history");
+    let garbage = b"echo \xff\xfe not utf-8\n";
+    std::fs::write(&histfile, garbage).unwrap();
+
+    let output = mysh_interactive(&[("HISTFILE", histfile.as_os_str())], "echo hello\n");
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello\n");
+
+    let backup = dir.path().join("history.bad");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("mysh: {}: can't load history", histfile.display())));
+    assert!(stderr.ends_with(&format!("moved to {}\n", backup.display())));
+
+    assert_eq!(std::fs::read(&backup).unwrap(), garbage);
+    let history = std::fs::read_to_string(&histfile).unwrap();
+    assert!(history.contains("echo hello"));
+    assert!(!history.contains("not utf-8"));
+}
+
+#[test]
+fn read_only_history_file_is_kept() {
+    // Permissions don't apply to root
+    if unsafe { libc::geteuid() } == 0 {
+        return;
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let histfile = dir.path().join("history");
+    std::fs::write(&histfile, "echo old\n").unwrap();
+    std::fs::set_permissions(&histfile, std::fs::Permissions::from_mode(0o444)).unwrap();
+    let home = tempfile::tempdir().unwrap();
+
+    let output = mysh_interactive(
+        &[
+            ("HISTFILE", histfile.as_os_str()),
+            ("XDG_DATA_HOME", home.path().as_os_str()),
+        ],
+        "echo hello\n",
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello\n");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with(&format!("mysh: {}: can't be written", histfile.display())));
+    assert_eq!(std::fs::read(&histfile).unwrap(), b"echo old\n");
+}
+
+#[test]
+fn pid_and_ppid() {
+    let child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "-c", "echo $$ \"$PPID\""])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run mysh");
+    let pid = child.id();
+    let output = child.wait_with_output().unwrap();
+
+    let expected = format!("{} {}\n", pid, std::process::id());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
+
+#[test]
+fn builtin_redirect_stderr_and_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out.txt");
+    let err = dir.path().join("err.txt");
+
+    let output = mysh(&["-c", &format!("type nosuch 2> {}", err.display())]);
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+    assert_eq!(std::fs::read(&err).unwrap(), b"nosuch: not found\n");
+
+    let output = mysh(&[
+        "-c",
+        &format!("type type 1> {} 2>> {}", out.display(), err.display()),
+    ]);
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+    assert_eq!(std::fs::read(&out).unwrap(), b"type is a shell builtin\n");
+    assert_eq!(std::fs::read(&err).unwrap(), b"nosuch: not found\n");
+
+    // The redirection takes precedence over the pipe
+    let output = mysh(&["-c", &format!("echo hello > {} | cat", out.display())]);
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read(&out).unwrap(), b"hello\n");
+}
+
+#[test]
+fn exit_status_of_shell() {
+    assert_eq!(mysh(&["-c", "false"]).status.code(), Some(1));
+    assert_eq!(mysh(&["-c", "true"]).status.code(), Some(0));
+    assert_eq!(mysh(&["-c", "type no-such-command"]).status.code(), Some(1));
+    assert_eq!(mysh(&["-c", "exit 3"]).status.code(), Some(3));
+    assert_eq!(mysh(&["-c", "exit 256"]).status.code(), Some(0));
+
+    let output = mysh(&["-c", "exit abc"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(output.stderr, b"exit: abc: numeric argument required\n");
+
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let histfile = [("HISTFILE", histfile.path().as_os_str())];
+    assert_eq!(
+        mysh_interactive(&histfile, "false\n").status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        mysh_interactive(&histfile, "false\nexit\n").status.code(),
+        Some(1)
+    );
+    assert_eq!(
+        mysh_interactive(&histfile, "exit 4\necho no\n")
+            .status
+            .code(),
+        Some(4)
+    );
+}
+
+#[test]
+fn redirect_without_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+
+    let output = mysh(&["-c", &format!("> {}", path("new"))]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read(path("new")).unwrap(), b"");
+
+    std::fs::write(path("existing"), "content\n").unwrap();
+    let output = mysh(&["-c", &format!(">> {}", path("existing"))]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read(path("existing")).unwrap(), b"content\n");
+
+    let output = mysh(&["-c", &format!("> {} > {}", path("a"), path("b"))]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read(path("a")).unwrap(), b"");
+    assert_eq!(std::fs::read(path("b")).unwrap(), b"");
+
+    // Truncated
+    let output = mysh(&["-c", &format!("> {}", path("existing"))]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read(path("existing")).unwrap(), b"");
+
+    let output = mysh(&["-c", &format!("< {}", path("existing"))]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("mysh: {}: no command to read the input\n", path("existing"))
+    );
+}
+
+#[test]
+fn multiple_redirections_last_wins() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    std::fs::write(path("in1"), "one\n").unwrap();
+    std::fs::write(path("in2"), "two\n").unwrap();
+
+    // Every file is opened, but only the last one is used
+    let output = mysh(&["-c", &format!("echo hi > {} > {}", path("a"), path("b"))]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(path("a")).unwrap(), b"");
+    assert_eq!(std::fs::read(path("b")).unwrap(), b"hi\n");
+
+    let output = mysh(&["-c", &format!("cat < {} < {}", path("in1"), path("in2"))]);
+    assert_eq!(output.stdout, b"two\n");
+}
+
+#[test]
+fn multios_redirections() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    std::fs::write(path("in1"), "one\n").unwrap();
+    std::fs::write(path("in2"), "two\n").unwrap();
+    std::fs::write(path("old"), "old\n").unwrap();
+    std::fs::write(path("truncated"), "old\n").unwrap();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+
+    let script = format!(
+        "set -o multios\n\
+         echo hi > {a} > {b}\n\
+         printf x >> {old} > {truncated}\n\
+         cat < {in1} < {in2}\n\
+         cat < {in1} < {in2} > {c} >> {old}\n",
+        a = path("a"),
+        b = path("b"),
+        c = path("c"),
+        old = path("old"),
+        truncated = path("truncated"),
+        in1 = path("in1"),
+        in2 = path("in2"),
+    );
+    let output = mysh_interactive(&[("HISTFILE", histfile.path().as_os_str())], &script);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"one\ntwo\n");
+
+    let read = |name| String::from_utf8(std::fs::read(path(name)).unwrap()).unwrap();
+    assert_eq!(read("a"), "hi\n");
+    assert_eq!(read("b"), "hi\n");
+    assert_eq!(read("truncated"), "x");
+    assert_eq!(read("c"), "one\ntwo\n");
+    assert_eq!(read("old"), "old\nxone\ntwo\n");
+}
+
+#[test]
+fn redirect_errors_skip_the_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    std::fs::write(path("file"), "").unwrap();
+    // A file can't be created under a file, even by root
+    let unwritable = format!("{}/out", path("file"));
+
+    for command in ["echo hi", "sh -c 'echo hi'"] {
+        let output = mysh(&["-c", &format!("{} > {}", command, unwritable)]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            format!("mysh: {}: Not a directory\n", unwritable)
+        );
+
+        // Nothing runs, and the files before it are still created like bash
+        let output = mysh(&[
+            "-c",
+            &format!(
+                "{} > {} < {} 2> {}",
+                command,
+                path("a"),
+                path("missing"),
+                path("b")
+            ),
+        ]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(
+            String::from_utf8(output.stderr).unwrap(),
+            format!("mysh: {}: No such file or directory\n", path("missing"))
+        );
+        assert!(!std::path::Path::new(&path("b")).exists());
+    }
+
+    let output = mysh(&["-c", &format!("echo hi > {}", dir.path().display())]);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("mysh: {}: Is a directory\n", dir.path().display())
+    );
+
+    // The same message with `multios`
+    let script = format!("set -o multios; echo hi > {} > {}", path("a"), unwritable);
+    let output = mysh(&["-c", &script]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("mysh: {}: Not a directory\n", unwritable)
+    );
+}
+
+#[test]
+fn duplicate_fds() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    let out_and_err = "sh -c 'echo out; echo err >&2'";
+
+    // Both go to the file
+    let output = mysh(&["-c", &format!("{} > {} 2>&1", out_and_err, path("a"))]);
+    assert!(output.stderr.is_empty());
+    assert_eq!(std::fs::read(path("a")).unwrap(), b"out\nerr\n");
+
+    // stderr goes where stdout was before
+    let output = mysh(&["-c", &format!("{} 2>&1 > {}", out_and_err, path("b"))]);
+    assert_eq!(output.stdout, b"err\n");
+    assert!(output.stderr.is_empty());
+    assert_eq!(std::fs::read(path("b")).unwrap(), b"out\n");
+
+    let output = mysh(&["-c", &format!("{} &> {}", out_and_err, path("c"))]);
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    let output = mysh(&["-c", &format!("{} &>> {}", out_and_err, path("c"))]);
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+    assert_eq!(std::fs::read(path("c")).unwrap(), b"out\nerr\nout\nerr\n");
+
+    // Like bash, `>&file` is `&>file`
+    mysh(&["-c", &format!("{} >& {}", out_and_err, path("d"))]);
+    assert_eq!(std::fs::read(path("d")).unwrap(), b"out\nerr\n");
+
+    let output = mysh(&["-c", "echo hi >&2"]);
+    assert!(output.stdout.is_empty());
+    assert_eq!(output.stderr, b"hi\n");
+
+    // Through a pipe, for external commands and builtins
+    let output = mysh(&["-c", &format!("{} 2>&1 | sort -r", out_and_err)]);
+    assert_eq!(output.stdout, b"out\nerr\n");
+    assert!(output.stderr.is_empty());
+    let output = mysh(&["-c", "type nosuch 2>&1 | tr a-z A-Z"]);
+    assert_eq!(output.stdout, b"NOSUCH: NOT FOUND\n");
+    assert!(output.stderr.is_empty());
+
+    // Input
+    std::fs::write(path("in"), "input\n").unwrap();
+    let output = mysh(&["-c", &format!("cat 3< {} <&3", path("in"))]);
+    assert_eq!(output.stdout, b"input\n");
+
+    // A fd which is not open is an error, instead of whatever the shell has there
+    for command in ["cat <&4", "sh -c 'echo hi' >&4", "echo hi >&4"] {
+        let output = mysh(&["-c", command]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        assert_eq!(
+            output.stderr,
+            b"mysh: redirection error: 4: Bad file descriptor\n"
+        );
+    }
+
+    let output = mysh(&["-c", "echo hi 2>&foo"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stderr, b"mysh: foo: ambiguous redirect\n");
+}
+
+#[test]
+fn redirect_high_fds() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    std::fs::write(path("a"), "a\n").unwrap();
+    std::fs::write(path("b"), "b\n").unwrap();
+
+    // Fds from 10 are where the shell opens the files first, they must still be set
+    let output = mysh(&[
+        "-c",
+        &format!(
+            "cat /dev/fd/10 /dev/fd/11 10< {} 11< {}",
+            path("a"),
+            path("b")
+        ),
+    ]);
+    assert_eq!(output.stdout, b"a\nb\n");
+    assert!(output.stderr.is_empty());
+
+    let script = format!(
+        "echo hi | tee /dev/fd/10 /dev/fd/12 10> {} 12> {} 11>&10",
+        path("c"),
+        path("d")
+    );
+    let output = mysh(&["-c", &script]);
+    assert_eq!(output.stdout, b"hi\n");
+    assert_eq!(std::fs::read(path("c")).unwrap(), b"hi\n");
+    assert_eq!(std::fs::read(path("d")).unwrap(), b"hi\n");
+}
+
+#[test]
+fn duplicate_input_fds() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    std::fs::write(path("a"), "a\n").unwrap();
+    std::fs::write(path("b"), "b\n").unwrap();
+
+    // There is no `exec` to open a fd for the following commands, so it is opened by the
+    // same command. Like bash, inputs are applied in the order they are written.
+    let output = mysh(&["-c", &format!("cat 3< {} <&3 3< {}", path("a"), path("b"))]);
+    assert_eq!(output.stdout, b"a\n");
+    // Fd 3 is not open yet, the same for builtins and external commands
+    for command in ["cat", "type echo"] {
+        let output = mysh(&["-c", &format!("{} 0<&3 3< {}", command, path("a"))]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        assert_eq!(
+            output.stderr,
+            b"mysh: redirection error: 3: Bad file descriptor\n"
+        );
+    }
+
+    // For builtins, and a fd other than stdin
+    let output = mysh(&["-c", &format!("type echo 3< {} 4<&3 <&4", path("a"))]);
+    assert_eq!(output.stdout, b"echo is a shell builtin\n");
+    let output = mysh(&["-c", "type echo <&9"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        output.stderr,
+        b"mysh: redirection error: 9: Bad file descriptor\n"
+    );
+
+    // A here-string can be read through another fd
+    let output = mysh(&["-c", "cat 3<<< hi <&3 | tr a-z A-Z"]);
+    assert_eq!(output.stdout, b"HI\n");
+}
+
+#[test]
+fn source_missing_file() {
+    let output = mysh(&["-c", "source /nonexistent/script.msh"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        output.stderr,
+        b"source: /nonexistent/script.msh: No such file or directory\n"
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let script = dir.path().join("script.msh");
+    std::fs::write(&script, "echo 'a\n").unwrap();
+    let output = mysh(&["-c", &format!("source {}", script.display())]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("source: {}: unexpected end of file\n", script.display())
+    );
+}
+
+#[test]
+fn here_strings() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+
+    let output = mysh(&[
+        "-c",
+        &format!("cat <<< \"hello  $1\" > {}", path("out")),
+        "world",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(path("out")).unwrap(), b"hello  world\n");
+
+    // Nothing is expanded in single quotes
+    let output = mysh(&["-c", "cat <<< '$1' <<<$1", "x"]);
+    assert_eq!(output.stdout, b"x\n");
+    let output = mysh(&["-c", "cat <<< '$1'", "x"]);
+    assert_eq!(output.stdout, b"$1\n");
+
+    // To another fd, and larger than a pipe can hold
+    let output = mysh(&["-c", "cat 3<<< three <&3"]);
+    assert_eq!(output.stdout, b"three\n");
+    let output = mysh(&["-c", "wc -c <<< $(printf %070000d 0)"]);
+    assert_eq!(output.stdout, b"70001\n");
+
+    // Without a command, it is ignored
+    let output = mysh(&["-c", "<<< text"]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty() && output.stderr.is_empty());
+}
+
+#[test]
+fn here_documents() {
+    let output = mysh(&["-c", "cat <<EOF\nline $1\n  two \\$1\nEOF", "one"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"line one\n  two $1\n");
+
+    // A quoted delimiter keeps the body literal, and `<<-` removes leading tabs
+    let output = mysh(&[
+        "-c",
+        "cat <<'EOF' 3<<-END <&3\n$1\nEOF\n\t\tthree $1\n\tEND",
+        "x",
+    ]);
+    assert_eq!(output.stdout, b"three x\n");
+    let output = mysh(&["-c", "cat <<\"E\"OF\n$1 \\$1\nEOF", "x"]);
+    assert_eq!(output.stdout, b"$1 \\$1\n");
+
+    // Lines are read until the delimiter when interactive
+    let output = mysh_interactive(
+        &[],
+        "cat <<EOF | tr a-z A-Z\nfirst\nsecond\nEOF\necho done\n",
+    );
+    assert_eq!(output.stdout, b"FIRST\nSECOND\ndone\n");
+
+    let output = mysh(&["-c", "cat <<\n"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        output.stderr,
+        b"mysh: syntax error near unexpected token `newline'\n"
+    );
+}
+
+#[test]
+fn process_substitution() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    std::fs::write(path("a"), "b\na\n").unwrap();
+    std::fs::write(path("b"), "a\nb\n").unwrap();
+
+    let output = mysh(&[
+        "-c",
+        &format!("diff <(sort {}) <(sort {})", path("a"), path("b")),
+    ]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // The command sees the variables of the shell, and its output is written before the
+    // shell goes on
+    let output = mysh(&["-c", "cat <(echo $1) <(echo two) && echo done", "one"]);
+    assert_eq!(output.stdout, b"one\ntwo\ndone\n");
+    let output = mysh(&[
+        "-c",
+        &format!("echo hello > >(tr a-z A-Z > {})", path("out")),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(path("out")).unwrap(), b"HELLO\n");
+
+    // The reader may exit before the end of the output
+    let output = mysh(&["-c", "head -1 <(yes)"]);
+    assert_eq!(output.stdout, b"y\n");
+}
+
+#[test]
+fn bad_fd_duplication_keeps_the_shell_running() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("out").display().to_string();
+
+    // fd 42 is not open, so the builtin is skipped, and the redirection to the file before
+    // it is undone
+    let output = mysh_interactive(
+        &[],
+        &format!(
+            "echo hi > {} 2>&42\necho $?\necho hi >&42 | cat\necho alive\n",
+            file
+        ),
+    );
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"1\nalive\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "mysh: redirection error: 42: Bad file descriptor\n".repeat(2)
+    );
+    assert_eq!(std::fs::read(&file).unwrap(), b"");
+}
+
+#[test]
+fn interactive_flag() {
+    let output = mysh(&["-c", "echo \"[$-]\""]);
+    assert_eq!(output.stdout, b"[]\n");
+
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let output = mysh_interactive(
+        &[("HISTFILE", histfile.path().as_os_str())],
+        "echo \"[$-]\"\n",
+    );
+    assert_eq!(output.stdout, b"[i]\n");
+}
+
+#[test]
+fn builtin_redirections_with_stdin_closed() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = |name| dir.path().join(name).display().to_string();
+    // With stdin closed, the first opened file gets fd 0
+    let run = |command: &str| {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_mysh"));
+        cmd.args(["--no-config", "-c", command]);
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(&mut cmd, || {
+                libc::close(0);
+                Ok(())
+            });
+        }
+        cmd.output().expect("Failed to run mysh")
+    };
+
+    let output = run(&format!(
+        "type nosuch 2> {} 1> {} 3> {}",
+        path("err"),
+        path("out"),
+        path("three")
+    ));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+    assert_eq!(std::fs::read(path("err")).unwrap(), b"nosuch: not found\n");
+    assert_eq!(std::fs::read(path("out")).unwrap(), b"");
+    assert_eq!(std::fs::read(path("three")).unwrap(), b"");
+
+    let output = run(&format!(
+        "type type > {} 2> {} > {}",
+        path("out"),
+        path("err"),
+        path("last")
+    ));
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read(path("out")).unwrap(), b"");
+    assert_eq!(std::fs::read(path("err")).unwrap(), b"");
+    assert_eq!(
+        std::fs::read(path("last")).unwrap(),
+        b"type is a shell builtin\n"
+    );
+
+    // An fd which is not open is closed again afterwards
+    let output = run(&format!("type type 9> {}", path("nine")));
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"type is a shell builtin\n");
+
+    // The shell's own output goes back to stdout after the redirections are restored
+    let output = run(&format!(
+        "echo hi 0< {} 2> {} | cat",
+        path("out"),
+        path("err")
+    ));
+    assert_eq!(output.stdout, b"hi\n");
+}
+
+#[test]
+fn builtin_output_stays_in_redirected_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out.txt");
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+
+    let script = format!(
+        "cd {}\npwd > {}\necho after\n",
+        dir.path().display(),
+        out.display()
+    );
+    let output = mysh_interactive(&[("HISTFILE", histfile.path().as_os_str())], &script);
+    assert_eq!(output.stdout, b"after\n");
+    assert_eq!(
+        std::fs::read_to_string(&out).unwrap(),
+        format!("{}\n", dir.path().display())
+    );
+}
+
+#[test]
+fn builtin_and_external_output_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out.txt");
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+
+    let script = format!(
+        "echo one\nprintf 'two\\n'\necho three | cat\ntype type\n\
+         echo a > {out}\ncat {out}\necho b | cat >> {out}\necho c | type type\n",
+        out = out.display()
+    );
+    let output = mysh_interactive(&[("HISTFILE", histfile.path().as_os_str())], &script);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "one\ntwo\nthree\ntype is a shell builtin\na\ntype is a shell builtin\n"
+    );
+    assert_eq!(std::fs::read(&out).unwrap(), b"a\nb\n");
+}
+
+#[test]
+fn trailing_backslash() {
+    let output = mysh(&["-c", r"echo foo\"]);
+    assert_eq!(output.stdout, b"foo\\\n");
+    let output = mysh(&["-c", r#"echo "foo\"#]);
+    assert_eq!(output.stdout, b"foo\\\n");
+
+    // Interactively, the line continues
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let histfile = [("HISTFILE", histfile.path().as_os_str())];
+    let output = mysh_interactive(&histfile, "echo foo\\\nbar \"a\\\nb\"\n");
+    assert_eq!(output.stdout, b"foobar ab\n");
+}
+
+#[test]
+fn printf_quote() {
+    let args = ["a b", "it's", "\"double\" 'single'", "", "plain"];
+    let mut command = vec!["-c", "printf '%q\\n' \"$@\""];
+    command.extend(args);
+    let output = mysh(&command);
+    assert!(output.status.success());
+    let quoted = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        quoted,
+        "'a b'\n'it'\\''s'\n'\"double\" '\\''single'\\'''\n''\nplain\n"
+    );
+
+    // The output is parsed back as the same arguments
+    let line = quoted.lines().collect::<Vec<_>>().join(" ");
+    let output = mysh(&["-c", &format!("printf '[%s]' {}", line)]);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[a b][it's][\"double\" 'single'][][plain]"
+    );
+}
+
+#[test]
+fn printf_format() {
+    let output = mysh(&["-c", r"printf '%s=%d;' a 1 b 0x10 c"]);
+    assert_eq!(output.stdout, b"a=1;b=16;c=0;");
+    let output = mysh(&["-c", r"printf '%5s|%-4s|%03d|%.2s|%x|%%\n' ab cd 7 xyz 255"]);
+    assert_eq!(output.stdout, b"   ab|cd  |007|xy|ff|%\n");
+    let output = mysh(&["-c", r"printf '%b|%s\n' 'a\tb' 'a\tb'"]);
+    assert_eq!(output.stdout, b"a\tb|a\\tb\n");
+
+    let output = mysh(&["-c", "printf '%d\\n' abc"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"0\n");
+    assert_eq!(output.stderr, b"printf: abc: invalid number\n");
+    let output = mysh(&["-c", "printf"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn printf_time() {
+    let printf = |script: &str| {
+        Command::new(env!("CARGO_BIN_EXE_mysh"))
+            .args(["--no-config", "-c", script])
+            .env("TZ", "UTC")
+            .output()
+            .unwrap()
+    };
+    let output = printf("printf '%(%Y-%m-%d %H:%M:%S)T|%8(%H:%M)T|%-6(%Y)T|\\n' 86400 3600 0");
+    assert_eq!(output.stdout, b"1970-01-02 00:00:00|   01:00|1970  |\n");
+
+    // `-1` and no argument are now, `-2` is when the shell started
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let output = printf("printf '%(%s)T %(%s)T %(%s)T'  -2 -1");
+    let times: Vec<u64> = String::from_utf8(output.stdout)
+        .unwrap()
+        .split(' ')
+        .map(|time| time.parse().unwrap())
+        .collect();
+    assert!(times.iter().all(|&time| time.abs_diff(now) < 60));
+    assert!(times[0] <= times[1] && times[1] <= times[2]);
+
+    let output = printf("printf '%(%Y)d'");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        output.stderr,
+        b"printf: `d': invalid time format specification\n"
+    );
+}
+
+#[test]
+fn autocd() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    std::fs::create_dir_all(dir.join("sub/inner")).unwrap();
+    std::fs::create_dir(dir.join("bin")).unwrap();
+    std::fs::create_dir(dir.join("tool")).unwrap();
+    let tool = dir.join("bin/tool");
+    std::fs::write(&tool, "#!/bin/sh\necho ran tool\n").unwrap();
+    std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let path = dir.join("bin");
+    let envs = [
+        ("HISTFILE", histfile.path().as_os_str()),
+        ("PATH", path.as_os_str()),
+    ];
+    let script = format!(
+        "set -o autocd\ncd {0}\nsub\npwd\ninner\npwd\n..\npwd\n{0}/sub/inner\npwd\n{0}\ntool\npwd\n",
+        dir.display()
+    );
+    let output = mysh_interactive(&envs, &script);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "{0}/sub\n{0}/sub/inner\n{0}/sub\n{0}/sub/inner\nran tool\n{0}\n",
+            dir.display()
+        )
+    );
+    assert_eq!(output.stderr, b"");
+
+    // Without the option, a directory is not a command
+    let script = format!("cd {}\nsub\npwd\n", dir.display());
+    let output = mysh_interactive(&envs, &script);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("{}\n", dir.display())
+    );
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn command_suggestions() {
+    let dir = tempfile::tempdir().unwrap();
+    let git = dir.path().join("git");
+    std::fs::write(&git, "#!/bin/sh\necho git \"$@\"\n").unwrap();
+    std::fs::set_permissions(&git, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let envs = [
+        ("HISTFILE", histfile.path().as_os_str()),
+        ("PATH", dir.path().as_os_str()),
+    ];
+
+    let output = mysh_interactive(&envs, "gti status\nehco\nzzzzzz\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "mysh: gti: command not found \u{2014} did you mean 'git'?\n\
+         mysh: ehco: command not found \u{2014} did you mean 'echo'?\n\
+         mysh: zzzzzz: command not found\n"
+    );
+
+    // Aliases are suggested, but not offered as corrections
+    let output = mysh_interactive(&envs, "alias gtt=true\ngtu\nset -o correct\ngtu\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "mysh: gtu: command not found \u{2014} did you mean 'gtt'?\n\
+         mysh: gtu: command not found \u{2014} did you mean 'gtt'?\n"
+    );
+
+    let output = mysh_interactive(&envs, "set +o suggest\ngti status\n");
+    assert_eq!(output.stderr, b"mysh: gti: command not found\n");
+
+    // With `correct`, the answer is read from the input
+    let output = mysh_interactive(&envs, "set -o correct\ngti status\ny\ngti\nn\ngti\na\n");
+    assert_eq!(output.stdout, b"git status\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "mysh: correct 'gti' to 'git' [nya]? \
+         mysh: correct 'gti' to 'git' [nya]? \
+         mysh: gti: command not found \u{2014} did you mean 'git'?\n\
+         mysh: correct 'gti' to 'git' [nya]? "
+    );
+}
+
+#[test]
+fn timeout() {
+    let output = mysh(&["-c", "timeout 5 sh -c 'echo ran; exit 3'"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"ran\n");
+
+    let start = std::time::Instant::now();
+    let output = mysh(&["-c", "timeout 0.1 sleep 10"]);
+    assert_eq!(output.status.code(), Some(124));
+    // A command which ignores `SIGTERM` is killed
+    let output = mysh(&[
+        "-c",
+        "timeout 0.1s sh -c 'trap \"\" TERM; while :; do :; done'",
+    ]);
+    assert_eq!(output.status.code(), Some(124));
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+
+    let output = mysh(&["-c", "timeout 1m no-such-command"]);
+    assert_eq!(output.status.code(), Some(127));
+    let output = mysh(&["-c", "timeout 1x true"]);
+    assert_eq!(output.status.code(), Some(125));
+    assert_eq!(output.stderr, b"timeout: invalid time interval '1x'\n");
+    let output = mysh(&["-c", "timeout 1"]);
+    assert_eq!(output.status.code(), Some(125));
+}
+
+#[test]
+fn pipeline_syntax_errors() {
+    let output = mysh(&["-c", "echo a | | cat"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        output.stderr,
+        b"mysh: syntax error near unexpected token `|'\n"
+    );
+}
+
+#[test]
+fn echo_many_arguments() {
+    let args: Vec<String> = (0..20_000).map(|i| i.to_string()).collect();
+    let mut argv = vec!["-c", "echo \"$@\""];
+    argv.extend(args.iter().map(String::as_str));
+    let output = mysh(&argv);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, format!("{}\n", args.join(" ")).into_bytes());
+}
+
+#[test]
+fn semicolon_segments_share_the_shell() {
+    // `cd` in one segment changes the directory of the external command in the next one
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    let script = format!("cd {}; /bin/pwd;; exit 3; echo no", dir.display());
+    let output = mysh(&["-c", &script]);
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, format!("{}\n", dir.display()).into_bytes());
+}
+
+#[test]
+fn suspend_without_job_control() {
+    // It would stop whatever started the shell in the same process group
+    for command in ["suspend", "suspend -f"] {
+        let output = mysh(&["-c", command]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(output.stderr, b"suspend: no job control\n");
+    }
+
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let output = mysh_interactive(
+        &[("HISTFILE", histfile.path().as_os_str())],
+        "suspend -f\necho $?\n",
+    );
+    assert_eq!(output.stdout, b"1\n");
+    assert_eq!(output.stderr, b"suspend: no job control\n");
+}
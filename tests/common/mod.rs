@@ -1,10 +1,16 @@
+#![allow(dead_code)]
+
 use std::{
+    cell::RefCell,
     ffi::OsStr,
     fs::File,
-    io,
+    io::{self, Read, Seek, SeekFrom},
     ops::{Deref, DerefMut},
+    rc::Rc,
 };
 
+use mysh::env::ExecEnv;
+
 use tempfile::NamedTempFile;
 
 pub struct TempFile {
@@ -41,3 +47,43 @@ impl Drop for TempFile {
         let _ = self.file.take().unwrap().close();
     }
 }
+
+pub fn read_from_temp(file: &mut File) -> String {
+    let mut vec = Vec::new();
+    file.read_to_end(&mut vec).unwrap();
+    String::from_utf8(vec).unwrap()
+}
+
+pub fn read_from_temp_u8(file: &mut File) -> Vec<u8> {
+    let mut vec = Vec::new();
+    file.read_to_end(&mut vec).unwrap();
+    vec
+}
+
+pub fn get_print_with_handler(file: &mut File) -> String {
+    let output = read_from_temp(file);
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.set_len(0).unwrap();
+    output
+}
+
+pub fn get_print_with_handler_u8(file: &mut File) -> Vec<u8> {
+    let output = read_from_temp_u8(file);
+    file.seek(SeekFrom::Start(0)).unwrap();
+    file.set_len(0).unwrap();
+    output
+}
+
+pub fn new_env() -> Rc<RefCell<ExecEnv>> {
+    let base_dirs = directories::BaseDirs::new().expect("Failed to get base directories");
+    Rc::new(RefCell::new(ExecEnv::new(base_dirs)))
+}
+
+/// Run a command line, `{}` in the line is replaced with the path.
+#[macro_export]
+macro_rules! execute {
+    ($path:expr, $env:expr, $rl:expr, $str:literal) => {
+        let context = ExecContext::new($rl.history_mut());
+        get_input_and_run(&format!($str, $path.display()), $env.clone(), context);
+    };
+}
@@ -0,0 +1,138 @@
+use mysh::{completion::ShellCompleter, env::PathEnv};
+use rustyline::{Context, completion::Completer, history::MemHistory};
+
+use crate::common::new_env;
+
+mod common;
+
+/// Complete at the end of the line, returns the start of the replacement and whether
+/// `echo` is a candidate.
+fn complete(completer: &ShellCompleter, line: &str) -> (usize, bool) {
+    let history = MemHistory::new();
+    let ctx = Context::new(&history);
+    let (start, candidates) = completer.complete(line, line.len(), &ctx).unwrap();
+    let echo = candidates.iter().any(|c| c.replacement == "echo ");
+    (start, echo)
+}
+
+#[test]
+fn command_word_boundary() {
+    let completer = ShellCompleter::new(new_env());
+
+    assert_eq!(complete(&completer, "ec"), (0, true));
+    assert_eq!(complete(&completer, "  ec"), (2, true));
+    assert_eq!(complete(&completer, "'ec"), (0, true));
+    assert_eq!(complete(&completer, "FOO=bar ec"), (8, true));
+    assert_eq!(complete(&completer, "ls | ec"), (5, true));
+    assert_eq!(complete(&completer, "ls|ec"), (3, true));
+    assert_eq!(complete(&completer, "> out ec"), (6, true));
+    assert_eq!(complete(&completer, "2>out ec"), (6, true));
+
+    // Not command names
+    assert_eq!(complete(&completer, "ls ec"), (3, false));
+    assert_eq!(complete(&completer, "ls > ec"), (5, false));
+    assert_eq!(complete(&completer, "FOO=ec"), (4, false));
+}
+
+#[test]
+fn argument_word_breaks() {
+    let mut completer = ShellCompleter::new(new_env());
+
+    assert_eq!(complete(&completer, "cmd --opt=pa").0, 10);
+    assert_eq!(complete(&completer, "cmd foo=bar/ba").0, 8);
+    assert_eq!(complete(&completer, "cmd a:b:c").0, 8);
+    // Quoted characters never break words
+    assert_eq!(complete(&completer, "cmd 'a=b'c").0, 4);
+    assert_eq!(complete(&completer, "cmd a\\=b").0, 4);
+    assert_eq!(complete(&completer, "PATH=/bin:/us").0, 10);
+
+    completer.set_word_breaks(&[',']);
+    assert_eq!(complete(&completer, "cmd --opt=a,b").0, 12);
+}
+
+/// Complete at the end of the line, returns the start of the replacement and the
+/// replacements.
+fn replacements(completer: &ShellCompleter, line: &str) -> (usize, Vec<String>) {
+    let history = MemHistory::new();
+    let ctx = Context::new(&history);
+    let (start, candidates) = completer.complete(line, line.len(), &ctx).unwrap();
+    (
+        start,
+        candidates.into_iter().map(|c| c.replacement).collect(),
+    )
+}
+
+#[test]
+fn cd_completes_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    for path in [
+        "src/completion",
+        "src/compat",
+        "src/.comp",
+        "target",
+        "my dir",
+    ] {
+        std::fs::create_dir_all(dir.path().join(path)).unwrap();
+    }
+    std::fs::write(dir.path().join("src/comp.rs"), "").unwrap();
+    let env = new_env();
+    env.borrow_mut().pwd = dir.path().to_path_buf();
+    let completer = ShellCompleter::new(env);
+
+    // The directory part is read and its entries are matched with the rest
+    assert_eq!(
+        replacements(&completer, "cd src/comp"),
+        (
+            3,
+            vec![String::from("src/compat/"), String::from("src/completion/")]
+        )
+    );
+    assert_eq!(
+        replacements(&completer, "cd src/.c"),
+        (3, vec![String::from("src/.comp/")])
+    );
+    assert_eq!(
+        replacements(&completer, "cd ./src/../t"),
+        (3, vec![String::from("./src/../target/")])
+    );
+    assert_eq!(
+        replacements(&completer, "cd my"),
+        (3, vec![String::from("'my dir'/")])
+    );
+    let absolute = format!("cd {}/src/completion", dir.path().display());
+    assert_eq!(
+        replacements(&completer, &absolute),
+        (3, vec![format!("{}/src/completion/", dir.path().display())])
+    );
+    assert_eq!(replacements(&completer, "cd missing/"), (3, Vec::new()));
+    // Only `cd` arguments are completed as directories
+    assert_eq!(replacements(&completer, "ls src/comp"), (3, Vec::new()));
+}
+
+#[test]
+fn path_cache_sees_new_executables() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let env = new_env();
+    env.borrow_mut().path_env = PathEnv::from_paths(vec![dir.path().to_owned()]);
+    let completer = ShellCompleter::new(env);
+    let candidates = |line: &str| {
+        let history = MemHistory::new();
+        let ctx = Context::new(&history);
+        let (_, candidates) = completer.complete(line, line.len(), &ctx).unwrap();
+        candidates
+            .into_iter()
+            .map(|c| c.display)
+            .collect::<Vec<_>>()
+    };
+
+    assert!(candidates("mycache").is_empty());
+    // The directory is read again once it changes
+    let tool = dir.path().join("mycache-tool");
+    std::fs::write(&tool, "").unwrap();
+    std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(candidates("mycache"), ["mycache-tool"]);
+    std::fs::remove_file(&tool).unwrap();
+    assert!(candidates("mycache").is_empty());
+}
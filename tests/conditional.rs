@@ -0,0 +1,94 @@
+use mysh::{completion::ShellCompleter, env::ExecContext, get_input_and_run};
+use rustyline::Editor;
+
+use crate::common::new_env;
+
+mod common;
+
+/// Run each line, and returns their statuses.
+fn statuses(lines: &[&str]) -> Vec<i32> {
+    let env = new_env();
+    env.borrow_mut()
+        .set_var(String::from("SPACED"), String::from("a  b"));
+    env.borrow_mut()
+        .set_var(String::from("STAR"), String::from("*"));
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    lines
+        .iter()
+        .map(|line| {
+            let context = ExecContext::new(rl.history_mut());
+            get_input_and_run(line, env.clone(), context);
+            env.borrow().last_status()
+        })
+        .collect()
+}
+
+#[test]
+fn patterns() {
+    assert_eq!(
+        statuses(&[
+            "[[ abc == a* ]]",
+            "[[ abc = *c ]]",
+            "[[ abc != a* ]]",
+            // Quoted parts only match themselves
+            "[[ abc == \"a*\" ]]",
+            "[[ 'a*' == a\\* ]]",
+            "[[ abc == $STAR ]]",
+            "[[ abc == \"$STAR\" ]]",
+        ]),
+        [0, 0, 1, 1, 0, 0, 1]
+    );
+}
+
+#[test]
+fn no_word_splitting() {
+    assert_eq!(
+        statuses(&[
+            "[[ $SPACED == \"a  b\" ]]",
+            "[[ -n $SPACED ]]",
+            "[[ -z $MYSH_NO_SUCH_VARIABLE ]]",
+            "[[ $MYSH_NO_SUCH_VARIABLE ]]",
+        ]),
+        [0, 0, 0, 1]
+    );
+}
+
+#[test]
+fn regex() {
+    assert_eq!(
+        statuses(&[
+            "[[ foo123 =~ ^fo+[0-9]+$ ]]",
+            "[[ xfoo =~ foo ]]",
+            "[[ foo =~ ^(bar|fo)o$ ]]",
+            "[[ abc =~ \"a.c\" ]]",
+            "[[ a.c =~ \"a.c\" ]]",
+            "[[ x =~ ( ]]",
+        ]),
+        [0, 0, 0, 1, 0, 2]
+    );
+}
+
+#[test]
+fn operators() {
+    assert_eq!(
+        statuses(&[
+            "[[ a < b ]]",
+            "[[ a > b ]]",
+            "[[ a < b && ( b < a || 1 -lt 2 ) ]]",
+            "[[ ! -d / || -z x ]]",
+            "[[ -d /&&-e / ]]",
+            "[[ a == a ]] | [[ a == b ]]",
+        ]),
+        [0, 1, 0, 1, 0, 1]
+    );
+}
+
+#[test]
+fn syntax_errors() {
+    assert_eq!(
+        statuses(&["[[ a", "[[ ]]", "[[ a b ]]", "[[ ( a ]]", "[[ a && ]]"]),
+        [2, 2, 2, 2, 2]
+    );
+    // Not a keyword unless it is the command name
+    assert_eq!(statuses(&["echo [[ a > /dev/null"]), [0]);
+}
@@ -0,0 +1,95 @@
+use std::{fs, path::PathBuf, process::Command};
+
+use mysh::config::{dump_config, load_config};
+
+use crate::common::new_env;
+
+mod common;
+
+#[test]
+fn round_trip() {
+    let env = new_env();
+    {
+        let mut env = env.borrow_mut();
+        env.options.posix = true;
+        env.histfile_env = Some(PathBuf::from("/tmp/some \"history\"\\file"));
+    }
+    let dumped = dump_config(&env.borrow());
+
+    let fresh = new_env();
+    let warnings = load_config(&mut fresh.borrow_mut(), &dumped);
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert!(fresh.borrow().options.posix);
+    assert_eq!(fresh.borrow().histfile_env, env.borrow().histfile_env);
+    assert_eq!(dump_config(&fresh.borrow()), dumped);
+}
+
+#[test]
+fn unknown_keys_are_warnings() {
+    let env = new_env();
+    let config = "\
+top = true
+[options]
+posix = true
+no_such_option = false
+posix = \"yes\"
+
+[future]
+anything = 1
+
+[history]
+file = \"unterminated
+";
+    let warnings = load_config(&mut env.borrow_mut(), config);
+    assert_eq!(
+        warnings,
+        [
+            "line 1: unknown key or invalid value `top` in section ``",
+            "line 4: unknown key or invalid value `no_such_option` in section `options`",
+            "line 5: unknown key or invalid value `posix` in section `options`",
+            "line 7: unknown section `future`",
+            "line 8: invalid value for `anything`",
+            "line 11: invalid value for `file`",
+        ]
+    );
+    // Valid lines are still applied
+    assert!(env.borrow().options.posix);
+}
+
+#[test]
+fn config_file_at_startup() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_dir = dir.path().join("mysh");
+    fs::create_dir_all(&config_dir).unwrap();
+    fs::write(
+        config_dir.join("config.toml"),
+        "[options]\nposix = true\nunknown = true\n",
+    )
+    .unwrap();
+
+    let mysh = || {
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_mysh"));
+        cmd.env("XDG_CONFIG_HOME", dir.path());
+        cmd
+    };
+
+    let output = mysh().args(["-c", "set +o"]).output().unwrap();
+    assert_eq!(
+        output.stdout,
+        b"set -o posix\nset +o multios\nset -o emacs\nset +o vi\nset +o autocd\nset -o suggest\nset +o correct\nset +o notify\nset -o history\n"
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("line 3: unknown key or invalid value `unknown`")
+    );
+
+    let output = mysh()
+        .args(["--no-config", "-c", "set +o"])
+        .output()
+        .unwrap();
+    assert_eq!(
+        output.stdout,
+        b"set +o posix\nset +o multios\nset -o emacs\nset +o vi\nset +o autocd\nset -o suggest\nset +o correct\nset +o notify\nset -o history\n"
+    );
+}
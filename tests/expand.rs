@@ -0,0 +1,280 @@
+use std::{ffi::OsString, io};
+
+use mysh::{
+    Shell, completion::ShellCompleter, env::ExecContext, expand::trim_command_output,
+    get_input_and_run,
+};
+use rustyline::Editor;
+
+use crate::common::{TempFile, get_print_with_handler, new_env};
+
+#[macro_use]
+mod common;
+
+#[test]
+fn star_and_at_in_double_quotes() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-star_and_at").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    env.borrow_mut().set_positional_params(
+        OsString::from("mysh"),
+        vec![OsString::from("a b"), OsString::from("c")],
+    );
+
+    execute!(path, env, rl, "echo \"$*\" >> {}"); // a b c
+    execute!(path, env, rl, "echo \"[$@]\" >> {}"); // [a b c]
+
+    env.borrow_mut()
+        .set_var(String::from("IFS"), String::from(":"));
+    execute!(path, env, rl, "echo \"$*\" >> {}"); // a b:c
+    execute!(path, env, rl, "echo \"$@\" >> {}"); // a b c
+
+    env.borrow_mut().set_var(String::from("IFS"), String::new());
+    execute!(path, env, rl, "echo \"$*\" >> {}"); // a bc
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "a b c\n[a b c]\na b:c\na b c\na bc\n");
+}
+
+#[test]
+fn unquoted_split_with_ifs() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-unquoted_split").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    env.borrow_mut()
+        .set_positional_params(OsString::from("mysh"), vec![OsString::from("x:y::z")]);
+
+    execute!(path, env, rl, "echo $1 >> {}"); // x:y::z
+    env.borrow_mut()
+        .set_var(String::from("IFS"), String::from(":"));
+    execute!(path, env, rl, "echo $1 >> {}"); // x y  z
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "x:y::z\nx y  z\n");
+
+    // Whitespace around another delimiter is part of it
+    let mut shell = Shell::new(new_env());
+    shell.run("IFS=' :'");
+    let split = |shell: &mut Shell, value: &str| {
+        shell.capture(&format!("x='{}'; printf '[%s]' $x", value)).0
+    };
+    assert_eq!(split(&mut shell, "a : b"), "[a][b]");
+    assert_eq!(split(&mut shell, "a: b"), "[a][b]");
+    assert_eq!(split(&mut shell, " :b"), "[][b]");
+    assert_eq!(split(&mut shell, "a : : b"), "[a][][b]");
+    assert_eq!(split(&mut shell, " a  b :"), "[a][b]");
+}
+
+#[test]
+fn variables() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-variables").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let home = std::env::var("HOME").unwrap();
+    env.borrow_mut()
+        .set_var(String::from("GREETING"), String::from("hello  world"));
+
+    execute!(path, env, rl, "echo \"$HOME\" >> {}"); // $HOME
+    execute!(path, env, rl, "echo '$HOME' >> {}"); // $HOME literally
+    execute!(path, env, rl, "echo \\$HOME \"\\$HOME\" >> {}"); // $HOME literally, twice
+    execute!(path, env, rl, "echo [$MYSH_NO_SUCH_VARIABLE] >> {}"); // []
+    execute!(path, env, rl, "echo $HOMEX. >> {}"); // `HOMEX` is unset
+    // Shell variables come before the environment, and are split unquoted
+    execute!(path, env, rl, "echo \"$GREETING\" $GREETING >> {}"); // hello  world hello world
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(
+        output,
+        format!("{home}\n$HOME\n$HOME $HOME\n[]\n.\nhello  world hello world\n")
+    );
+}
+
+#[test]
+fn append_assignment() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-append_assignment").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    env.borrow_mut()
+        .set_var(String::from("X"), String::from("foo"));
+
+    execute!(path, env, rl, "X+=bar printenv X >> {}"); // foobar
+    execute!(path, env, rl, "X+=\"$1 \" Y+=y printenv X Y >> {}"); // "foo ", y
+    execute!(path, env, rl, "X+=bar printenv X >> {}"); // the shell variable is unchanged
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "foobar\nfoo \ny\nfoobar\n");
+}
+
+#[test]
+fn command_output_trailing_newlines() {
+    assert_eq!(trim_command_output(b"hello\n"), b"hello");
+    assert_eq!(trim_command_output(b"a\n\nb\n\n\n"), b"a\n\nb");
+    assert_eq!(trim_command_output(b"tail \t\n"), b"tail \t");
+    assert_eq!(trim_command_output(b"\n\n\n"), b"");
+    assert_eq!(trim_command_output(b""), b"");
+}
+
+#[test]
+fn command_substitution() {
+    let mut shell = Shell::new(new_env());
+    let capture = |shell: &mut Shell, input| shell.capture(input).0;
+
+    assert_eq!(capture(&mut shell, "echo $(echo hi)"), "hi\n");
+    assert_eq!(
+        capture(&mut shell, "echo $(echo $(echo nested))"),
+        "nested\n"
+    );
+    // Split unless quoted, and trailing newlines are removed
+    assert_eq!(
+        capture(&mut shell, "printf '[%s]' $(printf 'a  b\\n\\n')"),
+        "[a][b]"
+    );
+    assert_eq!(
+        capture(&mut shell, "echo \"today is $(printf 'a  b\\n')\""),
+        "today is a  b\n"
+    );
+    assert_eq!(capture(&mut shell, "echo $(echo a b | wc -w)"), "2\n");
+    assert_eq!(capture(&mut shell, "$(echo echo) name"), "name\n");
+
+    // The command runs like a subshell
+    shell.run("x=outer");
+    let pwd = capture(&mut shell, "pwd");
+    assert_eq!(capture(&mut shell, "echo $(x=inner) $(cd /) $x"), "outer\n");
+    assert_eq!(capture(&mut shell, "pwd"), pwd);
+
+    // `$?` is the status of the substitution without a command name
+    shell.run("x=$(exit 3)");
+    assert_eq!(capture(&mut shell, "echo $?"), "3\n");
+    assert_eq!(shell.capture("echo $(false)"), (String::from("\n"), 0));
+}
+
+#[test]
+fn last_status() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-last_status").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "false > {}");
+    assert_eq!(env.borrow().last_status(), 1);
+    execute!(path, env, rl, "echo $? \"[$?]\" >> {}"); // 1 [1]
+    execute!(path, env, rl, "echo $? >> {}"); // 0, the status of `echo`
+    execute!(path, env, rl, "sh -c 'exit 3' >> {}");
+    execute!(path, env, rl, "echo '$?' $?? >> {}"); // $? 3?
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "1 [1]\n0\n$? 3?\n");
+    assert_eq!(env.borrow().last_status(), 0);
+}
+
+#[test]
+fn braced_parameters() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-braced_parameters").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    env.borrow_mut()
+        .set_var(String::from("NAME"), String::from("mysh"));
+    env.borrow_mut()
+        .set_var(String::from("EMPTY"), String::new());
+
+    execute!(path, env, rl, "echo foo${{NAME}}bar >> {}"); // foomyshbar
+    execute!(
+        path,
+        env,
+        rl,
+        "echo ${{EMPTY:-a  b}} \"${{EMPTY:-a  b}}\" >> {}"
+    ); // a b a  b
+    execute!(
+        path,
+        env,
+        rl,
+        "echo ${{NAME:-x}} [${{EMPTY:+x}}] ${{NAME:+set}} >> {}"
+    ); // mysh [] set
+    // Quotes and expansions in the default are kept up to the matching brace
+    execute!(
+        path,
+        env,
+        rl,
+        "echo ${{EMPTY:-${{NAME:-x}}' }}'\"!\"}} >> {}"
+    ); // mysh }!
+    execute!(path, env, rl, "echo ${{NEW:=new value}} >> {}"); // new value
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(
+        output,
+        "foomyshbar\na b a  b\nmysh [] set\nmysh }!\nnew value\n"
+    );
+    assert_eq!(env.borrow().get_var("NEW"), Some("new value"));
+
+    execute!(path, env, rl, "echo ${{NAME >> {}");
+    assert_eq!(env.borrow().last_status(), 2);
+    execute!(path, env, rl, "echo ${{1:=x}} >> {}");
+    assert_eq!(env.borrow().last_status(), 2);
+}
+
+#[test]
+fn assignment_without_command() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-assignment_without_command").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "X=hello > {}");
+    execute!(path, env, rl, "echo $X >> {}"); // hello
+    // Later assignments see earlier ones
+    execute!(path, env, rl, "A=1 B=$A >> {}");
+    execute!(path, env, rl, "echo $B >> {}"); // 1
+    // Only set for the command
+    execute!(path, env, rl, "Y=2 sh -c 'echo $Y' >> {}"); // 2
+    execute!(path, env, rl, "echo [$Y] >> {}"); // []
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "hello\n1\n2\n[]\n");
+    assert_eq!(env.borrow().get_var("X"), Some("hello"));
+    assert_eq!(env.borrow().get_var("Y"), None);
+}
+
+#[test]
+fn tilde() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-tilde").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let home = std::env::var("HOME").unwrap();
+
+    execute!(path, env, rl, "echo ~ ~/foo >> {}"); // $HOME $HOME/foo
+    // Quoted or in the middle of a word, it stays literal
+    execute!(path, env, rl, "echo \"~\" '~'/foo \\~ a~ ~\"root\" >> {}");
+    execute!(path, env, rl, "echo ~mysh_no_such_user/foo >> {}");
+    execute!(path, env, rl, "X=~/bar >> {}");
+    execute!(path, env, rl, "echo $X >> {}"); // $HOME/bar
+    // The home directory of a user is looked up like `sh` does
+    execute!(path, env, rl, "echo ~root/x >> {}");
+    execute!(path, env, rl, "sh -c 'echo ~root/x' >> {}");
+
+    let output = get_print_with_handler(temp_file.file());
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            format!("{home} {home}/foo"),
+            String::from("~ ~/foo ~ a~ ~root"),
+            String::from("~mysh_no_such_user/foo"),
+            format!("{home}/bar"),
+        ]
+    );
+    assert_eq!(lines[4], lines[5]);
+}
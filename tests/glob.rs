@@ -0,0 +1,171 @@
+use std::{fs, process::Command};
+
+use mysh::{Shell, completion::ShellCompleter, env::ExecContext, get_input_and_run, glob::matches};
+use rustyline::Editor;
+
+use crate::common::new_env;
+
+mod common;
+
+#[test]
+fn match_star() {
+    assert!(matches(b"*", b"anything"));
+    assert!(matches(b"*", b""));
+    assert!(matches(b"*.rs", b"main.rs"));
+    assert!(!matches(b"*.rs", b"main.rsx"));
+    assert!(matches(b"a*b*c", b"aXbYbZc"));
+    assert!(!matches(b"a*b*c", b"aXbYbZ"));
+    assert!(matches(b"**x", b"xx"));
+    // Escaped characters only match themselves
+    assert!(matches(b"\\*", b"*"));
+    assert!(!matches(b"\\*", b"x"));
+    assert!(matches(b"\\\\*", b"\\foo"));
+}
+
+#[test]
+fn match_question_mark() {
+    assert!(matches(b"file?.txt", b"file1.txt"));
+    assert!(!matches(b"file?.txt", b"file.txt"));
+    assert!(!matches(b"file?.txt", b"file12.txt"));
+    assert!(matches(b"??", b"ab"));
+    // One character, not one byte
+    assert!(matches(b"?", "é".as_bytes()));
+    assert!(matches(b"*?", b"a"));
+    assert!(!matches(b"*?", b""));
+    assert!(matches(b"\\?", b"?"));
+    assert!(!matches(b"\\?", b"a"));
+}
+
+#[test]
+fn match_brackets() {
+    assert!(matches(b"[abc]", b"b"));
+    assert!(!matches(b"[abc]", b"d"));
+    assert!(matches(b"log[0-9].txt", b"log7.txt"));
+    assert!(!matches(b"log[0-9].txt", b"logx.txt"));
+    assert!(matches(b"[!0-9]", b"x"));
+    assert!(!matches(b"[!0-9]", b"5"));
+    assert!(matches(b"[^0-9]", b"x"));
+    assert!(matches(b"[a-cx-z]", b"y"));
+    assert!(matches("[à-ü]".as_bytes(), "é".as_bytes()));
+    // `]` first is a member, and `-` last is a member
+    assert!(matches(b"[]a]", b"]"));
+    assert!(matches(b"[!]a]", b"b"));
+    assert!(matches(b"[a-]", b"-"));
+    // Escaped characters in brackets are members
+    assert!(matches(b"[\\]]", b"]"));
+    assert!(matches(b"[\\!a]", b"!"));
+    // An unterminated `[` is literal
+    assert!(matches(b"[ab", b"[ab"));
+    assert!(!matches(b"[ab", b"a"));
+    assert!(matches(b"a[", b"a["));
+    assert!(!matches(b"\\[ab]", b"a"));
+    assert!(matches(b"\\[ab]", b"[ab]"));
+}
+
+#[test]
+fn match_posix_classes() {
+    assert!(matches(b"[[:digit:]]", b"7"));
+    assert!(!matches(b"[[:digit:]]", b"x"));
+    assert!(matches(b"[[:alpha:]]*", b"abc1"));
+    assert!(matches("[[:alpha:]]".as_bytes(), "é".as_bytes()));
+    assert!(!matches(b"[[:alpha:]]", b"1"));
+    assert!(matches(b"[[:upper:][:digit:]]", b"Q"));
+    assert!(matches(b"[[:upper:][:digit:]]", b"3"));
+    assert!(!matches(b"[[:upper:][:digit:]]", b"q"));
+    assert!(matches(b"[![:space:]]", b"x"));
+    assert!(!matches(b"[![:space:]]", b" "));
+    assert!(matches(b"[[:xdigit:]_]", b"_"));
+    assert!(matches(b"[[:punct:]]", b"!"));
+    assert!(matches(b"[[:blank:]]", b"\t"));
+    assert!(!matches(b"[[:blank:]]", b"\n"));
+    assert!(matches(b"[[:cntrl:]]", b"\x1b"));
+    assert!(!matches(b"[[:graph:]]", b" "));
+    assert!(matches(b"[[:print:]]", b" "));
+    assert!(matches(b"file[[:digit:]][[:digit:]]", b"file42"));
+    // Ranges, negation and `]` or `-` as members still work with classes
+    assert!(matches(b"[]a-c[:digit:]-]", b"]"));
+    assert!(matches(b"[]a-c[:digit:]-]", b"-"));
+    assert!(matches(b"[]a-c[:digit:]-]", b"b"));
+    assert!(!matches(b"[]a-c[:digit:]-]", b"x"));
+    // An unknown class matches nothing, and `[:` without `:]` is a member
+    assert!(!matches(b"[[:foo:]]", b"f"));
+    assert!(matches(b"[[:a]", b":"));
+    assert!(matches(b"[[:a]", b"["));
+    // Escaped, the class is only a set of characters followed by `]`
+    assert!(matches(b"[\\[:digit:]]", b"d]"));
+    assert!(!matches(b"[\\[:digit:]]", b"1]"));
+}
+
+#[test]
+fn expand_in_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["b.rs", "a.rs", "c.txt", "*x", ".hidden.rs"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    fs::write(dir.path().join("sub/d.rs"), "").unwrap();
+    // Temporary directories have no special characters
+    let d = dir.path().to_str().unwrap();
+    let mut shell = Shell::new(new_env());
+    let mut echo = |args: &str| shell.capture(&format!("echo {}", args.replace("DIR", d))).0;
+
+    assert_eq!(echo("DIR/*.rs"), format!("{d}/a.rs {d}/b.rs\n"));
+    assert_eq!(echo("DIR/*/*.rs"), format!("{d}/sub/d.rs\n"));
+    assert_eq!(echo("DIR/*/"), format!("{d}/sub/\n"));
+    assert_eq!(echo("DIR/.*.rs"), format!("{d}/.hidden.rs\n"));
+    // Quoted or escaped wildcards don't expand
+    assert_eq!(
+        echo("DIR/'*'x DIR/\\*.rs \"DIR/*\""),
+        format!("{d}/*x {d}/*.rs {d}/*\n")
+    );
+    // A pattern which matches nothing is kept
+    assert_eq!(echo("DIR/*.none"), format!("{d}/*.none\n"));
+}
+
+#[test]
+fn expand_relative_to_working_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in ["b", "a", ".c"] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "-c", "echo *"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"a b\n");
+}
+
+#[test]
+fn character_classes_through_get_input_and_run() {
+    let dir = tempfile::tempdir().unwrap();
+    for name in [
+        "file1.txt",
+        "file2.txt",
+        "fileA.txt",
+        "log3.txt",
+        "?.txt",
+        "[x",
+    ] {
+        fs::write(dir.path().join(name), "").unwrap();
+    }
+    let d = dir.path().to_str().unwrap();
+    let out = dir.path().join("out");
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    let line = format!(
+        "echo {d}/file?.txt {d}/log[0-9].txt {d}/file[!0-9].txt {d}/[x {d}/'?'.txt \
+         {d}/\\[x {d}/file[3-9].txt > {}",
+        out.display()
+    );
+    let context = ExecContext::new(rl.history_mut());
+    get_input_and_run(&line, env, context);
+
+    assert_eq!(
+        fs::read_to_string(&out).unwrap(),
+        format!(
+            "{d}/file1.txt {d}/file2.txt {d}/fileA.txt {d}/log3.txt {d}/fileA.txt {d}/[x \
+             {d}/?.txt {d}/[x {d}/file[3-9].txt\n"
+        )
+    );
+}
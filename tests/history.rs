@@ -0,0 +1,236 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    process::{Command, Stdio},
+};
+
+use mysh::history::{HistoryLoader, add_entry, expand_history, read_recent_entries};
+use rustyline::{
+    Config,
+    history::{FileHistory, History},
+};
+
+#[test]
+fn concurrent_sessions_keep_all_entries() {
+    const SESSIONS: usize = 8;
+    const ENTRIES: usize = 5;
+
+    let dir = tempfile::tempdir().unwrap();
+    let histfile = dir.path().join("history");
+
+    let mut children: Vec<_> = (0..SESSIONS)
+        .map(|_| {
+            Command::new(env!("CARGO_BIN_EXE_mysh"))
+                .arg("--no-config")
+                .env("HISTFILE", &histfile)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("Failed to run mysh")
+        })
+        .collect();
+
+    let mut stdins: Vec<_> = children
+        .iter_mut()
+        .map(|c| c.stdin.take().unwrap())
+        .collect();
+    for (session, stdin) in stdins.iter_mut().enumerate() {
+        for entry in 0..ENTRIES {
+            writeln!(stdin, "echo session-{}-entry-{}", session, entry).unwrap();
+        }
+    }
+    // All sessions exit, and write the history at about the same time
+    drop(stdins);
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let history = std::fs::read_to_string(&histfile).unwrap();
+    for session in 0..SESSIONS {
+        for entry in 0..ENTRIES {
+            let line = format!("echo session-{}-entry-{}", session, entry);
+            let count = history.lines().filter(|l| *l == line).count();
+            assert_eq!(count, 1, "{:?} in history:\n{}", line, history);
+        }
+    }
+}
+
+fn add_lines(lines: &[&str], ignore_dups: bool) -> Vec<String> {
+    let config = Config::builder()
+        .history_ignore_dups(false)
+        .unwrap()
+        .build();
+    let mut history = FileHistory::with_config(&config);
+    for line in lines {
+        add_entry(&mut history, line, ignore_dups).unwrap();
+    }
+    history.iter().cloned().collect()
+}
+
+#[test]
+fn skip_blank_and_duplicate_lines() {
+    let lines = ["ls", "", "   ", "\t", "ls", "pwd", "ls", "ls ", "ls"];
+
+    assert_eq!(add_lines(&lines, true), ["ls", "pwd", "ls", "ls ", "ls"]);
+    assert_eq!(
+        add_lines(&lines, false),
+        ["ls", "ls", "pwd", "ls", "ls ", "ls"]
+    );
+}
+
+#[test]
+fn history_expansion() {
+    let lines = ["ls -l", "echo one", "pwd"];
+    let mut history = FileHistory::new();
+    for line in lines {
+        history.add(line).unwrap();
+    }
+
+    let expand = |line| expand_history(line, &history);
+    assert_eq!(expand("!!").unwrap(), "pwd");
+    assert_eq!(expand("!! | cat").unwrap(), "pwd | cat");
+    assert_eq!(expand("!1").unwrap(), "ls -l");
+    assert_eq!(expand("!-2").unwrap(), "echo one");
+    assert_eq!(expand("!ec x").unwrap(), "echo one x");
+    assert_eq!(expand("sudo !!").unwrap(), "sudo pwd");
+
+    // Not expanded
+    assert_eq!(
+        expand("echo '!!' \\!! ! !=").unwrap(),
+        "echo '!!' \\!! ! !="
+    );
+
+    assert_eq!(expand("!4"), Err(String::from("!4")));
+    assert_eq!(expand("!-4"), Err(String::from("!-4")));
+    assert_eq!(expand("!nope"), Err(String::from("!nope")));
+}
+
+/// Counts the bytes read from a file.
+struct CountingReader {
+    file: File,
+    read: u64,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.file.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for CountingReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+#[test]
+fn recent_entries_of_long_file() {
+    const ENTRIES: usize = 100_000;
+
+    let dir = tempfile::tempdir().unwrap();
+    let histfile = dir.path().join("history");
+    let mut history =
+        FileHistory::with_config(&Config::builder().max_history_size(ENTRIES).unwrap().build());
+    for i in 0..ENTRIES - 1 {
+        history.add(&format!("echo entry-{}", i)).unwrap();
+    }
+    history.add("echo 'multi\nline' \\\\").unwrap();
+    history.save(&histfile).unwrap();
+    let size = std::fs::metadata(&histfile).unwrap().len();
+
+    let mut file = CountingReader {
+        file: File::open(&histfile).unwrap(),
+        read: 0,
+    };
+    let entries = read_recent_entries(&mut file, 100).unwrap();
+    let expected: Vec<_> = history.iter().skip(ENTRIES - 100).cloned().collect();
+    assert_eq!(entries, expected);
+    assert!(
+        file.read < size / 10,
+        "{} of {} bytes read",
+        file.read,
+        size
+    );
+
+    // The whole file is loaded in the background
+    let config = Config::default();
+    let mut recent = FileHistory::with_config(&config);
+    let loader = HistoryLoader::start(&mut recent, &histfile, &config);
+    assert_eq!(recent.len(), config.max_history_size());
+    loader.finish(&mut recent).unwrap();
+    let mut loaded = FileHistory::with_config(&config);
+    loaded.load(&histfile).unwrap();
+    assert!(recent.iter().eq(loaded.iter()));
+
+    // Only the new entries are appended
+    recent.add("echo new").unwrap();
+    recent.append(&histfile).unwrap();
+    let content = std::fs::read_to_string(&histfile).unwrap();
+    assert!(
+        content.ends_with("\\\\\necho new\n"),
+        "{:?}",
+        &content[content.len() - 40..]
+    );
+}
+
+#[test]
+fn recent_entries_of_short_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let histfile = dir.path().join("history");
+
+    // Files without the header are not escaped
+    std::fs::write(&histfile, "ls\n\npwd\necho a\\nb\n").unwrap();
+    let mut file = File::open(&histfile).unwrap();
+    assert_eq!(
+        read_recent_entries(&mut file, 10).unwrap(),
+        ["ls", "pwd", "echo a\\nb"]
+    );
+    assert_eq!(
+        read_recent_entries(&mut file, 2).unwrap(),
+        ["pwd", "echo a\\nb"]
+    );
+
+    std::fs::write(&histfile, "#V2\nls\necho a\\nb\n").unwrap();
+    let mut file = File::open(&histfile).unwrap();
+    assert_eq!(
+        read_recent_entries(&mut file, 10).unwrap(),
+        ["ls", "echo a\nb"]
+    );
+
+    std::fs::write(&histfile, "").unwrap();
+    let mut file = File::open(&histfile).unwrap();
+    assert!(read_recent_entries(&mut file, 10).unwrap().is_empty());
+}
+
+#[test]
+fn history_option_pauses_recording() {
+    let dir = tempfile::tempdir().unwrap();
+    let histfile = dir.path().join("history");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", &histfile)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Failed to run mysh");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"echo before\nset +o history\necho secret\nset -o history\necho after\n")
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+
+    // The line which turns it off is still added, like bash
+    let history = std::fs::read_to_string(&histfile).unwrap();
+    assert_eq!(
+        history
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .collect::<Vec<_>>(),
+        ["echo before", "set +o history", "echo after"]
+    );
+}
@@ -0,0 +1,140 @@
+use mysh::job::{JobState, JobTable};
+
+/// Wait statuses as encoded by `waitpid` on Linux.
+fn exited(code: i32) -> i32 {
+    code << 8
+}
+
+fn stopped(signal: i32) -> i32 {
+    (signal << 8) | 0x7f
+}
+
+const CONTINUED: i32 = 0xffff;
+
+#[test]
+fn finished_jobs_are_reported_once() {
+    let jobs = JobTable::new();
+    let id = jobs.add(
+        100,
+        String::from("make | less"),
+        vec![100, 101],
+        JobState::Running,
+    );
+    assert!(jobs.update(100, exited(0)));
+    assert_eq!(jobs.get(id).unwrap().state, JobState::Running);
+    assert!(jobs.take_finished().is_empty());
+
+    assert!(jobs.update(101, exited(3)));
+    assert_eq!(jobs.get(id).unwrap().state, JobState::Done(exited(3)));
+    assert_eq!(
+        jobs.take_finished(),
+        ["[1]+  Exit 3                  make | less"]
+    );
+    assert!(jobs.is_empty());
+    assert!(jobs.take_finished().is_empty());
+}
+
+#[test]
+fn status_of_last_process() {
+    let jobs = JobTable::new();
+    // The last process exits first, the status of the job is still its status.
+    jobs.add(
+        200,
+        String::from("false | true"),
+        vec![200, 201],
+        JobState::Running,
+    );
+    jobs.update(201, exited(0));
+    jobs.update(200, exited(1));
+    assert_eq!(
+        jobs.take_finished(),
+        ["[1]+  Done                    false | true"]
+    );
+
+    jobs.add(300, String::from("sleep 100"), vec![300], JobState::Running);
+    jobs.update(300, libc::SIGKILL);
+    assert_eq!(
+        jobs.take_finished(),
+        ["[1]+  Killed                  sleep 100"]
+    );
+}
+
+#[test]
+fn stopped_and_continued_jobs() {
+    let jobs = JobTable::new();
+    let id = jobs.add(100, String::from("vim"), vec![100], JobState::Running);
+    jobs.update(100, stopped(libc::SIGTSTP));
+    assert_eq!(jobs.get(id).unwrap().state, JobState::Stopped);
+    assert_eq!(jobs.exit_warning(), Some("There are stopped jobs."));
+    assert!(jobs.take_finished().is_empty());
+
+    jobs.update(100, CONTINUED);
+    assert_eq!(jobs.get(id).unwrap().state, JobState::Running);
+    assert_eq!(jobs.exit_warning(), Some("There are running jobs."));
+
+    jobs.update(100, exited(0));
+    assert_eq!(jobs.take_finished().len(), 1);
+    assert_eq!(jobs.exit_warning(), None);
+}
+
+#[test]
+fn unknown_processes_are_ignored() {
+    let jobs = JobTable::new();
+    jobs.add(100, String::from("sleep 1"), vec![100], JobState::Running);
+    assert!(!jobs.update(999, exited(0)));
+    assert_eq!(jobs.len(), 1);
+}
+
+#[test]
+fn current_and_previous_markers() {
+    let jobs = JobTable::new();
+    let first = jobs.add(100, String::from("a"), vec![100], JobState::Stopped);
+    let second = jobs.add(200, String::from("b"), vec![200], JobState::Stopped);
+    let third = jobs.add(300, String::from("c"), vec![300], JobState::Running);
+    assert_eq!((first, second, third), (1, 2, 3));
+    assert!(
+        jobs.status_line(first)
+            .unwrap()
+            .starts_with("[1]   Stopped")
+    );
+    assert!(
+        jobs.status_line(second)
+            .unwrap()
+            .starts_with("[2]-  Stopped")
+    );
+    assert!(
+        jobs.status_line(third)
+            .unwrap()
+            .starts_with("[3]+  Running")
+    );
+
+    // Ids are reused once the largest one is gone.
+    jobs.remove(third);
+    assert_eq!(
+        jobs.add(400, String::from("d"), vec![400], JobState::Running),
+        3
+    );
+}
+
+#[test]
+fn job_specs() {
+    let jobs = JobTable::new();
+    assert_eq!(jobs.find("%%"), None);
+    let first = jobs.add(100, String::from("sleep 1"), vec![100], JobState::Running);
+    let second = jobs.add(200, String::from("sleep 2"), vec![200], JobState::Stopped);
+    assert_eq!(jobs.find("%1"), Some(first));
+    assert_eq!(jobs.find("%2"), Some(second));
+    assert_eq!(jobs.find("%3"), None);
+    for current in ["%", "%%", "%+"] {
+        assert_eq!(jobs.find(current), Some(second));
+    }
+    assert_eq!(jobs.find("%-"), Some(first));
+    assert_eq!(jobs.find("1"), None);
+    assert_eq!(jobs.find("%x"), None);
+
+    // A job put back keeps its id, and becomes the current job
+    let job = jobs.remove(first).unwrap();
+    assert_eq!(jobs.find("%-"), None);
+    jobs.restore(job);
+    assert_eq!(jobs.find("%+"), Some(first));
+}
@@ -0,0 +1,53 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs, io,
+    os::unix::ffi::OsStrExt,
+};
+
+use mysh::{completion::ShellCompleter, env::ExecContext, get_input_and_run};
+use rustyline::Editor;
+
+use crate::common::new_env;
+
+#[macro_use]
+mod common;
+
+#[test]
+fn redirect_to_non_utf8_filename() {
+    let _lock = io::stdout().lock();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(OsStr::from_bytes(b"out\xff.txt"));
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    env.borrow_mut()
+        .set_positional_params(OsString::from("mysh"), vec![path.clone().into()]);
+
+    let context = ExecContext::new(rl.history_mut());
+    get_input_and_run("echo hello > \"$1\"", env.clone(), context);
+    let context = ExecContext::new(rl.history_mut());
+    get_input_and_run("echo world >> $1", env.clone(), context);
+
+    assert_eq!(fs::read(&path).unwrap(), b"hello\nworld\n");
+}
+
+#[test]
+fn non_utf8_arguments() {
+    let _lock = io::stdout().lock();
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join(OsStr::from_bytes(b"in\xfe"));
+    let output = dir.path().join("out");
+    fs::write(&input, b"from file\n").unwrap();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    env.borrow_mut().set_positional_params(
+        OsString::from("mysh"),
+        vec![input.into(), OsString::from(OsStr::from_bytes(b"a\xffb"))],
+    );
+
+    // builtin
+    execute!(output, env, rl, "echo $2 >> {}");
+    // external command
+    execute!(output, env, rl, "cat $1 >> {}");
+
+    assert_eq!(fs::read(&output).unwrap(), b"a\xffb\nfrom file\n");
+}
@@ -0,0 +1,334 @@
+use mysh::parse::{self, Connector, ParsedCommand, ParsedRedirect, Word, WordPart};
+
+fn text(value: &str, quoted: bool) -> WordPart {
+    WordPart::Text {
+        value: value.to_owned(),
+        quoted,
+    }
+}
+
+fn texts(words: &[Word]) -> Vec<String> {
+    words.iter().map(Word::to_text).collect()
+}
+
+#[test]
+fn parse_pipeline() {
+    let commands =
+        parse::parse("FOO=bar grep -n 'a b' < in.txt 2>> err.txt | sort > \"$1\"").unwrap();
+    assert_eq!(commands.len(), 2);
+
+    let ParsedCommand {
+        connector,
+        negated,
+        assignments,
+        name,
+        args,
+        redirects,
+        background,
+    } = &commands[0];
+    assert_eq!(*connector, None);
+    assert!(!background);
+    assert!(!negated);
+    assert_eq!(assignments.len(), 1);
+    assert_eq!(assignments[0].name, "FOO");
+    assert_eq!(assignments[0].value.to_text(), "bar");
+    assert_eq!(name.as_ref().unwrap().to_text(), "grep");
+    assert_eq!(texts(args), ["-n", "a b"]);
+    assert_eq!(args[1].parts, [text("a b", true)]);
+    assert_eq!(
+        redirects
+            .iter()
+            .map(|r| (r.fd, r.is_input, r.append, r.target.to_text()))
+            .collect::<Vec<_>>(),
+        [
+            (0, true, false, String::from("in.txt")),
+            (2, false, true, String::from("err.txt")),
+        ]
+    );
+
+    let sort = &commands[1];
+    assert_eq!(sort.connector, Some(Connector::Pipe));
+    assert!(sort.assignments.is_empty());
+    assert_eq!(sort.name.as_ref().unwrap().to_text(), "sort");
+    assert!(sort.args.is_empty());
+    let ParsedRedirect {
+        fd,
+        is_input,
+        append,
+        dup,
+        both,
+        here_string,
+        heredoc,
+        target,
+    } = &sort.redirects[0];
+    assert_eq!((*fd, *is_input, *append), (1, false, false));
+    assert!(!dup && !both && !here_string && !heredoc);
+    assert_eq!(target.to_text(), "$1");
+    assert!(target.parts.contains(&WordPart::Param {
+        name: String::from("1"),
+        quoted: true,
+    }));
+}
+
+#[test]
+fn parse_empty() {
+    assert!(parse::parse("").unwrap().is_empty());
+    assert!(parse::parse("   ").unwrap().is_empty());
+}
+
+#[test]
+fn parse_redirect_only() {
+    let commands = parse::parse("> out.txt").unwrap();
+    assert_eq!(commands.len(), 1);
+    assert_eq!(commands[0].name, None);
+    assert!(commands[0].args.is_empty());
+    assert_eq!(commands[0].redirects[0].target.to_text(), "out.txt");
+}
+
+#[test]
+fn parse_duplications() {
+    let commands = parse::parse("cmd >out 2>&1 <&3 &>all &>>more a\\&b").unwrap();
+    assert_eq!(texts(&commands[0].args), ["a&b"]);
+    assert_eq!(
+        commands[0]
+            .redirects
+            .iter()
+            .map(|r| (
+                r.fd,
+                r.is_input,
+                r.append,
+                r.dup,
+                r.both,
+                r.target.to_text()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            (1, false, false, false, false, String::from("out")),
+            (2, false, false, true, false, String::from("1")),
+            (0, true, false, true, false, String::from("3")),
+            (1, false, false, false, true, String::from("all")),
+            (1, false, true, false, true, String::from("more")),
+        ]
+    );
+}
+
+#[test]
+fn parse_command_substitution() {
+    let command = |s: &str, quoted| WordPart::Command {
+        command: s.to_owned(),
+        quoted,
+    };
+    let commands =
+        parse::parse("echo $(echo $(echo hi)) \"x $(echo \")\" | wc)\" $(a (b) 'c)')").unwrap();
+    assert_eq!(commands.len(), 1);
+    let args = &commands[0].args;
+    assert_eq!(args[0].parts, [command("echo $(echo hi)", false)]);
+    assert_eq!(
+        args[1].parts,
+        [text("x ", true), command("echo \")\" | wc", true)]
+    );
+    assert_eq!(args[2].parts, [command("a (b) 'c)'", false)]);
+    assert_eq!(texts(args)[2], "$(a (b) 'c)')");
+
+    assert_eq!(
+        parse::parse("echo $(echo (a)").unwrap_err(),
+        "unexpected EOF while looking for matching `)'"
+    );
+}
+
+#[test]
+fn parse_process_substitution() {
+    let commands = parse::parse("diff <(sort a) x>(tee '>(b)') < <(ls) '<(c)'").unwrap();
+    assert_eq!(commands.len(), 1);
+    let command = &commands[0];
+    let args = &command.args;
+    assert_eq!(
+        args[0].parts,
+        [WordPart::Process {
+            command: String::from("sort a"),
+            output: false,
+        }]
+    );
+    assert_eq!(texts(args), ["<(sort a)", "x>(tee '>(b)')", "<(c)"]);
+    assert_eq!(args[2].parts, [text("<(c)", true)]);
+    // A redirection from a process substitution
+    assert_eq!(command.redirects[0].target.to_text(), "<(ls)");
+}
+
+#[test]
+fn comments() {
+    let words = |input| {
+        parse::parse(input)
+            .unwrap()
+            .iter()
+            .map(|command| {
+                let mut words = vec![command.name.as_ref().unwrap().to_text()];
+                words.extend(texts(&command.args));
+                words
+            })
+            .collect::<Vec<_>>()
+    };
+    // At the start of the line, and after whitespace
+    assert!(parse::parse("# echo hello").unwrap().is_empty());
+    assert!(parse::parse("   #echo hello | cat").unwrap().is_empty());
+    assert_eq!(words("echo hello # a | b"), [["echo", "hello"]]);
+    assert_eq!(words("echo a|#b\ncat"), [vec!["echo", "a"], vec!["cat"]]);
+    // In a word, or quoted
+    assert_eq!(
+        words("echo foo#bar a# $1#"),
+        [["echo", "foo#bar", "a#", "$1#"]]
+    );
+    assert_eq!(
+        words("echo \"#tag\" '#x' \\#y ''#z"),
+        [["echo", "#tag", "#x", "#y", "#z"]]
+    );
+
+    // A backslash in a comment doesn't continue the line
+    assert!(!parse::is_incomplete("echo a # b\\"));
+    assert!(parse::is_incomplete("echo a#b\\"));
+    assert!(parse::is_incomplete("echo a # b\necho c\\"));
+}
+
+#[test]
+fn trailing_backslash() {
+    assert!(parse::is_incomplete(r"echo foo\"));
+    assert!(parse::is_incomplete(r#"echo "foo\"#));
+    assert!(!parse::is_incomplete(r"echo foo\\"));
+    assert!(!parse::is_incomplete(r"echo 'foo\'"));
+    assert!(!parse::is_incomplete("echo foo\\\nbar"));
+
+    // Open quotes continue the line, but not a quote in the body of a here-document
+    assert!(parse::is_incomplete("echo 'a"));
+    assert!(parse::is_incomplete("echo \"a\nb"));
+    assert!(!parse::is_incomplete("echo \"a\nb\""));
+    assert!(!parse::is_incomplete("cat <<EOF\nit's\nEOF"));
+
+    // Without more input, the backslash is kept
+    let commands = parse::parse(r"echo foo\").unwrap();
+    assert_eq!(texts(&commands[0].args), [r"foo\"]);
+    let commands = parse::parse(r#"echo "foo\"#).unwrap();
+    assert_eq!(texts(&commands[0].args), [r"foo\"]);
+
+    // An escaped newline is removed
+    let commands = parse::parse("echo foo\\\nbar \"a\\\nb\" 'c\\\nd'").unwrap();
+    assert_eq!(texts(&commands[0].args), ["foobar", "ab", "c\\\nd"]);
+}
+
+#[test]
+fn pipeline_stages() {
+    let commands = parse::parse("echo 'a|b' \"c|d\" e\\|f | cat|wc -l").unwrap();
+    assert_eq!(commands.len(), 3);
+    assert_eq!(texts(&commands[0].args), ["a|b", "c|d", "e|f"]);
+    assert_eq!(commands[1].name.as_ref().unwrap().to_text(), "cat");
+    assert_eq!(texts(&commands[2].args), ["-l"]);
+
+    let error = "syntax error near unexpected token `|'";
+    assert_eq!(parse::parse("| cat").unwrap_err(), error);
+    assert_eq!(parse::parse("echo a | | cat").unwrap_err(), error);
+    assert_eq!(
+        parse::parse("echo a |").unwrap_err(),
+        "syntax error: unexpected end of file"
+    );
+}
+
+#[test]
+fn and_or_lists() {
+    let commands = parse::parse("true&&echo 'a&&b' || ls | wc -l").unwrap();
+    let connectors: Vec<_> = commands.iter().map(|c| c.connector).collect();
+    assert_eq!(
+        connectors,
+        [
+            None,
+            Some(Connector::And),
+            Some(Connector::Or),
+            Some(Connector::Pipe)
+        ]
+    );
+    assert_eq!(texts(&commands[1].args), ["a&&b"]);
+    assert_eq!(commands[2].name.as_ref().unwrap().to_text(), "ls");
+
+    assert_eq!(
+        parse::parse("&& true").unwrap_err(),
+        "syntax error near unexpected token `&&'"
+    );
+    assert_eq!(
+        parse::parse("true || && false").unwrap_err(),
+        "syntax error near unexpected token `&&'"
+    );
+    assert_eq!(
+        parse::parse("true ||").unwrap_err(),
+        "syntax error: unexpected end of file"
+    );
+}
+
+#[test]
+fn semicolon_lists() {
+    let commands = parse::parse("echo 'a;b' \"c;d\" e\\;f;cat;;  ; ls;").unwrap();
+    let connectors: Vec<_> = commands.iter().map(|c| c.connector).collect();
+    assert_eq!(
+        connectors,
+        [None, Some(Connector::Semicolon), Some(Connector::Semicolon)]
+    );
+    assert_eq!(texts(&commands[0].args), ["a;b", "c;d", "e;f"]);
+    assert!(parse::parse(";").unwrap().is_empty());
+
+    assert_eq!(
+        parse::parse("echo a | ; cat").unwrap_err(),
+        "syntax error near unexpected token `;'"
+    );
+    assert_eq!(
+        parse::parse("echo a ; && cat").unwrap_err(),
+        "syntax error near unexpected token `&&'"
+    );
+    assert_eq!(
+        parse::parse("[[ a ; ]]").unwrap_err(),
+        "syntax error in conditional expression: unexpected token `;'"
+    );
+    assert_eq!(parse::parse("[[ a ]]; ls").unwrap().len(), 2);
+}
+
+#[test]
+fn background_lists() {
+    let commands = parse::parse("sleep 1 | cat & echo a&&echo b &").unwrap();
+    let background: Vec<_> = commands.iter().map(|c| c.background).collect();
+    assert_eq!(background, [false, true, false, true]);
+    let connectors: Vec<_> = commands.iter().map(|c| c.connector).collect();
+    assert_eq!(
+        connectors,
+        [
+            None,
+            Some(Connector::Pipe),
+            Some(Connector::Semicolon),
+            Some(Connector::And)
+        ]
+    );
+    assert_eq!(texts(&commands[2].args), ["a"]);
+
+    for line in ["& ls", "ls & &", "ls &; cat", "ls && &"] {
+        assert!(parse::parse(line).is_err(), "{}", line);
+    }
+    assert_eq!(
+        parse::parse("ls &; cat").unwrap_err(),
+        "syntax error near unexpected token `;'"
+    );
+}
+
+#[test]
+fn negated_pipelines() {
+    let commands = parse::parse("! grep a | ! b && echo ! '!' ; ! ! ls").unwrap();
+    let negated: Vec<_> = commands.iter().map(|c| c.negated).collect();
+    assert_eq!(negated, [true, false, false, false]);
+    assert_eq!(commands[0].name.as_ref().unwrap().to_text(), "grep");
+    // Only at the start of a pipeline
+    assert_eq!(commands[1].name.as_ref().unwrap().to_text(), "!");
+    assert_eq!(texts(&commands[2].args), ["!", "!"]);
+    assert_eq!(commands[3].name.as_ref().unwrap().to_text(), "ls");
+
+    let commands = parse::parse("!").unwrap();
+    assert!(commands[0].negated);
+    assert_eq!(commands[0].name, None);
+    // `[[` after `!` is still a conditional expression
+    let commands = parse::parse("! [[ a && b ]]").unwrap();
+    assert!(commands[0].negated);
+    assert_eq!(texts(&commands[0].args), ["a", "&&", "b", "]]"]);
+}
@@ -0,0 +1,296 @@
+use std::{
+    io,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use mysh::{
+    Shell,
+    builtin::{BROKEN_PIPE_STATUS, echo_command},
+    completion::ShellCompleter,
+    env::ExecContext,
+    get_input_and_run,
+};
+use rustyline::{
+    Editor,
+    history::{FileHistory, History},
+};
+
+use crate::common::{TempFile, get_print_with_handler, new_env};
+
+#[macro_use]
+mod common;
+
+#[test]
+fn builtin_to_external() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-builtin_to_external").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "echo hello | cat > {}");
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "hello\n");
+}
+
+#[test]
+fn three_stages() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-three_stages").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "printf 'b\\na\\nb\\n' | sort | uniq > {}");
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "a\nb\n");
+}
+
+#[test]
+fn history_to_external() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-history_to_external").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+    rl.history_mut().add("echo first").unwrap();
+    rl.history_mut().add("echo second").unwrap();
+
+    execute!(path, env, rl, "history | cat > {}");
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "    1  echo first\n    2  echo second\n");
+}
+
+#[test]
+fn external_to_builtin() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-external_to_builtin").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    // `echo` doesn't read stdin, the pipeline should still finish
+    execute!(path, env, rl, "printf ignored | echo builtin > {}");
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "builtin\n");
+}
+
+#[test]
+fn builtin_in_the_middle() {
+    let _lock = io::stdout().lock();
+    let mut temp_file = TempFile::build("mysh-tests-builtin_in_the_middle").unwrap();
+    let path = temp_file.path().to_path_buf();
+    let env = new_env();
+    let mut rl: Editor<ShellCompleter, _> = Editor::new().unwrap();
+
+    execute!(path, env, rl, "printf ignored | echo middle | cat > {}");
+
+    let output = get_print_with_handler(temp_file.file());
+    assert_eq!(output, "middle\n");
+}
+
+#[test]
+fn builtin_to_closed_pipe() {
+    let env = new_env();
+    let mut history = FileHistory::new();
+    let mut context = ExecContext::new(&mut history);
+
+    // The reader has gone before the builtin writes anything
+    let (reader, writer) = io::pipe().unwrap();
+    drop(reader);
+    env.borrow_mut().pipe_out = Some(writer);
+
+    let status = echo_command(vec!["hello".into()], env.borrow_mut(), &mut context);
+    assert_eq!(status, BROKEN_PIPE_STATUS);
+}
+
+#[test]
+fn shell_output_to_closed_pipe() {
+    let (reader, writer) = io::pipe().unwrap();
+    drop(reader);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "-c", "echo hello"])
+        .stdout(writer)
+        .stderr(Stdio::piped())
+        .output()
+        .unwrap();
+
+    // No panic message, nor any diagnostic for the broken pipe
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn pipeline_ends_when_reader_exits() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .args(["--no-config", "-c", "yes | head -1"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // `yes` should be killed by SIGPIPE once `head` exits, and the shell waits for both
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            child.wait().unwrap();
+            panic!("`yes | head -1` didn't finish");
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.stdout, b"y\n");
+}
+
+#[test]
+fn and_or_lists() {
+    let mut shell = Shell::new(new_env());
+
+    assert_eq!(shell.capture("false && echo no"), (String::new(), 1));
+    assert_eq!(
+        shell.capture("false || echo yes"),
+        (String::from("yes\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("true && echo yes"),
+        (String::from("yes\n"), 0)
+    );
+    assert_eq!(shell.capture("true || echo no"), (String::new(), 0));
+
+    // Left-associative, a skipped command keeps the status
+    assert_eq!(
+        shell.capture("false && echo no || echo yes"),
+        (String::from("yes\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("true || echo no && echo yes"),
+        (String::from("yes\n"), 0)
+    );
+    assert_eq!(
+        shell.capture("true && false || exit 3 && echo no"),
+        (String::new(), 3)
+    );
+
+    // The status of a pipeline is its last command
+    assert_eq!(
+        shell.capture("false | true && echo a | cat && false | cat || echo no"),
+        (String::from("a\n"), 0)
+    );
+    // The left side is waited for before the right side runs
+    assert_eq!(
+        shell.capture("sh -c 'sleep 0.1; echo a' && echo b"),
+        (String::from("a\nb\n"), 0)
+    );
+    // Quoted operators are arguments
+    assert_eq!(
+        shell.capture("echo '&&' \"||\" \\&\\&"),
+        (String::from("&& || &&\n"), 0)
+    );
+}
+
+#[test]
+fn semicolons() {
+    let mut shell = Shell::new(new_env());
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("out");
+
+    shell.run(&format!("pwd > {0}; pwd >> {0}", file.display()));
+    let pwd = std::env::current_dir().unwrap();
+    let output = std::fs::read_to_string(&file).unwrap();
+    assert_eq!(output, format!("{0}\n{0}\n", pwd.display()));
+
+    // The status is the one of the last command, and empty commands are skipped
+    assert_eq!(
+        shell.capture("echo a;;echo b ; false"),
+        (String::from("a\nb\n"), 1)
+    );
+    assert_eq!(shell.capture("; false; true;"), (String::new(), 0));
+    assert_eq!(
+        shell.capture("false && echo no; echo yes || echo no"),
+        (String::from("yes\n"), 0)
+    );
+    assert_eq!(shell.capture("exit 4; echo no"), (String::new(), 4));
+}
+
+#[test]
+fn background_pipelines() {
+    let mut shell = Shell::new(new_env());
+    let start = Instant::now();
+    assert_eq!(
+        shell.capture("sleep 5 > /dev/null & echo done"),
+        (String::from("done\n"), 0)
+    );
+    assert!(start.elapsed() < Duration::from_secs(3));
+    // Starting a job succeeds, whatever its status is
+    assert_eq!(shell.capture("false &"), (String::new(), 0));
+    assert_eq!(shell.env().borrow().jobs.len(), 2);
+
+    // Quoted `&`, `&&` and `&>` don't start jobs
+    assert_eq!(
+        shell.capture("echo 'a & b' \"&\" \\& && echo c &> /dev/null"),
+        (String::from("a & b & &\n"), 0)
+    );
+    assert_eq!(shell.env().borrow().jobs.len(), 2);
+}
+
+#[test]
+fn jobs_builtin() {
+    let mut shell = Shell::new(new_env());
+    assert_eq!(shell.capture("jobs"), (String::new(), 0));
+
+    shell.run("sleep 0.1 > /dev/null &");
+    shell.run("sleep 3 > /dev/null &");
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(
+        shell.capture("jobs"),
+        (
+            String::from(
+                "[1]-  Done                    sleep 0.1 > /dev/null &\n\
+                 [2]+  Running                 sleep 3 > /dev/null &\n"
+            ),
+            0
+        )
+    );
+    // The job which is done is removed once listed
+    let (output, status) = shell.capture("jobs %1 %2");
+    assert_eq!(
+        output,
+        "[2]+  Running                 sleep 3 > /dev/null &\n"
+    );
+    assert_eq!(status, 1);
+}
+
+#[test]
+fn negated_pipelines() {
+    let mut shell = Shell::new(new_env());
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    std::fs::write(&file, "bar\n").unwrap();
+
+    assert_eq!(
+        shell.capture(&format!("! grep foo {} && echo absent", file.display())),
+        (String::from("absent\n"), 0)
+    );
+    assert_eq!(
+        shell.capture(&format!("! grep -q bar {} || echo present", file.display())),
+        (String::from("present\n"), 0)
+    );
+    // The status of the whole pipeline is negated
+    assert_eq!(shell.capture("! echo a | grep -q b"), (String::new(), 0));
+    assert_eq!(shell.capture("! false | true"), (String::new(), 1));
+    // Builtins, and `$?` sees the negated status
+    assert_eq!(
+        shell.capture("! test -n x; echo $?"),
+        (String::from("1\n"), 0)
+    );
+    assert_eq!(shell.capture("! [[ a == b ]]"), (String::new(), 0));
+    assert_eq!(shell.capture("echo ! x"), (String::from("! x\n"), 0));
+    assert_eq!(shell.capture("!"), (String::new(), 1));
+}
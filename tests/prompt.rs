@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+use mysh::prompt::{DEFAULT_PS1, render_prompt};
+
+use crate::common::new_env;
+
+mod common;
+
+#[test]
+fn default_prompt() {
+    let env = new_env();
+    assert_eq!(render_prompt(&env.borrow()), DEFAULT_PS1);
+}
+
+#[test]
+fn prompt_escapes() {
+    let env = new_env();
+    env.borrow_mut().pwd = PathBuf::from("/mysh-tests/dir");
+    env.borrow_mut()
+        .set_var(String::from("PS1"), String::from(r"[\j] \w \\ \x \$ "));
+
+    let root = unsafe { libc::geteuid() } == 0;
+    let expected = format!(
+        r"[0] /mysh-tests/dir \ \x {} ",
+        if root { '#' } else { '$' }
+    );
+    assert_eq!(render_prompt(&env.borrow()), expected);
+}
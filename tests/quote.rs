@@ -0,0 +1,70 @@
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+use mysh::{
+    parse::{ParseFragment, parse_to_fragments},
+    quote, quote_os,
+};
+
+fn reparse(s: &str) -> Vec<String> {
+    parse_to_fragments(s)
+        .unwrap()
+        .into_iter()
+        .map(|frag| match frag {
+            ParseFragment::Argument(word) => word.to_text(),
+            other => panic!("unexpected fragment {:?} when parsing {:?}", other, s),
+        })
+        .collect()
+}
+
+#[test]
+fn quote_bare() {
+    assert_eq!(quote("hello"), "hello");
+    assert_eq!(quote("/usr/bin/env"), "/usr/bin/env");
+    assert_eq!(quote("a-b_c.d:e,f@g+h"), "a-b_c.d:e,f@g+h");
+}
+
+#[test]
+fn quote_special() {
+    assert_eq!(quote(""), "''");
+    assert_eq!(quote("hello world"), "'hello world'");
+    assert_eq!(quote("it's"), r"'it'\''s'");
+    assert_eq!(quote("a>b"), "'a>b'");
+    assert_eq!(quote("$HOME"), "'$HOME'");
+    assert_eq!(quote("~"), "'~'");
+}
+
+#[test]
+fn quote_os_non_utf8() {
+    let s = OsStr::from_bytes(b"a\xffb c");
+    assert_eq!(quote_os(s).as_bytes(), b"'a\xffb c'");
+    assert_eq!(quote_os(OsStr::new("plain")), OsStr::new("plain"));
+}
+
+#[test]
+fn quote_round_trip() {
+    const ALPHABET: &[char] = &[
+        'a', 'Z', '0', ' ', '\t', '\n', '\'', '"', '\\', '$', '`', '>', '<', '|', '&', ';', '*',
+        '?', '[', ']', '{', '}', '~', '#', '=', '%', '(', ')', '!', '-', '_', '/', '.', 'é', '中',
+    ];
+
+    let mut rng = fastrand::Rng::with_seed(0x6d7973);
+    for _ in 0..2000 {
+        let len = rng.usize(0..16);
+        let s: String = (0..len)
+            .map(|_| ALPHABET[rng.usize(0..ALPHABET.len())])
+            .collect();
+        assert_eq!(
+            reparse(&quote(&s)),
+            vec![s.clone()],
+            "quoted: {}",
+            quote(&s)
+        );
+    }
+}
+
+#[test]
+fn quote_round_trip_multiple_words() {
+    let words = ["echo", "", "it's", "a b", "\\", "\"\""];
+    let line = words.iter().map(|w| quote(w)).collect::<Vec<_>>().join(" ");
+    assert_eq!(reparse(&line), words);
+}
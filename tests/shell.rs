@@ -0,0 +1,78 @@
+use std::{cell::RefCell, rc::Rc};
+
+use mysh::Shell;
+
+use crate::common::new_env;
+
+mod common;
+
+#[test]
+fn capture_output_and_status() {
+    let mut shell = Shell::new(new_env());
+
+    assert_eq!(shell.capture("echo hi"), (String::from("hi\n"), 0));
+    assert_eq!(
+        shell.capture("printf 'a\\nb\\n' | sort -r"),
+        (String::from("b\na\n"), 0)
+    );
+    // Only stdout is captured
+    assert_eq!(
+        shell.capture("sh -c 'echo out; echo err >&2; exit 3'"),
+        (String::from("out\n"), 3)
+    );
+    assert_eq!(shell.capture("exit 4"), (String::new(), 4));
+    assert_eq!(shell.capture("echo |"), (String::new(), 2));
+    assert_eq!(shell.env().borrow().last_status(), 2);
+}
+
+#[test]
+fn capture_large_output() {
+    let mut shell = Shell::new(new_env());
+
+    // More than a pipe can hold
+    let (output, status) = shell.capture("seq 100000");
+    assert_eq!(status, 0);
+    assert_eq!(output.lines().count(), 100000);
+    assert!(output.ends_with("\n100000\n"));
+}
+
+#[test]
+fn capture_keeps_shell_state() {
+    let mut shell = Shell::new(new_env());
+
+    assert_eq!(shell.capture("echo ${X:=1}"), (String::from("1\n"), 0));
+    assert_eq!(shell.env().borrow().get_var("X"), Some("1"));
+}
+
+#[test]
+fn hooks_around_command_lines() {
+    let mut shell = Shell::new(new_env());
+    let calls = Rc::new(RefCell::new(Vec::new()));
+
+    let pre = Rc::clone(&calls);
+    let post = Rc::clone(&calls);
+    let mut env = shell.env().borrow_mut();
+    env.hooks.pre_exec.push(Box::new(move |line| {
+        pre.borrow_mut().push(format!("pre {}", line))
+    }));
+    env.hooks.post_exec.push(Box::new(move |line, status| {
+        post.borrow_mut().push(format!("post {} {}", line, status))
+    }));
+    drop(env);
+
+    shell.run("true");
+    // Not for command substitutions
+    assert_eq!(shell.capture("echo $(exit 2)"), (String::from("\n"), 0));
+    shell.run("exit 3");
+    assert_eq!(
+        *calls.borrow(),
+        [
+            "pre true",
+            "post true 0",
+            "pre echo $(exit 2)",
+            "post echo $(exit 2) 0",
+            "pre exit 3",
+            "post exit 3 3",
+        ]
+    );
+}
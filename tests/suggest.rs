@@ -0,0 +1,35 @@
+use mysh::suggest::{closest, edit_distance};
+
+#[test]
+fn edit_distances() {
+    assert_eq!(edit_distance("git", "git"), 0);
+    assert_eq!(edit_distance("gti", "git"), 1);
+    assert_eq!(edit_distance("gt", "git"), 1);
+    assert_eq!(edit_distance("gitt", "git"), 1);
+    assert_eq!(edit_distance("got", "git"), 1);
+    assert_eq!(edit_distance("sl", "ls"), 1);
+    assert_eq!(edit_distance("", "ls"), 2);
+    assert_eq!(edit_distance("carg", "cargo"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+}
+
+#[test]
+fn closest_candidate() {
+    let candidates = ["echo", "exit", "git", "grep"];
+    assert_eq!(closest("gti", candidates), Some("git"));
+    assert_eq!(closest("ecoh", candidates), Some("echo"));
+    // The first of candidates at the same distance
+    assert_eq!(closest("exho", candidates), Some("echo"));
+    assert_eq!(closest("gerp", candidates), Some("grep"));
+    assert_eq!(closest("cargo", candidates), None);
+    // The name itself is not suggested
+    assert_eq!(closest("git", ["git"]), None);
+
+    // Short names are only close if some characters are kept
+    let candidates = [".", "[", "ls", "git"];
+    assert_eq!(closest("x", candidates), None);
+    assert_eq!(closest("l", candidates), None);
+    assert_eq!(closest("sl", candidates), Some("ls"));
+    assert_eq!(closest("gt", candidates), Some("git"));
+    assert_eq!(closest("gi", ["."]), None);
+}
@@ -0,0 +1,529 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command, Stdio},
+    ptr,
+    time::{Duration, Instant},
+};
+
+/// Open a pseudo terminal, returns the master and the slave.
+fn open_pty() -> (File, OwnedFd) {
+    let (mut master, mut slave) = (0, 0);
+    let ret = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    assert_eq!(ret, 0, "Failed to open a pseudo terminal");
+    unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) }
+}
+
+/// Read from the master until `pattern` is found.
+fn read_until(master: &mut File, output: &mut String, pattern: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut buf = [0; 1024];
+    while !output.contains(pattern) {
+        assert!(Instant::now() < deadline, "Timeout, output: {:?}", output);
+        let n = master.read(&mut buf).unwrap();
+        output.push_str(&String::from_utf8_lossy(&buf[..n]));
+    }
+}
+
+/// Read from the master until `pattern` is found, then until the next prompt. `output` is
+/// left empty.
+fn read_until_prompt(master: &mut File, output: &mut String, pattern: &str) {
+    read_until(master, output, pattern);
+    let end = output.find(pattern).unwrap() + pattern.len();
+    output.drain(..end);
+    read_until(master, output, "$ ");
+    output.clear();
+}
+
+#[test]
+fn restore_echo_after_child() {
+    let (mut master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "xterm")
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
+        .spawn()
+        .expect("Failed to run mysh");
+
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"stty -echo\n").unwrap();
+    read_until(&mut master, &mut output, "$ ");
+
+    // `stty` without arguments prints the settings different from the defaults,
+    // which include `-echo` if echo is off
+    output.clear();
+    master.write_all(b"stty\n").unwrap();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"exit\n").unwrap();
+    child.wait().unwrap();
+
+    assert!(!output.contains("-echo"), "output: {:?}", output);
+}
+
+#[test]
+fn vi_edit_mode() {
+    let (mut master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "xterm")
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
+        .spawn()
+        .expect("Failed to run mysh");
+
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"set -o vi\n").unwrap();
+    output.clear();
+    read_until(&mut master, &mut output, "$ ");
+
+    // Escape to the command mode, then `x` deletes the last character. The escape is sent
+    // alone, since rustyline waits a while for the rest of an escape sequence.
+    master.write_all(b"echo abcd\x1b").unwrap();
+    std::thread::sleep(Duration::from_millis(1000));
+    master.write_all(b"x\n").unwrap();
+    output.clear();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"exit\n").unwrap();
+    child.wait().unwrap();
+
+    assert!(output.contains("abc\r\n"), "output: {:?}", output);
+    assert!(!output.contains("abcd\r\n"), "output: {:?}", output);
+}
+
+#[test]
+fn ctrl_c_sets_status() {
+    let (mut master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "xterm")
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
+        .spawn()
+        .expect("Failed to run mysh");
+
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"echo discarded\x03").unwrap();
+    read_until_prompt(&mut master, &mut output, "discarded");
+    master.write_all(b"echo status $?\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "status 130\r\n");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn backslash_continues_line() {
+    let (mut master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "xterm")
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
+        .spawn()
+        .expect("Failed to run mysh");
+
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"echo foo\\\r").unwrap();
+    master.write_all(b"bar\r").unwrap();
+    // The prompt is drawn again while editing, so it can't be waited for
+    read_until(&mut master, &mut output, "foobar\r\n");
+    master.write_all(b"exit\n").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn plain_reader_without_terminal_output() {
+    let (mut master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "xterm")
+        .stdin(Stdio::from(slave))
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run mysh");
+
+    // Lines are read like a plain reader, without escape sequences of the line editor
+    master.write_all(b"echo hello\\\rworld\rexit\r").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "$ helloworld\n$ "
+    );
+}
+
+#[test]
+fn plain_reader_with_dumb_terminal() {
+    let (mut master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mysh"))
+        .arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "dumb")
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave))
+        .spawn()
+        .expect("Failed to run mysh");
+
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"echo hello\r").unwrap();
+    read_until(&mut master, &mut output, "hello\r\n$ ");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+
+    assert!(!output.contains('\x1b'), "output: {:?}", output);
+}
+
+/// Run mysh with the slave of a new pty as its controlling terminal, which job control
+/// needs. The history file must outlive mysh.
+fn spawn_with_job_control() -> (File, Child, tempfile::NamedTempFile) {
+    spawn_command_with_job_control(Command::new(env!("CARGO_BIN_EXE_mysh")))
+}
+
+/// Same as `spawn_with_job_control`, but runs `cmd`, e.g. with another `argv[0]`.
+fn spawn_command_with_job_control(mut cmd: Command) -> (File, Child, tempfile::NamedTempFile) {
+    let (master, slave) = open_pty();
+    let histfile = tempfile::NamedTempFile::new().unwrap();
+    cmd.arg("--no-config")
+        .env("HISTFILE", histfile.path())
+        .env("TERM", "xterm")
+        .stdin(Stdio::from(slave.try_clone().unwrap()))
+        .stdout(Stdio::from(slave.try_clone().unwrap()))
+        .stderr(Stdio::from(slave));
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = cmd.spawn().expect("Failed to run mysh");
+    (master, child, histfile)
+}
+
+/// Wait until the leader of the foreground process group of the terminal is `comm`, returns
+/// the process group.
+fn wait_for_foreground(master: &File, comm: &str) -> libc::pid_t {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        assert!(
+            Instant::now() < deadline,
+            "{} is not in the foreground",
+            comm
+        );
+        let pgid = unsafe { libc::tcgetpgrp(master.as_raw_fd()) };
+        let name = std::fs::read_to_string(format!("/proc/{}/comm", pgid)).unwrap_or_default();
+        if name.strip_suffix('\n') == Some(comm) {
+            return pgid;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Returns true if no process of the group `pgid` is left, not even one which hasn't been
+/// waited for.
+fn group_is_gone(pgid: libc::pid_t) -> bool {
+    unsafe { libc::kill(-pgid, 0) == -1 && *libc::__errno_location() == libc::ESRCH }
+}
+
+#[test]
+fn ctrl_z_stops_foreground_job() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"sleep 10\r").unwrap();
+    read_until(&mut master, &mut output, "sleep 10");
+    // Wait until sleep runs in the foreground, then Ctrl-Z
+    wait_for_foreground(&master, "sleep");
+    master.write_all(b"\x1a").unwrap();
+    // Typed-ahead input is dropped by the line editor, so wait for the prompt each time.
+    read_until_prompt(
+        &mut master,
+        &mut output,
+        "[1]+  Stopped                 sleep 10\r\n",
+    );
+
+    // The shell has the terminal again
+    master.write_all(b"echo after\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "after\r\n");
+
+    // The first `exit` is refused since the job is stopped
+    master.write_all(b"exit\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "There are stopped jobs.\r\n");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+/// Type `keys` one at a time, waiting for each to be echoed. With `set -o notify`, the line
+/// editor lags behind keys which arrive together. Redraws end with `\x1b[?2026l`, so keys
+/// typed while the line may be redrawn shouldn't be `l`.
+fn type_keys(master: &mut File, output: &mut String, keys: &[u8]) {
+    for &key in keys {
+        master.write_all(&[key]).unwrap();
+        let start = output.len();
+        let echoed = |output: &str| match key {
+            b'\r' => output[start..].contains("\r\n"),
+            _ => output.len() > start && output.ends_with(key as char),
+        };
+        let mut buf = [0; 1024];
+        while !echoed(output) {
+            let n = master.read(&mut buf).unwrap();
+            output.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+    }
+}
+
+/// A command which stops itself, then exits with 3 after it is continued in the background.
+const STOP_THEN_EXIT: &[u8] = b"sh -c '(sleep 0.5; kill -CONT $$) & kill -STOP $$; exit 3'\r";
+
+#[test]
+fn finished_job_is_reported_before_prompt() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(STOP_THEN_EXIT).unwrap();
+    read_until_prompt(&mut master, &mut output, "[1]+  Stopped");
+    std::thread::sleep(Duration::from_millis(1000));
+    output.clear();
+    // Nothing is printed until the next prompt
+    master.write_all(b"echo next\r").unwrap();
+    read_until(&mut master, &mut output, "[1]+  Exit 3");
+    assert!(output.find("next\r\n").unwrap() < output.find("[1]+  Exit 3").unwrap());
+    read_until_prompt(&mut master, &mut output, "[1]+  Exit 3");
+
+    // The job is gone, so `exit` isn't refused
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn notify_reports_finished_job_while_typing() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"set -o notify\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "notify");
+    type_keys(&mut master, &mut output, STOP_THEN_EXIT);
+    read_until_prompt(&mut master, &mut output, "[1]+  Stopped");
+    // The report is printed above the line being edited, which is redrawn
+    type_keys(&mut master, &mut output, b"echo do");
+    read_until(&mut master, &mut output, "[1]+  Exit 3");
+    type_keys(&mut master, &mut output, b"ne\r");
+    read_until_prompt(&mut master, &mut output, "done\r\n");
+
+    type_keys(&mut master, &mut output, b"exit\r");
+    child.wait().unwrap();
+}
+
+#[test]
+fn ctrl_c_interrupts_foreground_pipeline() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"sleep 10 | sleep 10\r").unwrap();
+    read_until(&mut master, &mut output, "sleep 10 | sleep 10");
+    let pgid = wait_for_foreground(&master, "sleep");
+    let start = Instant::now();
+    master.write_all(b"\x03").unwrap();
+    read_until(&mut master, &mut output, "$ ");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(group_is_gone(pgid));
+    output.clear();
+
+    master.write_all(b"echo status $?\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "status 130\r\n");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn sigint_to_shell_is_sent_to_foreground_job() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    // `sh` catches `SIGINT` and exits with 3, but the status is still 130
+    master
+        .write_all(b"sleep 10 | sh -c 'trap \"exit 3\" INT; sleep 10 & wait'\r")
+        .unwrap();
+    read_until(&mut master, &mut output, "wait'");
+    let pgid = wait_for_foreground(&master, "sleep");
+    let start = Instant::now();
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    read_until(&mut master, &mut output, "$ ");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    output.clear();
+
+    master.write_all(b"echo status $?\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "status 130\r\n");
+    // The background `sleep` of `sh` is in the group too
+    unsafe { libc::kill(-pgid, libc::SIGKILL) };
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn and_list_with_job_control() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    // Each pipeline gets a process group of its own
+    master
+        .write_all(b"sleep 0.1 && /bin/echo first; /bin/echo second\r")
+        .unwrap();
+    read_until_prompt(&mut master, &mut output, "first\r\nsecond\r\n");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn background_job() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    // The prompt comes back right away, after the job id and the pid
+    let start = Instant::now();
+    master.write_all(b"sleep 10 &\r").unwrap();
+    read_until(&mut master, &mut output, "[1] ");
+    read_until(&mut master, &mut output, "$ ");
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let line = &output[output.find("[1] ").unwrap() + 4..];
+    let pid: libc::pid_t = line[..line.find('\r').unwrap()].parse().unwrap();
+    output.clear();
+
+    // A job which finishes while another command runs is reported before the next prompt
+    master.write_all(b"sleep 0.2 &\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "[2] ");
+    master.write_all(b"sleep 1\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "[2]+  Done");
+
+    master.write_all(b"exit\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "There are running jobs.");
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn job_spec_resumes_job() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master
+        .write_all(b"sh -c 'kill -STOP $$; echo resumed; exit 3'\r")
+        .unwrap();
+    read_until_prompt(&mut master, &mut output, "[1]+  Stopped");
+
+    // `%1` is `fg %1`, which prints the command and waits for the job
+    master.write_all(b"%1\r").unwrap();
+    read_until(&mut master, &mut output, "resumed\r\n");
+    assert!(output.contains("sh -c 'kill -STOP $$; echo resumed; exit 3'\r\nresumed"));
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"echo status $?\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "status 3\r\n");
+
+    master.write_all(b"%1\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "mysh: %1: no such job\r\n");
+    // A quoted job spec is a command name
+    master.write_all(b"'%1'\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "%1: command not found");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn fg_waits_for_background_job() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master
+        .write_all(b"sh -c 'sleep 0.3; echo finished' &\r")
+        .unwrap();
+    read_until_prompt(&mut master, &mut output, "[1] ");
+
+    // `fg` without arguments is the current job, and the prompt waits for it
+    master.write_all(b"fg\r").unwrap();
+    read_until(&mut master, &mut output, "finished\r\n");
+    read_until(&mut master, &mut output, "$ ");
+    output.clear();
+    master.write_all(b"fg 1; echo $?\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "fg: 1: no such job\r\n1\r\n");
+    master.write_all(b"fg\r").unwrap();
+    read_until_prompt(&mut master, &mut output, "fg: current: no such job\r\n");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn suspend_stops_the_shell() {
+    let (mut master, mut child, _histfile) = spawn_with_job_control();
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"suspend; echo re''sumed\r").unwrap();
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    assert_eq!(
+        unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) },
+        pid
+    );
+    assert!(libc::WIFSTOPPED(status));
+    unsafe { libc::kill(pid, libc::SIGCONT) };
+    read_until_prompt(&mut master, &mut output, "resumed\r\n");
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+
+    // A login shell is only stopped with `-f`
+    let mut cmd = Command::new(env!("CARGO_BIN_EXE_mysh"));
+    cmd.arg0("-mysh");
+    let (mut master, mut child, _histfile) = spawn_command_with_job_control(cmd);
+    let mut output = String::new();
+    read_until(&mut master, &mut output, "$ ");
+    master.write_all(b"suspend; echo $?\r").unwrap();
+    read_until_prompt(
+        &mut master,
+        &mut output,
+        "suspend: cannot suspend a login shell\r\n1\r\n",
+    );
+    master.write_all(b"exit\r").unwrap();
+    child.wait().unwrap();
+}